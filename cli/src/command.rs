@@ -0,0 +1,364 @@
+use common::{
+    Fault, FaultKind, FaultRecord, Identity, Message, MotorState, NodeKind, PanicText, ResetReason, StepperState,
+    Usage, Version,
+};
+
+pub const HELP: &str = "\
+commands:
+    drive <idle|fwd|rev> <power> <direction>
+    estop
+    start
+    stop
+    calibrate
+    zero
+    goto <steps>
+    baud <rate>
+    baud-ack <rate>
+    time-sync <ms>
+    telemetry <idle|fwd|rev> <power> <steering> <ms> <reset> <dropped>
+    steer-status <unzeroed|zeroing|ready|alarm> <steps> <ms> <reset> <dropped>
+    ping
+    pong
+    identify
+    identity <left|right|steering> <id> <version> <git hash> <calibration> <uptime> <reset>
+    get-panic
+    panic <text...>
+    get-fault <index>
+    fault <index> none
+    fault <index> <stall|alarm|link-lost|estop|watchdog|panic> <ms> <context>
+    clear-faults
+    get-usage
+    usage <run seconds> <starts> <shifts> <steps>
+    error <alarm|limit|link-lost>
+
+<reset> is why the node last started: power-on, pin, watchdog, software,
+low-power or unknown";
+
+fn number<T: std::str::FromStr>(word: Option<&&str>, what: &str) -> Result<T, String> {
+    let word = word.ok_or_else(|| format!("missing {}", what))?;
+    word.parse().map_err(|_| format!("bad {}: {}", what, word))
+}
+
+fn motor_state(kind: Option<&&str>, power: Option<&&str>) -> Result<MotorState, String> {
+    let power = number(power, "power")?;
+    match kind {
+        Some(&"idle") => Ok(MotorState::Idle(power)),
+        Some(&"fwd") => Ok(MotorState::Fwd(power)),
+        Some(&"rev") => Ok(MotorState::Rev(power)),
+        Some(x) => Err(format!("bad motor state: {}", x)),
+        None => Err("missing motor state".into()),
+    }
+}
+
+fn fault(word: Option<&&str>) -> Result<Fault, String> {
+    match word {
+        Some(&"alarm") => Ok(Fault::StepperAlarm),
+        Some(&"limit") => Ok(Fault::Limit),
+        Some(&"link-lost") => Ok(Fault::LinkLost),
+        Some(x) => Err(format!("bad fault: {}", x)),
+        None => Err("missing fault".into()),
+    }
+}
+
+fn stepper_state(word: Option<&&str>) -> Result<StepperState, String> {
+    match word {
+        Some(&"unzeroed") => Ok(StepperState::Unzeroed),
+        Some(&"zeroing") => Ok(StepperState::Zeroing),
+        Some(&"ready") => Ok(StepperState::Ready),
+        Some(&"alarm") => Ok(StepperState::Alarm),
+        Some(x) => Err(format!("bad stepper state: {}", x)),
+        None => Err("missing stepper state".into()),
+    }
+}
+
+fn identity(args: &[&str]) -> Result<Identity, String> {
+    let kind = match args.first() {
+        Some(&"left") => NodeKind::LeftDriver,
+        Some(&"right") => NodeKind::RightDriver,
+        Some(&"steering") => NodeKind::Steering,
+        Some(x) => return Err(format!("bad node kind: {}", x)),
+        None => return Err("missing node kind".into()),
+    };
+    let version = args.get(2).ok_or("missing version")?;
+    let git_hash = args.get(3).ok_or("missing git hash")?;
+    Ok(Identity {
+        kind,
+        id: number(args.get(1), "id")?,
+        version: Version::parse(version).ok_or_else(|| format!("bad version: {}", version))?,
+        git_hash: u32::from_str_radix(git_hash, 16).map_err(|_| format!("bad git hash: {}", git_hash))?,
+        calibration: number(args.get(4), "calibration")?,
+        uptime: number(args.get(5), "uptime")?,
+        reset: reset_reason(args.get(6))?,
+    })
+}
+
+fn reset_reason(word: Option<&&str>) -> Result<ResetReason, String> {
+    match word {
+        Some(&"power-on") => Ok(ResetReason::PowerOn),
+        Some(&"pin") => Ok(ResetReason::Pin),
+        Some(&"watchdog") => Ok(ResetReason::Watchdog),
+        Some(&"software") => Ok(ResetReason::Software),
+        Some(&"low-power") => Ok(ResetReason::LowPower),
+        Some(&"unknown") => Ok(ResetReason::Unknown),
+        Some(x) => Err(format!("bad reset reason: {}", x)),
+        None => Err("missing reset reason".into()),
+    }
+}
+
+fn fault_record(args: &[&str]) -> Result<Option<FaultRecord>, String> {
+    let kind = match args.first() {
+        Some(&"none") => return Ok(None),
+        Some(&"stall") => FaultKind::Stall,
+        Some(&"alarm") => FaultKind::StepperAlarm,
+        Some(&"link-lost") => FaultKind::LinkLost,
+        Some(&"estop") => FaultKind::EStop,
+        Some(&"watchdog") => FaultKind::WatchdogReset,
+        Some(&"panic") => FaultKind::Panic,
+        Some(x) => return Err(format!("bad fault kind: {}", x)),
+        None => return Err("missing fault kind".into()),
+    };
+    Ok(Some(FaultRecord {
+        kind,
+        time: number(args.get(1), "time")?,
+        context: number(args.get(2), "context")?,
+    }))
+}
+
+/// Parses a command given as separate words, the same way `format` prints it
+pub fn parse(words: &[&str]) -> Result<Message, String> {
+    let (cmd, args) = words.split_first().ok_or("missing command")?;
+    let (n, msg) = match *cmd {
+        "drive" => (3, Message::Drive {
+            motor_state: motor_state(args.first(), args.get(1))?,
+            direction: number(args.get(2), "direction")?,
+        }),
+        "estop" => (0, Message::EStop),
+        "start" => (0, Message::EngineStart),
+        "stop" => (0, Message::EngineStop),
+        "calibrate" => (0, Message::Calibrate),
+        "zero" => (0, Message::SteerZero),
+        "goto" => (1, Message::SteerGoto(number(args.first(), "steps")?)),
+        "baud" => (1, Message::BaudPropose(number(args.first(), "rate")?)),
+        "baud-ack" => (1, Message::BaudAck(number(args.first(), "rate")?)),
+        "time-sync" => (1, Message::TimeSync(number(args.first(), "time")?)),
+        "telemetry" => (6, Message::Telemetry {
+            motor_state: motor_state(args.first(), args.get(1))?,
+            steering: number(args.get(2), "steering")?,
+            time: number(args.get(3), "time")?,
+            reset: reset_reason(args.get(4))?,
+            dropped: number(args.get(5), "dropped")?,
+        }),
+        "steer-status" => (5, Message::SteerStatus {
+            state: stepper_state(args.first())?,
+            position: number(args.get(1), "steps")?,
+            time: number(args.get(2), "time")?,
+            reset: reset_reason(args.get(3))?,
+            dropped: number(args.get(4), "dropped")?,
+        }),
+        "ping" => (0, Message::Ping),
+        "pong" => (0, Message::Pong),
+        "identify" => (0, Message::Identify),
+        "identity" => (7, Message::Identity(identity(args)?)),
+        "get-panic" => (0, Message::GetPanic),
+        // The rest of the words, cut to what fits
+        "panic" => (args.len(), Message::Panic(PanicText::new(&args.join(" ")))),
+        "get-fault" => (1, Message::GetFault(number(args.first(), "index")?)),
+        "fault" => {
+            let record = fault_record(args.get(1..).unwrap_or(&[]))?;
+            let n = if record.is_some() { 4 } else { 2 };
+            (n, Message::LoggedFault { index: number(args.first(), "index")?, record })
+        }
+        "clear-faults" => (0, Message::ClearFaults),
+        "get-usage" => (0, Message::GetUsage),
+        "usage" => (4, Message::Usage(Usage {
+            run_seconds: number(args.first(), "run seconds")?,
+            starts: number(args.get(1), "starts")?,
+            shifts: number(args.get(2), "shifts")?,
+            steps: number(args.get(3), "steps")?,
+        })),
+        "error" => (1, Message::Error(fault(args.first())?)),
+        x => return Err(format!("unknown command: {}", x)),
+    };
+    if args.len() > n {
+        return Err(format!("too many arguments for {}", cmd));
+    }
+    Ok(msg)
+}
+
+fn format_reset_reason(reset: ResetReason) -> &'static str {
+    match reset {
+        ResetReason::PowerOn => "power-on",
+        ResetReason::Pin => "pin",
+        ResetReason::Watchdog => "watchdog",
+        ResetReason::Software => "software",
+        ResetReason::LowPower => "low-power",
+        ResetReason::Unknown => "unknown",
+    }
+}
+
+fn format_motor_state(state: MotorState) -> String {
+    match state {
+        MotorState::Idle(p) => format!("idle {}", p),
+        MotorState::Fwd(p) => format!("fwd {}", p),
+        MotorState::Rev(p) => format!("rev {}", p),
+    }
+}
+
+/// Prints a message as the command that sends it
+pub fn format(msg: &Message) -> String {
+    match *msg {
+        Message::Drive { motor_state, direction } => {
+            format!("drive {} {}", format_motor_state(motor_state), direction)
+        }
+        Message::EStop => "estop".into(),
+        Message::EngineStart => "start".into(),
+        Message::EngineStop => "stop".into(),
+        Message::Calibrate => "calibrate".into(),
+        Message::SteerZero => "zero".into(),
+        Message::SteerGoto(steps) => format!("goto {}", steps),
+        Message::BaudPropose(baud) => format!("baud {}", baud),
+        Message::BaudAck(baud) => format!("baud-ack {}", baud),
+        Message::TimeSync(time) => format!("time-sync {}", time),
+        Message::Telemetry { motor_state, steering, time, reset, dropped } => format!(
+            "telemetry {} {} {} {} {}",
+            format_motor_state(motor_state),
+            steering,
+            time,
+            format_reset_reason(reset),
+            dropped
+        ),
+        Message::SteerStatus { state, position, time, reset, dropped } => {
+            let name = match state {
+                StepperState::Unzeroed => "unzeroed",
+                StepperState::Zeroing => "zeroing",
+                StepperState::Ready => "ready",
+                StepperState::Alarm => "alarm",
+            };
+            format!("steer-status {} {} {} {} {}", name, position, time, format_reset_reason(reset), dropped)
+        }
+        Message::Ping => "ping".into(),
+        Message::Pong => "pong".into(),
+        Message::Identify => "identify".into(),
+        Message::Identity(identity) => {
+            let kind = match identity.kind {
+                NodeKind::LeftDriver => "left",
+                NodeKind::RightDriver => "right",
+                NodeKind::Steering => "steering",
+            };
+            let reset = format_reset_reason(identity.reset);
+            let Version { major, minor, patch } = identity.version;
+            format!(
+                "identity {} {} {}.{}.{} {:08x} {} {} {}",
+                kind, identity.id, major, minor, patch, identity.git_hash, identity.calibration, identity.uptime, reset
+            )
+        }
+        Message::GetPanic => "get-panic".into(),
+        Message::Panic(text) => format!("panic {}", text.as_str()).trim_end().into(),
+        Message::GetFault(index) => format!("get-fault {}", index),
+        Message::LoggedFault { index, record: None } => format!("fault {} none", index),
+        Message::LoggedFault { index, record: Some(record) } => {
+            let kind = match record.kind {
+                FaultKind::Stall => "stall",
+                FaultKind::StepperAlarm => "alarm",
+                FaultKind::LinkLost => "link-lost",
+                FaultKind::EStop => "estop",
+                FaultKind::WatchdogReset => "watchdog",
+                FaultKind::Panic => "panic",
+            };
+            format!("fault {} {} {} {}", index, kind, record.time, record.context)
+        }
+        Message::ClearFaults => "clear-faults".into(),
+        Message::GetUsage => "get-usage".into(),
+        Message::Usage(usage) => {
+            format!("usage {} {} {} {}", usage.run_seconds, usage.starts, usage.shifts, usage.steps)
+        }
+        Message::Error(fault) => {
+            let name = match fault {
+                Fault::StepperAlarm => "alarm",
+                Fault::Limit => "limit",
+                Fault::LinkLost => "link-lost",
+            };
+            format!("error {}", name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_parses_back() {
+        let messages = [
+            Message::Drive { motor_state: MotorState::Rev(30), direction: 200 },
+            Message::EStop,
+            Message::EngineStart,
+            Message::EngineStop,
+            Message::Calibrate,
+            Message::SteerZero,
+            Message::SteerGoto(-1200),
+            Message::BaudPropose(115_200),
+            Message::BaudAck(57_600),
+            Message::TimeSync(86_400_000),
+            Message::Telemetry {
+                motor_state: MotorState::Idle(0),
+                steering: 400,
+                time: 1500,
+                reset: ResetReason::Pin,
+                dropped: 3,
+            },
+            Message::SteerStatus {
+                state: StepperState::Zeroing,
+                position: -12,
+                time: 1600,
+                reset: ResetReason::Watchdog,
+                dropped: 0,
+            },
+            Message::Ping,
+            Message::Pong,
+            Message::Identify,
+            Message::Identity(Identity {
+                kind: NodeKind::Steering,
+                id: 3,
+                version: Version { major: 0, minor: 1, patch: 2 },
+                git_hash: 0x00ab_cdef,
+                calibration: 4,
+                uptime: 3600,
+                reset: ResetReason::Watchdog,
+            }),
+            Message::GetPanic,
+            Message::Panic(PanicText::new("index out of bounds")),
+            Message::Panic(PanicText::empty()),
+            Message::GetFault(2),
+            Message::LoggedFault { index: 0, record: None },
+            Message::LoggedFault {
+                index: 1,
+                record: Some(FaultRecord { kind: FaultKind::Stall, time: 61_000, context: 1 }),
+            },
+            Message::ClearFaults,
+            Message::GetUsage,
+            Message::Usage(Usage { run_seconds: 36_000, starts: 12, shifts: 340, steps: 1_200_000 }),
+            Message::Error(Fault::LinkLost),
+        ];
+        for msg in &messages {
+            let text = format(msg);
+            let words: Vec<_> = text.split_whitespace().collect();
+            assert_eq!(parse(&words), Ok(*msg));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["fly"]).is_err());
+        assert!(parse(&["drive", "up", "1", "2"]).is_err());
+        assert!(parse(&["drive", "fwd", "300", "2"]).is_err());
+        assert!(parse(&["goto"]).is_err());
+        assert!(parse(&["steer-status", "ready", "10"]).is_err());
+        assert!(parse(&["steer-status", "ready", "10", "20", "reboot", "0"]).is_err());
+        assert!(parse(&["estop", "now"]).is_err());
+        assert!(parse(&["identity", "left", "1", "0.1", "0", "0", "0", "pin"]).is_err());
+        assert!(parse(&["fault", "0", "none", "12"]).is_err());
+        assert!(parse(&["fault", "0", "stall", "12"]).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{CaptureReader, CobsParser, Direction, FrameParser, Framing, Packet, DEFAULT_BAUD, HOST, MASTER};
+use serialport::SerialPort;
+
+mod command;
+mod session;
+
+use session::Session;
+
+const USAGE: &str = "\
+usage: boat <port> [options] [<command>...]
+       boat <port> [options] replay <capture> [sent|received]
+
+Sends the command and prints the replies. With no command every packet
+received is printed until interrupted. Replay sends the packets of a capture
+at their original timing, by default the ones the recorder received.
+
+options:
+    --baud <rate>     link speed, default 9600
+    --cobs            COBS framing instead of the preamble
+    --id <node>       node to address, default 0 for every node. Nodes only
+                      answer packets addressed to them. 255 is the
+                      controller itself, it answers the fault log commands
+    --repeat <ms>     send the command again every <ms> until interrupted
+    --record <file>   capture everything sent and received to <file>";
+
+/// How long to wait for replies after sending a command once
+const LISTEN: Duration = Duration::from_millis(500);
+
+struct Options {
+    port: String,
+    baud: u32,
+    cobs: bool,
+    id: u8,
+    repeat: Option<Duration>,
+    record: Option<String>,
+    command: Vec<String>,
+}
+
+fn value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, name: &str) -> Result<T, String> {
+    let v = args.next().ok_or_else(|| format!("{} needs a value", name))?;
+    v.parse().map_err(|_| format!("bad value for {}: {}", name, v))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut opts = Options {
+        port: String::new(),
+        baud: DEFAULT_BAUD,
+        cobs: false,
+        id: 0,
+        repeat: None,
+        record: None,
+        command: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--baud" => opts.baud = value(&mut args, "--baud")?,
+            "--cobs" => opts.cobs = true,
+            "--id" => opts.id = value(&mut args, "--id")?,
+            "--repeat" => opts.repeat = Some(Duration::from_millis(value(&mut args, "--repeat")?)),
+            "--record" => opts.record = Some(value(&mut args, "--record")?),
+            "-h" | "--help" => return Err(String::new()),
+            x if x.starts_with("--") => return Err(format!("unknown option: {}", x)),
+            _ if opts.port.is_empty() => opts.port = arg,
+            _ => opts.command.push(arg),
+        }
+    }
+    if opts.port.is_empty() {
+        return Err("missing port".into());
+    }
+    Ok(opts)
+}
+
+fn print(events: Vec<session::Event>) {
+    for event in events {
+        println!("{}", event.format());
+    }
+}
+
+fn run<F: Framing>(opts: &Options, port: Box<dyn SerialPort>) -> Result<(), String> {
+    let mut session = Session::<F, _>::new(port);
+    if let Some(path) = &opts.record {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        session.record(Box::new(file)).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    if opts.command.is_empty() {
+        loop {
+            print(session.poll().map_err(|e| e.to_string())?);
+        }
+    }
+
+    let words: Vec<&str> = opts.command.iter().map(|s| s.as_str()).collect();
+    if words[0] == "replay" {
+        return replay(&mut session, &words[1..]);
+    }
+    let message = command::parse(&words)?;
+    let mut seq: u8 = 0;
+    loop {
+        // The controller only answers a host, anything else comes from it
+        let src = if opts.id == MASTER { HOST } else { MASTER };
+        let packet = Packet { dst: opts.id, src, seq, message };
+        print(vec![session.send(&packet).map_err(|e| e.to_string())?]);
+        // Drivers drop packets with the sequence number they saw last
+        seq = seq.wrapping_add(1);
+
+        let until = Instant::now() + opts.repeat.unwrap_or(LISTEN);
+        while Instant::now() < until {
+            print(session.poll().map_err(|e| e.to_string())?);
+        }
+        if opts.repeat.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+fn replay<F: Framing, P: Read + Write>(session: &mut Session<F, P>, args: &[&str]) -> Result<(), String> {
+    let (path, direction) = match args {
+        [path] => (path, Direction::Received),
+        [path, "received"] => (path, Direction::Received),
+        [path, "sent"] => (path, Direction::Sent),
+        _ => return Err("usage: replay <capture> [sent|received]".into()),
+    };
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let records = CaptureReader::new(&bytes).map_err(|e| format!("{}: {:?}", path, e))?;
+
+    let start = Instant::now();
+    for record in records {
+        let record = record.map_err(|e| format!("{}: {:?}", path, e))?;
+        if record.direction != direction {
+            continue;
+        }
+        let at = start + Duration::from_millis(record.time as u64);
+        while Instant::now() < at {
+            print(session.poll().map_err(|e| e.to_string())?);
+        }
+        print(vec![session.send(&record.packet).map_err(|e| e.to_string())?]);
+    }
+
+    let until = Instant::now() + LISTEN;
+    while Instant::now() < until {
+        print(session.poll().map_err(|e| e.to_string())?);
+    }
+    Ok(())
+}
+
+fn main() {
+    let opts = match parse_args(std::env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{}\n", e);
+            }
+            eprintln!("{}\n\n{}", USAGE, command::HELP);
+            process::exit(2);
+        }
+    };
+
+    let port = serialport::new(opts.port.as_str(), opts.baud)
+        .timeout(Duration::from_millis(20))
+        .open();
+    let port = match port {
+        Ok(port) => port,
+        Err(e) => {
+            eprintln!("{}: {}", opts.port, e);
+            process::exit(1);
+        }
+    };
+    // Give a board that resets on open time to come up
+    thread::sleep(Duration::from_millis(100));
+
+    let result = if opts.cobs {
+        run::<CobsParser>(&opts, port)
+    }
+    else {
+        run::<FrameParser>(&opts, port)
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
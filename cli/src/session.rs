@@ -0,0 +1,194 @@
+use std::io::{self, Read, Write};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use common::{
+    write_capture_header, Direction, Framing, Packet, ParseError, Record, CAPTURE_HEADER_LEN,
+    MAX_PACKET_LEN, MAX_RECORD_LEN,
+};
+
+use crate::command;
+
+pub enum Event {
+    Sent(Packet),
+    Received(Packet),
+    Error(ParseError),
+}
+
+impl Event {
+    pub fn format(&self) -> String {
+        match self {
+            // The node a packet went to or came from
+            Event::Sent(p) => format!("> {:>3} {:>3}  {}", p.dst, p.seq, command::format(&p.message)),
+            Event::Received(p) => format!("< {:>3} {:>3}  {}", p.src, p.seq, command::format(&p.message)),
+            Event::Error(e) => format!("! {:?}", e),
+        }
+    }
+}
+
+/// Talks the protocol on a port with framing `F`, every packet can be
+/// captured to a file
+pub struct Session<F, P> {
+    port: P,
+    parser: F,
+    capture: Option<Box<dyn Write>>,
+    start: Instant,
+}
+
+impl<F: Framing, P: Read + Write> Session<F, P> {
+    pub fn new(port: P) -> Self {
+        Session {
+            port,
+            parser: F::default(),
+            capture: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// Every packet sent or received from now on is also written to `capture`
+    pub fn record(&mut self, mut capture: Box<dyn Write>) -> io::Result<()> {
+        self.start = Instant::now();
+        let since_1970 = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut buf = [0; CAPTURE_HEADER_LEN];
+        write_capture_header(&mut buf, since_1970.as_millis() as u64);
+        capture.write_all(&buf)?;
+        self.capture = Some(capture);
+        Ok(())
+    }
+
+    fn event(&mut self, event: Event) -> io::Result<Event> {
+        let (direction, packet) = match event {
+            Event::Sent(p) => (Direction::Sent, p),
+            Event::Received(p) => (Direction::Received, p),
+            Event::Error(_) => return Ok(event),
+        };
+        if let Some(capture) = self.capture.as_mut() {
+            let record = Record {
+                time: self.start.elapsed().as_millis() as u32,
+                direction,
+                packet,
+            };
+            let mut buf = [0; MAX_RECORD_LEN];
+            let len = record.write(&mut buf);
+            capture.write_all(&buf[..len])?;
+        }
+        Ok(event)
+    }
+
+    pub fn send(&mut self, packet: &Packet) -> io::Result<Event> {
+        let mut buf = [0; MAX_PACKET_LEN];
+        let len = F::write(packet, &mut buf);
+        self.port.write_all(&buf[..len])?;
+        self.port.flush()?;
+        self.event(Event::Sent(*packet))
+    }
+
+    /// Reads what the port has, returns the events in the order they happened.
+    /// A read timeout is not an error, it just gives nothing.
+    pub fn poll(&mut self) -> io::Result<Vec<Event>> {
+        let mut buf = [0; 256];
+        let n = match self.port.read(&mut buf) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => 0,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => 0,
+            Err(e) => return Err(e),
+        };
+
+        let mut events = Vec::new();
+        for b in &buf[..n] {
+            let event = match self.parser.push(*b) {
+                Ok(Some(packet)) => Event::Received(packet),
+                Ok(None) => continue,
+                Err(e) => Event::Error(e),
+            };
+            events.push(self.event(event)?);
+            while let Some(packet) = self.parser.pending() {
+                events.push(self.event(Event::Received(packet))?);
+            }
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{CobsParser, FrameParser, Message, MotorState, MASTER};
+    use serialport::{SerialPort, TTYPort};
+    use std::time::Duration;
+
+    fn pair() -> (TTYPort, TTYPort) {
+        let (mut a, mut b) = TTYPort::pair().unwrap();
+        a.set_timeout(Duration::from_millis(100)).unwrap();
+        b.set_timeout(Duration::from_millis(100)).unwrap();
+        (a, b)
+    }
+
+    fn round_trip<F: Framing>() {
+        let (a, b) = pair();
+        let mut tx = Session::<F, _>::new(a);
+        let mut rx = Session::<F, _>::new(b);
+
+        let packet = Packet {
+            dst: 1,
+            src: MASTER,
+            seq: 4,
+            message: Message::Drive { motor_state: MotorState::Fwd(80), direction: 100 },
+        };
+        tx.send(&packet).unwrap();
+
+        let mut got = Vec::new();
+        for _ in 0..10 {
+            for event in rx.poll().unwrap() {
+                if let Event::Received(p) = event {
+                    got.push(p);
+                }
+            }
+            if !got.is_empty() {
+                break;
+            }
+        }
+        assert_eq!(got, vec![packet]);
+    }
+
+    /// Shares what the session writes with the test
+    #[derive(Clone, Default)]
+    struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn captures_what_it_sends() {
+        let (a, _b) = pair();
+        let mut session = Session::<FrameParser, _>::new(a);
+        let capture = Shared::default();
+        session.record(Box::new(capture.clone())).unwrap();
+
+        let packet = Packet { dst: 2, src: MASTER, seq: 0, message: Message::SteerGoto(300) };
+        session.send(&packet).unwrap();
+
+        let bytes = capture.0.borrow();
+        let reader = common::CaptureReader::new(&bytes).unwrap();
+        // Some time after 2020
+        assert!(reader.start() > 1_577_836_800_000);
+        let records: Vec<_> = reader.collect();
+        assert_eq!(records.len(), 1);
+        let record = records[0].unwrap();
+        assert_eq!(record.direction, Direction::Sent);
+        assert_eq!(record.packet, packet);
+    }
+
+    #[test]
+    fn talks_over_a_pty() {
+        round_trip::<FrameParser>();
+        round_trip::<CobsParser>();
+    }
+}
@@ -0,0 +1,26 @@
+#![no_main]
+
+//! Feeds random byte streams to every framing. The parsers must never panic,
+//! and every packet they return has to be in the stream exactly as its
+//! framing would have sent it.
+//!
+//! Run with `cargo +nightly fuzz run parser` from `common`.
+
+use common::*;
+use libfuzzer_sys::fuzz_target;
+
+fn check<F: Framing>(data: &[u8]) {
+    let mut parser = F::default();
+    for (i, b) in data.iter().enumerate() {
+        if let Ok(Some(packet)) = parser.push(*b) {
+            let mut buf = [0; MAX_PACKET_LEN];
+            let len = F::write(&packet, &mut buf);
+            assert!(data[..=i].windows(len).any(|w| w == &buf[..len]));
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    check::<FrameParser>(data);
+    check::<CobsParser>(data);
+});
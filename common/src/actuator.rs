@@ -1,10 +1,53 @@
-use core::cell::{Cell, RefCell};
 use embedded_hal::digital::{InputPin, OutputPin};
-use embedded_hal::timer::{CountDown, Periodic};
 use embedded_hal::adc::{Channel, OneShot};
-use embedded_hal::PwmPin;
 
-use crate::adc::Adc;
+/// Readings in a row a limit switch needs before it counts as changed
+pub const DEBOUNCE_TICKS: u8 = 3;
+
+/// Positions and targets are given in per-mille of the travel, this is the end
+/// of it
+pub const FULL_TRAVEL: u16 = 1000;
+
+/// Distance from the target, in per-mille, that counts as there
+const DEADBAND: u16 = 10;
+
+/// Distance, in per-mille, that `within` and `goto` treat as close enough
+const NEAR: u16 = 50;
+
+/// Ticks an actuator may be driven without moving `STALL_DISTANCE` before it
+/// counts as stalled
+pub const STALL_TICKS: u16 = 500;
+
+/// Per-mille a driven actuator has to move every `STALL_TICKS`
+const STALL_DISTANCE: u16 = DEADBAND;
+
+/// How an actuator is wired
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct ActuatorConfig {
+    /// Sensor reading at one end of the travel, has to be below `max`
+    pub min: u16,
+    /// Sensor reading at the other end of the travel
+    pub max: u16,
+    /// `min` is full travel and `max` is zero, instead of the other way round
+    pub invert_sensor: bool,
+    /// Going forward moves toward zero, instead of toward full travel
+    pub invert_motor: bool,
+}
+
+impl ActuatorConfig {
+    /// Per-mille of travel for a sensor reading, clamped to the span
+    pub fn per_mille(&self, raw: u16) -> u16 {
+        let raw = raw.max(self.min).min(self.max);
+        let span = (self.max - self.min).max(1) as u32;
+        let p = ((raw - self.min) as u32 * FULL_TRAVEL as u32 / span) as u16;
+        if self.invert_sensor {
+            FULL_TRAVEL - p
+        }
+        else {
+            p
+        }
+    }
+}
 
 #[derive(Eq, PartialEq)]
 enum State {
@@ -13,64 +56,144 @@ enum State {
     Rev,
 }
 
-pub struct ActuatorController {
-    target: Cell<u16>,
-    pos: Cell<u16>,
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum End {
+    Fwd,
+    Rev,
+}
+
+/// Stands in for a pin that is not fitted, a limit switch that never closes
+/// or an output that goes nowhere
+pub struct NoPin;
+
+impl InputPin for NoPin {
+    fn is_high(&self) -> bool {
+        false
+    }
+
+    fn is_low(&self) -> bool {
+        true
+    }
+}
+
+impl OutputPin for NoPin {
+    fn set_low(&mut self) {}
+
+    fn set_high(&mut self) {}
+}
+
+/// Debounced switch input, a limit switch or an alarm output
+pub struct Limit<P> {
+    pin: P,
+    polarity: Polarity,
+    active: bool,
+    /// Readings in a row that disagree with `active`
+    count: u8,
 }
 
-impl ActuatorController {
-    pub fn new() -> Self {
-        ActuatorController {
-            target: Cell::new(0),
-            pos: Cell::new(0),
+impl<P: InputPin> Limit<P> {
+    pub fn new(pin: P, polarity: Polarity) -> Self {
+        let mut limit = Limit {
+            pin,
+            polarity,
+            active: false,
+            count: 0,
+        };
+        limit.active = limit.read();
+        limit
+    }
+
+    fn read(&self) -> bool {
+        match self.polarity {
+            Polarity::ActiveHigh => self.pin.is_high(),
+            Polarity::ActiveLow => self.pin.is_low(),
         }
     }
 
-    pub fn set_target(&self, target: u16) {
-        self.target.set(target);
+    pub(crate) fn update(&mut self) {
+        if self.read() == self.active {
+            self.count = 0;
+        }
+        else {
+            self.count += 1;
+            if self.count >= DEBOUNCE_TICKS {
+                self.active = !self.active;
+                self.count = 0;
+            }
+        }
     }
 
-    pub fn in_position(&self) -> bool {
-        self.pos.get() < self.target.get() + 200
-            && self.pos.get() + 200 > self.target.get()
+    pub fn is_active(&self) -> bool {
+        self.active
     }
 }
 
+impl Limit<NoPin> {
+    pub fn none() -> Self {
+        Limit::new(NoPin, Polarity::ActiveHigh)
+    }
+}
 
-pub struct Actuator<IN1, IN2, PosPin, Lim> {
+pub struct Actuator<IN1, IN2, PosPin, LimF, LimR> {
     in1: IN1,
     in2: IN2,
     pos_pin: PosPin,
-    lim: Lim,
+    lim_fwd: Limit<LimF>,
+    lim_rev: Limit<LimR>,
+    config: ActuatorConfig,
     state: State,
+    /// In per-mille of the travel
     target: Option<u16>,
+    /// In per-mille of the travel
     position: u16,
+    /// Last sensor reading
+    raw: u16,
+    /// End whose limit stopped the last move
+    tripped: Option<End>,
+    /// Position the current move last made progress from
+    progress_from: u16,
+    /// Ticks since then
+    stall_ticks: u16,
 }
-
 impl<
         IN1: OutputPin,
         IN2: OutputPin,
-        Lim: InputPin,
+        LimF: InputPin,
+        LimR: InputPin,
         PosPin,
         //AdcDev,
         //PosAdc: OneShot<AdcDev, u16, PosPin>,
         //PosPin: Channel,
-    > Actuator<IN1, IN2, PosPin, Lim>
+    > Actuator<IN1, IN2, PosPin, LimF, LimR>
 {
     pub fn new(
         in1: IN1,
         in2: IN2,
         pos_pin: PosPin,
-        lim: Lim,
-    ) -> Actuator<IN1, IN2, PosPin, Lim> {
+        lim_fwd: Limit<LimF>,
+        lim_rev: Limit<LimR>,
+        config: ActuatorConfig,
+    ) -> Actuator<IN1, IN2, PosPin, LimF, LimR> {
         Actuator {
             in1,
             in2,
             pos_pin,
-            lim,
+            lim_fwd,
+            lim_rev,
+            config,
             state: State::Stop,
             target: None,
             position: 0,
+            raw: 0,
+            tripped: None,
+            progress_from: 0,
+            stall_ticks: 0,
         }
     }
     fn update(&mut self) {
@@ -99,20 +222,23 @@ impl<
     }
 
     pub fn go_fwd(&mut self) {
-        if self.state != State::Fwd && self.lim.is_high() {
+        if self.state != State::Fwd && !self.lim_fwd.is_active() {
             self.state = State::Fwd;
             self.update();
         }
     }
 
     pub fn go_rev(&mut self) {
-        if self.state != State::Rev {
+        if self.state != State::Rev && !self.lim_rev.is_active() {
             self.state = State::Rev;
             self.update();
         }
     }
 
+    /// `target` is in per-mille of the travel
     pub fn goto(&mut self, target: u16) {
+        let target = target.min(FULL_TRAVEL);
+        self.tripped = None;
         if !self.within(target) || Some(target) == self.target {
             self.target = Some(target);
         }
@@ -130,17 +256,52 @@ impl<
         };
     }*/
 
-    fn pos_diff(&self) -> Option<u16> {
-        let mi = core::cmp::min(self.position, self.target?);
-        let ma = core::cmp::max(self.position, self.target?);
-        Some(ma - mi)
-    } 
+    /// Stops a move that ran into a limit switch
+    fn trip(&mut self, end: End) {
+        self.stop();
+        self.tripped = Some(end);
+    }
+
+    /// Drives toward full travel if `up`, else toward zero
+    fn go_toward(&mut self, up: bool) {
+        if up != self.config.invert_motor {
+            self.go_fwd();
+            if self.state != State::Fwd {
+                self.trip(End::Fwd);
+            }
+        }
+        else {
+            self.go_rev();
+            if self.state != State::Rev {
+                self.trip(End::Rev);
+            }
+        }
+    }
+
+    fn watch_progress(&mut self) {
+        let moved = self.position.max(self.progress_from) - self.position.min(self.progress_from);
+        if self.state == State::Stop || moved >= STALL_DISTANCE {
+            self.progress_from = self.position;
+            self.stall_ticks = 0;
+        }
+        else if self.stall_ticks < STALL_TICKS {
+            self.stall_ticks += 1;
+        }
+    }
 
     pub fn tick<AdcDev, PosAdc: OneShot<AdcDev, u16, PosPin>>(&mut self, pos_adc: &mut PosAdc) where PosPin: Channel<AdcDev> {
-        self.position = pos_adc.read(&mut self.pos_pin).ok().unwrap();
+        self.raw = pos_adc.read(&mut self.pos_pin).ok().unwrap();
+        self.position = self.config.per_mille(self.raw);
+        self.watch_progress();
 
-        if self.lim.is_low() && self.state == State::Fwd {
-            self.stop();
+        self.lim_fwd.update();
+        self.lim_rev.update();
+        if self.state == State::Fwd && self.lim_fwd.is_active() {
+            self.trip(End::Fwd);
+            return
+        }
+        if self.state == State::Rev && self.lim_rev.is_active() {
+            self.trip(End::Rev);
             return
         }
 
@@ -153,15 +314,15 @@ impl<
         }*/
 
         if let Some(target) = self.target {
-            if self.position + 10 < target {
-                self.go_rev();
+            if self.position + DEADBAND < target {
+                self.go_toward(true);
             }
             /*
             else if self.state == State::Fwd && self.position > self.target + 10 {
                 self.go_rev();
             }*/
-            else if self.position > target + 10 {
-                self.go_fwd();
+            else if self.position > target + DEADBAND {
+                self.go_toward(false);
             }
             /*
             else if self.state == State::Rev && self.position + 10 < self.target {
@@ -173,15 +334,39 @@ impl<
         }
     }
 
+    /// In per-mille of the travel
     pub fn position(&self) -> u16 {
         self.position
     }
 
+    /// Sensor reading, for calibrating the span
+    pub fn raw_position(&self) -> u16 {
+        self.raw
+    }
+
     pub fn within(&self, pos: u16) -> bool {
-        self.position + 50 >= pos && self.position <= pos + 50
+        self.position + NEAR >= pos && self.position <= pos + NEAR
     }
 
     pub fn stopped(&self) -> bool {
         self.target.is_none()
     }
+
+    pub fn at_limit(&self, end: End) -> bool {
+        match end {
+            End::Fwd => self.lim_fwd.is_active(),
+            End::Rev => self.lim_rev.is_active(),
+        }
+    }
+
+    /// End whose limit switch stopped the last move, cleared by `goto`
+    pub fn tripped(&self) -> Option<End> {
+        self.tripped
+    }
+
+    /// Driven for `STALL_TICKS` without getting anywhere. It keeps being
+    /// driven, this is only reported.
+    pub fn stalled(&self) -> bool {
+        self.stall_ticks >= STALL_TICKS
+    }
 }
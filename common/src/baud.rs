@@ -0,0 +1,56 @@
+/// Every link starts out at this speed and falls back to it
+pub const DEFAULT_BAUD: u32 = 9_600;
+
+/// Speeds that can be negotiated, a node does not agree to anything else
+pub const BAUD_RATES: [u32; 5] = [9_600, 19_200, 38_400, 57_600, 115_200];
+
+/// Tracks the speed of one end of a link and decides when to fall back to
+/// `DEFAULT_BAUD` because nothing decodes any more
+pub struct Autobaud {
+    baud: u32,
+    since_packet: u16,
+    timeout: u16,
+}
+
+impl Autobaud {
+    /// `timeout` is counted in calls to `tick`. The link counts as quiet
+    /// until the first packet.
+    pub const fn new(timeout: u16) -> Autobaud {
+        Autobaud {
+            baud: DEFAULT_BAUD,
+            since_packet: timeout,
+            timeout,
+        }
+    }
+
+    pub fn baud(&self) -> u32 {
+        self.baud
+    }
+
+    pub fn switch(&mut self, baud: u32) {
+        self.baud = baud;
+        self.since_packet = 0;
+    }
+
+    /// Call for every packet that decodes
+    pub fn received(&mut self) {
+        self.since_packet = 0;
+    }
+
+    /// Nothing decoded for `timeout` ticks
+    pub fn is_quiet(&self) -> bool {
+        self.since_packet >= self.timeout
+    }
+
+    /// Returns the speed to switch to when the link has to fall back
+    pub fn tick(&mut self) -> Option<u32> {
+        self.since_packet = self.since_packet.saturating_add(1);
+        if self.baud != DEFAULT_BAUD && self.since_packet >= self.timeout {
+            self.switch(DEFAULT_BAUD);
+            Some(DEFAULT_BAUD)
+        }
+        else {
+            None
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::button::Event;
+use crate::controls::{Mode, Side};
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Action {
+    EngineStart(Side),
+    EngineStop(Side),
+    EStop,
+    SwitchMode,
+    TrimSteering(i8),
+    CentreSteering,
+    CruiseHold,
+    CruiseAdjust(i8),
+}
+
+pub struct Binding {
+    /// Mode the binding is active in, `None` means every mode
+    pub mode: Option<Mode>,
+    pub button: u8,
+    pub event: Event,
+    pub action: Action,
+}
+
+const fn bind(mode: Option<Mode>, button: u8, event: Event, action: Action) -> Binding {
+    Binding {
+        mode,
+        button,
+        event,
+        action,
+    }
+}
+
+/* *****************
+ * Button layout
+ * *****************
+ *
+ * The table lives in flash, edit it to change the layout of a boat.
+ * Buttons are numbered as in the pinout, btn_1 is 1.
+ *
+ * Engines are started by holding the button so they can't be started by
+ * accident, and stopped by tapping it. The stop waits for the button to be
+ * let go, a press that turns into a hold would stop the engine first and
+ * the driver does not start it while the stop relay is closed.
+ *
+ * Cruise hold latches the current state of every running engine, pressing
+ * it again or the e-stop releases it. An engine is also released when its
+ * lever is pushed past the held position.
+ */
+pub static BINDINGS: [Binding; 17] = [
+    bind(None, 1, Event::Press, Action::SwitchMode),
+    bind(None, 5, Event::Press, Action::EStop),
+
+    bind(Some(Mode::MotorControl), 2, Event::Tap, Action::EngineStop(Side::Left)),
+    bind(Some(Mode::MotorControl), 2, Event::Hold, Action::EngineStart(Side::Left)),
+    bind(Some(Mode::MotorControl), 3, Event::Press, Action::CruiseHold),
+    bind(Some(Mode::MotorControl), 4, Event::Tap, Action::EngineStop(Side::Right)),
+    bind(Some(Mode::MotorControl), 4, Event::Hold, Action::EngineStart(Side::Right)),
+
+    bind(Some(Mode::DirectionControl), 2, Event::Press, Action::TrimSteering(-4)),
+    bind(Some(Mode::DirectionControl), 3, Event::Press, Action::CentreSteering),
+    bind(Some(Mode::DirectionControl), 4, Event::Press, Action::TrimSteering(4)),
+    bind(Some(Mode::DirectionControl), 2, Event::Hold, Action::TrimSteering(-16)),
+    bind(Some(Mode::DirectionControl), 4, Event::Hold, Action::TrimSteering(16)),
+
+    bind(Some(Mode::CruiseControl), 2, Event::Press, Action::CruiseAdjust(-4)),
+    bind(Some(Mode::CruiseControl), 3, Event::Press, Action::CruiseHold),
+    bind(Some(Mode::CruiseControl), 4, Event::Press, Action::CruiseAdjust(4)),
+    bind(Some(Mode::CruiseControl), 2, Event::Hold, Action::CruiseAdjust(-16)),
+    bind(Some(Mode::CruiseControl), 4, Event::Hold, Action::CruiseAdjust(16)),
+];
+
+pub(crate) fn lookup(mode: Mode, button: u8, event: Event) -> Option<Action> {
+    BINDINGS
+        .iter()
+        .find(|b| {
+            b.button == button
+                && b.event == event
+                && b.mode.map(|m| m == mode).unwrap_or(true)
+        })
+        .map(|b| b.action)
+}
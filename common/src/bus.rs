@@ -0,0 +1,141 @@
+/* *****************
+ * Bus addressing
+ * *****************
+ *
+ * Every node on a link hears every packet, as on an RS-485 bus, and only one
+ * of them may talk at a time. The controller is the master:
+ *
+ * - Only the master starts a conversation. It has at most one request
+ *   waiting for an answer and moves on once it comes or times out.
+ * - A node answers every packet addressed to it from the master with exactly
+ *   one packet, and never answers a broadcast.
+ * - Nodes are found by pinging the addresses that have not answered yet.
+ * - A host tool plugged into a port of the controller, in place of a node,
+ *   may ask the master about itself. The master answers it between its own
+ *   requests.
+ *
+ * Node addresses run from 1 to MAX_NODE.
+ */
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal::serial::Write;
+
+use crate::Packet;
+
+/// Address of every node at once
+pub const BROADCAST: u8 = 0;
+/// Address of the controller
+pub const MASTER: u8 = 0xff;
+/// Address of a host tool talking to the controller itself
+pub const HOST: u8 = 0xfe;
+/// Highest node address
+pub const MAX_NODE: u8 = 15;
+/// Ticks the master waits for an answer. A request can go out just before a
+/// tick, so an answer only counts as late once a whole tick went by.
+pub const REPLY_TICKS: u16 = 2;
+
+/// Keeps the master to one outstanding request and tracks which nodes answer
+pub struct BusMaster {
+    /// Node an answer is expected from, and ticks waited for it
+    waiting: Option<(u8, u16)>,
+    /// Ticks to wait for an answer
+    timeout: u16,
+    /// Bit n is set while node n answers
+    present: u16,
+    /// Address pinged last
+    scan: u8,
+}
+
+impl BusMaster {
+    /// `timeout` is counted in calls to `tick`
+    pub const fn new(timeout: u16) -> BusMaster {
+        BusMaster {
+            waiting: None,
+            timeout,
+            present: 0,
+            scan: 0,
+        }
+    }
+
+    /// True when a request can go out
+    pub fn is_idle(&self) -> bool {
+        self.waiting.is_none()
+    }
+
+    /// Call after sending a request to `node` that it has to answer
+    pub fn expect(&mut self, node: u8) {
+        self.waiting = Some((node, 0));
+    }
+
+    /// Call for every packet received, returns true if it is the answer
+    /// the master was waiting for
+    pub fn received(&mut self, packet: &Packet) -> bool {
+        match self.waiting {
+            Some((node, _)) if packet.src == node && packet.dst == MASTER => {
+                self.waiting = None;
+                self.present |= 1 << node;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the node that did not answer in time
+    pub fn tick(&mut self) -> Option<u8> {
+        let (node, ticks) = self.waiting?;
+        if ticks + 1 >= self.timeout {
+            self.waiting = None;
+            self.present &= !(1 << node);
+            Some(node)
+        }
+        else {
+            self.waiting = Some((node, ticks + 1));
+            None
+        }
+    }
+
+    pub fn is_present(&self, node: u8) -> bool {
+        (1..=MAX_NODE).contains(&node) && self.present & 1 << node != 0
+    }
+
+    /// Next address to ping, going round the ones that are not present.
+    /// None once every address answers.
+    pub fn next_scan(&mut self) -> Option<u8> {
+        for _ in 0..MAX_NODE {
+            self.scan = self.scan % MAX_NODE + 1;
+            if !self.is_present(self.scan) {
+                return Some(self.scan);
+            }
+        }
+        None
+    }
+}
+
+/// Serial writer for a half duplex transceiver. `de` is driven high with the
+/// first byte, and low again by `flush` once the last byte is out.
+pub struct HalfDuplex<W, DE> {
+    tx: W,
+    de: DE,
+}
+
+impl<W, DE: OutputPin> HalfDuplex<W, DE> {
+    pub fn new(tx: W, mut de: DE) -> Self {
+        de.set_low();
+        HalfDuplex { tx, de }
+    }
+}
+
+impl<W: Write<u8>, DE: OutputPin> Write<u8> for HalfDuplex<W, DE> {
+    type Error = W::Error;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), W::Error> {
+        self.de.set_high();
+        self.tx.write(byte)
+    }
+
+    fn flush(&mut self) -> nb::Result<(), W::Error> {
+        self.tx.flush()?;
+        self.de.set_low();
+        Ok(())
+    }
+}
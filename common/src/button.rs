@@ -0,0 +1,107 @@
+use embedded_hal::digital::InputPin;
+
+const MASK: u32    = 0xfff00fff;
+const RISING: u32  = 0x00000fff;
+const FALLING: u32 = 0xfff00000;
+const HIGH: u32    = 0xffffffff;
+const LOW: u32     = 0x00000000;
+
+/// Number of clock ticks a button has to be held down before it counts as a hold
+pub const HOLD_TICKS: u8 = 10;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Event {
+    Press,
+    /// Let go before it counted as a hold
+    Tap,
+    /// Let go after a hold
+    Release,
+    Hold,
+}
+
+pub struct Button<Pin: InputPin> {
+    pin: Pin,
+    state: u32,
+    held: u8,
+    /// Set once `held` reported this press as a hold
+    was_held: bool,
+}
+
+impl<Pin: InputPin> Button<Pin> {
+    pub fn new(pin: Pin) -> Self {
+        Button {
+            pin,
+            // Released, a boot does not show up as a tap
+            state: HIGH,
+            held: 0,
+            was_held: false,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.state = (self.state << 1) | (self.pin.is_high() as u32);
+    }
+
+    pub fn is_rising(&mut self) -> bool {
+        if (self.state & MASK) == RISING {
+            self.state = 0xff_ff_ff_ff;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    pub fn is_falling(&mut self) -> bool {
+        if (self.state & MASK) == FALLING {
+            self.state = 0x00_00_00_00;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    /// Buttons are pulled up, so a press shows up as a falling edge
+    pub fn event(&mut self) -> Option<Event> {
+        if self.is_falling() {
+            self.was_held = false;
+            Some(Event::Press)
+        }
+        else if self.is_rising() {
+            if self.was_held {
+                Some(Event::Release)
+            }
+            else {
+                Some(Event::Tap)
+            }
+        }
+        else {
+            None
+        }
+    }
+
+    /// Should be called once per clock tick, returns true once when the
+    /// button has been held down for `HOLD_TICKS` ticks
+    pub fn held(&mut self) -> bool {
+        if self.is_low() {
+            self.held = self.held.saturating_add(1);
+            if self.held == HOLD_TICKS {
+                self.was_held = true;
+            }
+            self.held == HOLD_TICKS
+        }
+        else {
+            self.held = 0;
+            false
+        }
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.state == HIGH
+    }
+
+    pub fn is_low(&self) -> bool {
+        self.state == LOW
+    }
+}
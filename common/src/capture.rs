@@ -0,0 +1,144 @@
+use byteorder::{ByteOrder, LE};
+
+use crate::packet::{Encode, RawPacket, MAX_BODY_LEN};
+use crate::Packet;
+
+/* *****************
+ * Capture layout
+ * *****************
+ *
+ * header:   "BCAP", the version as a u8, then the wall clock time the
+ *           capture started at as a u64, milliseconds since 1970 UTC
+ *
+ * Followed by one record per packet:
+ *
+ * time:     u32 little endian, milliseconds since the capture started
+ * dir:      u8, 0 for a packet sent by the recorder, 1 for one it received
+ * len:      u8, length of the body
+ * body:     the packet as laid out in packet.rs, without any framing
+ *
+ * Leaving out the framing means a capture can be replayed on any link.
+ */
+
+pub const CAPTURE_MAGIC: [u8; 4] = *b"BCAP";
+/// Version 2 added the source address to the packet header, version 3 the
+/// start time and times in telemetry
+pub const CAPTURE_VERSION: u8 = 3;
+pub const CAPTURE_HEADER_LEN: usize = 13;
+/// Longest record in a capture
+pub const MAX_RECORD_LEN: usize = 6 + MAX_BODY_LEN;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Record {
+    /// Milliseconds since the capture started
+    pub time: u32,
+    pub direction: Direction,
+    pub packet: Packet,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum CaptureError {
+    /// Not a capture, or one written by a newer version
+    BadHeader,
+    /// The capture ends in the middle of a record
+    Truncated,
+    /// A record that does not hold a valid packet
+    BadRecord,
+}
+
+/// Writes the header every capture starts with, returns the number of bytes
+/// written. `start` is the wall clock time in milliseconds since 1970 UTC.
+pub fn write_capture_header(buf: &mut [u8], start: u64) -> usize {
+    buf[0..4].copy_from_slice(&CAPTURE_MAGIC);
+    buf[4] = CAPTURE_VERSION;
+    LE::write_u64(&mut buf[5..13], start);
+    CAPTURE_HEADER_LEN
+}
+
+impl Record {
+    /// Returns the number of bytes written, `buf` has to hold `MAX_RECORD_LEN`
+    pub fn write(&self, buf: &mut [u8]) -> usize {
+        LE::write_u32(&mut buf[0..4], self.time);
+        buf[4] = match self.direction {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        };
+        let len = self.packet.encode(&mut buf[6..]);
+        buf[5] = len as u8;
+        6 + len
+    }
+}
+
+/// Reads the records of a capture held in memory
+pub struct CaptureReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> CaptureReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Result<Self, CaptureError> {
+        if buf.len() < CAPTURE_HEADER_LEN
+            || buf[0..4] != CAPTURE_MAGIC
+            || buf[4] != CAPTURE_VERSION
+        {
+            return Err(CaptureError::BadHeader);
+        }
+        Ok(CaptureReader {
+            buf,
+            pos: CAPTURE_HEADER_LEN,
+        })
+    }
+
+    /// When the capture started, milliseconds since 1970 UTC. A record was
+    /// made at this plus its `time`.
+    pub fn start(&self) -> u64 {
+        LE::read_u64(&self.buf[5..13])
+    }
+
+    fn read(&mut self) -> Result<Record, CaptureError> {
+        let rest = &self.buf[self.pos..];
+        if rest.len() < 6 {
+            return Err(CaptureError::Truncated);
+        }
+        let len = rest[5] as usize;
+        if rest.len() < 6 + len {
+            return Err(CaptureError::Truncated);
+        }
+        let direction = match rest[4] {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            _ => return Err(CaptureError::BadRecord),
+        };
+        let packet = RawPacket::read(&rest[6..6 + len])
+            .and_then(|raw| Packet::from_raw(&raw))
+            .ok_or(CaptureError::BadRecord)?;
+        self.pos += 6 + len;
+        Ok(Record {
+            time: LE::read_u32(&rest[0..4]),
+            direction,
+            packet,
+        })
+    }
+}
+
+impl<'a> Iterator for CaptureReader<'a> {
+    type Item = Result<Record, CaptureError>;
+
+    /// Stops after the first error, the rest of the capture can't be trusted
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.buf.len() {
+            return None;
+        }
+        let record = self.read();
+        if record.is_err() {
+            self.pos = self.buf.len();
+        }
+        Some(record)
+    }
+}
@@ -0,0 +1,76 @@
+//! Millisecond clocks. Every board counts its own uptime, and keeps the
+//! system time, the uptime of the controller, from the `TimeSync` packets the
+//! controller broadcasts. Times in telemetry and fault logs are system times,
+//! so they line up between boards.
+//!
+//! Both wrap after about 49 days. A node that has not been synced yet runs
+//! the system time from its own start.
+
+/// A controller further behind than this has restarted, its syncs come a
+/// second apart so a clock cannot drift this far
+pub const RESTART_MS: u32 = 10_000;
+
+/// Counted in the SysTick interrupt of a board
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Clock {
+    /// Milliseconds since the board started
+    uptime: u32,
+    /// Milliseconds on the controller
+    time: u32,
+    /// Ticks the system time stands still for, to let the controller catch up
+    hold: u32,
+}
+
+impl Clock {
+    pub const fn new() -> Clock {
+        Clock {
+            uptime: 0,
+            time: 0,
+            hold: 0,
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::new()
+    }
+}
+
+impl Clock {
+    /// Call every millisecond
+    pub fn tick(&mut self) {
+        self.uptime = self.uptime.wrapping_add(1);
+        if self.hold > 0 {
+            self.hold -= 1;
+        }
+        else {
+            self.time = self.time.wrapping_add(1);
+        }
+    }
+
+    pub fn uptime(&self) -> u32 {
+        self.uptime
+    }
+
+    /// The system time, it only goes back when the controller restarts
+    pub fn now(&self) -> u32 {
+        self.time
+    }
+
+    /// Takes the system time the controller sent. A clock that is behind
+    /// jumps ahead, one that is a little ahead stands still until the
+    /// controller catches up. One that is more than `RESTART_MS` ahead
+    /// follows a restarted controller back. The time the packet took on the
+    /// link is not made up for.
+    pub fn sync(&mut self, time: u32) {
+        let ahead = self.time.wrapping_sub(time) as i32;
+        if ahead > 0 && ahead as u32 <= RESTART_MS {
+            self.hold = ahead as u32;
+        }
+        else {
+            self.time = time;
+            self.hold = 0;
+        }
+    }
+}
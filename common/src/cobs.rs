@@ -0,0 +1,134 @@
+use crate::framing::{Framing, ParseError};
+use crate::packet::{RawPacket, MAX_BODY_LEN, MAX_PACKET_LEN};
+use crate::Packet;
+
+/// Framing that COBS encodes every packet and ends it with a zero byte.
+/// Zero never shows up inside a packet, so the parser can't lock onto a
+/// false start in the payload.
+pub struct CobsParser {
+    buf: [u8; MAX_PACKET_LEN],
+    n: usize,
+    /// Set when a packet overflowed, the rest of it is skipped
+    skip: bool,
+    errors: u32,
+}
+
+impl CobsParser {
+    pub const fn new() -> CobsParser {
+        CobsParser {
+            buf: [0; MAX_PACKET_LEN],
+            n: 0,
+            skip: false,
+            errors: 0,
+        }
+    }
+
+    fn error(&mut self, e: ParseError) -> Result<Option<Packet>, ParseError> {
+        self.errors = self.errors.wrapping_add(1);
+        Err(e)
+    }
+}
+
+impl Default for CobsParser {
+    fn default() -> Self {
+        CobsParser::new()
+    }
+}
+
+/// Decodes `buf` in place, returns the decoded length
+fn decode(buf: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < buf.len() {
+        let code = buf[read] as usize;
+        if code == 0 || read + code > buf.len() {
+            return None;
+        }
+        read += 1;
+        for _ in 1..code {
+            buf[write] = buf[read];
+            write += 1;
+            read += 1;
+        }
+        // A full block has no implicit zero, neither does the last one
+        if code != 0xff && read < buf.len() {
+            buf[write] = 0;
+            write += 1;
+        }
+    }
+    Some(write)
+}
+
+impl Framing for CobsParser {
+    fn frame(body: &[u8], buf: &mut [u8]) -> usize {
+        let mut code_i = 0;
+        let mut write = 1;
+        let mut code = 1u8;
+        for b in body {
+            if *b == 0 {
+                buf[code_i] = code;
+                code_i = write;
+                write += 1;
+                code = 1;
+            }
+            else {
+                buf[write] = *b;
+                write += 1;
+                code += 1;
+                if code == 0xff {
+                    buf[code_i] = code;
+                    code_i = write;
+                    write += 1;
+                    code = 1;
+                }
+            }
+        }
+        buf[code_i] = code;
+        buf[write] = 0;
+        write + 1
+    }
+
+    fn push(&mut self, byte: u8) -> Result<Option<Packet>, ParseError> {
+        if byte != 0 {
+            if self.skip {
+                return Ok(None);
+            }
+            if self.n == self.buf.len() {
+                self.skip = true;
+                self.n = 0;
+                return self.error(ParseError::BadLength(MAX_BODY_LEN as u8));
+            }
+            self.buf[self.n] = byte;
+            self.n += 1;
+            return Ok(None);
+        }
+
+        let n = self.n;
+        self.n = 0;
+        if self.skip || n == 0 {
+            // End of an overlong packet, or zeros between packets
+            self.skip = false;
+            return Ok(None);
+        }
+
+        let len = match decode(&mut self.buf[..n]) {
+            Some(len) => len,
+            None => return self.error(ParseError::BadEncoding),
+        };
+        let raw = match RawPacket::read(&self.buf[..len]) {
+            Some(raw) => raw,
+            None => return self.error(ParseError::BadChecksum),
+        };
+        match Packet::from_raw(&raw) {
+            Some(packet) => Ok(Some(packet)),
+            None => {
+                let tag = raw.tag;
+                self.error(ParseError::UnknownPacket(tag))
+            }
+        }
+    }
+
+    fn errors(&self) -> u32 {
+        self.errors
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{CruiseHold, Message, MotorState};
+
+use crate::bindings::{self, Action};
+use crate::button::Event;
+
+/// Steering pot has to move this much before a centred wheel is released
+const CENTRE_RELEASE: i16 = 4;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Mode {
+    MotorControl,
+    DirectionControl,
+    CruiseControl,
+}
+
+impl Mode {
+    fn switch(&mut self) {
+        match self {
+            Mode::MotorControl => *self = Mode::DirectionControl,
+            Mode::DirectionControl => *self = Mode::CruiseControl,
+            Mode::CruiseControl => *self = Mode::MotorControl,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    fn index(self) -> usize {
+        match self {
+            Side::Left => 0,
+            Side::Right => 1,
+        }
+    }
+}
+
+pub struct Controls {
+    pub mode: Mode,
+    running: [bool; 2],
+    /// Start or stop waiting to be sent to each driver
+    commands: [Option<Message>; 2],
+    hold: [Option<CruiseHold>; 2],
+    trim: i16,
+    /// Pot position when the wheel was centred
+    centre: Option<u8>,
+    /// Last state sent for each engine
+    motor_state: [MotorState; 2],
+    direction: u8,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Controls::new()
+    }
+}
+
+impl Controls {
+    pub fn new() -> Self {
+        Controls {
+            mode: Mode::MotorControl,
+            running: [false; 2],
+            commands: [None; 2],
+            hold: [None; 2],
+            trim: 0,
+            centre: None,
+            motor_state: [MotorState::Idle(0); 2],
+            direction: 128,
+        }
+    }
+
+    pub fn handle(&mut self, button: u8, event: Event) {
+        if let Some(action) = bindings::lookup(self.mode, button, event) {
+            self.apply(action);
+        }
+    }
+
+    pub fn apply(&mut self, action: Action) {
+        match action {
+            Action::EngineStart(side) => {
+                // Only start in neutral
+                if let MotorState::Idle(_) = self.motor_state[side.index()] {
+                    self.running[side.index()] = true;
+                    self.commands[side.index()] = Some(Message::EngineStart);
+                }
+            }
+            Action::EngineStop(side) => {
+                self.running[side.index()] = false;
+                self.commands[side.index()] = Some(Message::EngineStop);
+            }
+            Action::EStop => {
+                self.running = [false; 2];
+                self.hold = [None; 2];
+            }
+            Action::SwitchMode => self.mode.switch(),
+            Action::TrimSteering(x) => {
+                self.trim = (self.trim + x as i16).clamp(-128, 127);
+            }
+            Action::CentreSteering => {
+                self.trim = 0;
+                self.centre = Some(self.direction);
+            }
+            Action::CruiseHold => {
+                if self.hold.iter().any(|h| h.is_some()) {
+                    self.hold = [None; 2];
+                }
+                else {
+                    for i in 0..2 {
+                        if self.running[i] {
+                            self.hold[i] = Some(CruiseHold::latch(self.motor_state[i]));
+                        }
+                    }
+                }
+            }
+            Action::CruiseAdjust(x) => {
+                for hold in self.hold.iter_mut().flatten() {
+                    hold.adjust(x);
+                }
+            }
+        }
+    }
+
+    /// `EngineStart` or `EngineStop` for the driver of `side`, once
+    pub fn take_command(&mut self, side: Side) -> Option<Message> {
+        self.commands[side.index()].take()
+    }
+
+    pub fn is_running(&self, side: Side) -> bool {
+        self.running[side.index()]
+    }
+
+    pub fn is_held(&self, side: Side) -> bool {
+        self.hold[side.index()].is_some()
+    }
+
+    pub fn motor_state(&mut self, side: Side, lever: MotorState) -> MotorState {
+        let i = side.index();
+        if let Some(hold) = self.hold[i] {
+            if hold.releases(lever) {
+                self.hold[i] = None;
+            }
+        }
+
+        self.motor_state[i] = match self.hold[i] {
+            Some(hold) => hold.state(),
+            None => lever,
+        };
+
+        if self.running[i] {
+            self.motor_state[i]
+        }
+        else {
+            MotorState::Idle(0)
+        }
+    }
+
+    pub fn motor_direction(&mut self, pot: u8) -> u8 {
+        self.direction = pot;
+
+        if let Some(c) = self.centre {
+            if (pot as i16 - c as i16).abs() > CENTRE_RELEASE {
+                self.centre = None;
+            }
+        }
+
+        let direction = match self.centre {
+            Some(_) => 128,
+            None => pot as i16,
+        };
+
+        (direction + self.trim).clamp(0, 255) as u8
+    }
+}
@@ -0,0 +1,59 @@
+use crate::MotorState;
+
+/// Motor state as a signed power, forward is positive
+fn signed_power(state: MotorState) -> i16 {
+    match state {
+        MotorState::Idle(_) => 0,
+        MotorState::Fwd(p) => p as i16,
+        MotorState::Rev(p) => -(p as i16),
+    }
+}
+
+/// A motor state kept by cruise control while the lever is left alone
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct CruiseHold {
+    state: MotorState,
+    /// Where the lever was when the hold was taken
+    lever: MotorState,
+}
+
+impl CruiseHold {
+    /// Holds what the lever asks for now
+    pub fn latch(lever: MotorState) -> CruiseHold {
+        CruiseHold {
+            state: lever,
+            lever,
+        }
+    }
+
+    pub fn state(&self) -> MotorState {
+        self.state
+    }
+
+    /// Changes the held power without changing its direction
+    pub fn adjust(&mut self, x: i8) {
+        let power = |p: u8| (p as i16 + x as i16).clamp(0, 255) as u8;
+        self.state = match self.state {
+            MotorState::Idle(p) => MotorState::Idle(p),
+            MotorState::Fwd(p) => MotorState::Fwd(power(p)),
+            MotorState::Rev(p) => MotorState::Rev(power(p)),
+        };
+    }
+
+    /// The hold is released when the lever is pushed past where it was when
+    /// the hold was taken, or to the opposite direction. Adjusting the hold
+    /// does not move that point.
+    pub fn releases(&self, lever: MotorState) -> bool {
+        let latched = signed_power(self.lever);
+        let lever = signed_power(lever);
+        if latched > 0 {
+            lever > latched || lever < 0
+        }
+        else if latched < 0 {
+            lever < latched || lever > 0
+        }
+        else {
+            lever != 0
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Faults kept in flash over power cycles, in a `FlashRing`. When the ring
+//! is full the oldest page of records is dropped.
+//!
+//! The data of a slot is a fault record, see `FaultRecord::write`, and a
+//! byte of padding.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use byteorder::{ByteOrder, LE};
+
+use crate::flash_ring::{Flash, FlashError, FlashRing};
+use crate::{Lock, Queue};
+
+/// Bytes of a `FaultRecord` in a packet or a slot
+pub const FAULT_RECORD_LEN: usize = 9;
+
+const SLOT_LEN: usize = 16;
+
+/// Faults that can wait for the main loop. They come one at a time and the
+/// loop writes them every pass, so the queue only fills if it hangs.
+pub const FAULT_QUEUE_LEN: usize = 8;
+
+/// What went wrong
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum FaultKind {
+    /// A gear or throttle actuator was driven but did not move, the context
+    /// is 0 for the gear and 1 for the throttle
+    Stall,
+    /// The stepper driver raised its alarm line, the context is the
+    /// position in steps
+    StepperAlarm,
+    /// Nothing was heard on a link for too long. On a node the context is
+    /// the serial port, on the controller the node that went quiet.
+    LinkLost,
+    /// The context is the node the stop came from, `MASTER` for the
+    /// controller
+    EStop,
+    /// Logged at the boot after the reset
+    WatchdogReset,
+    /// Logged at the boot after the reset, `GetPanic` has the message
+    Panic,
+}
+
+impl FaultKind {
+    pub(crate) fn code(self) -> u8 {
+        match self {
+            FaultKind::Stall => 1,
+            FaultKind::StepperAlarm => 2,
+            FaultKind::LinkLost => 3,
+            FaultKind::EStop => 4,
+            FaultKind::WatchdogReset => 5,
+            FaultKind::Panic => 6,
+        }
+    }
+
+    pub(crate) fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(FaultKind::Stall),
+            2 => Some(FaultKind::StepperAlarm),
+            3 => Some(FaultKind::LinkLost),
+            4 => Some(FaultKind::EStop),
+            5 => Some(FaultKind::WatchdogReset),
+            6 => Some(FaultKind::Panic),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct FaultRecord {
+    pub kind: FaultKind,
+    /// System time in milliseconds, see clock.rs
+    pub time: u32,
+    /// More about the fault, see `FaultKind`
+    pub context: i32,
+}
+
+impl FaultRecord {
+    /// [kind, u32 time, i32 context]
+    pub(crate) fn write(&self, buf: &mut [u8]) {
+        buf[0] = self.kind.code();
+        LE::write_u32(&mut buf[1..5], self.time);
+        LE::write_i32(&mut buf[5..9], self.context);
+    }
+
+    pub(crate) fn read(buf: &[u8]) -> Option<FaultRecord> {
+        Some(FaultRecord {
+            kind: FaultKind::from_code(buf[0])?,
+            time: LE::read_u32(&buf[1..5]),
+            context: LE::read_i32(&buf[5..9]),
+        })
+    }
+}
+
+pub struct FaultLog<F> {
+    ring: FlashRing<F>,
+}
+
+impl<F: Flash> FaultLog<F> {
+    /// Finds where the log left off, the flash should only ever have been
+    /// written by a `FaultLog`
+    pub fn open(flash: F) -> FaultLog<F> {
+        FaultLog { ring: FlashRing::open(flash, SLOT_LEN) }
+    }
+
+    /// Adds a record, blocking for a page erase whenever the ring moves on
+    /// to the next page
+    pub fn record(&mut self, record: FaultRecord) -> Result<(), FlashError> {
+        let mut data = [0xff; SLOT_LEN - 6];
+        record.write(&mut data);
+        self.ring.push(&data)
+    }
+
+    /// A record from the log, 0 is the newest
+    pub fn get(&self, index: usize) -> Option<FaultRecord> {
+        let mut data = [0; SLOT_LEN - 6];
+        if self.ring.get(index, &mut data) {
+            FaultRecord::read(&data)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Erases every page
+    pub fn clear(&mut self) -> Result<(), FlashError> {
+        self.ring.clear()
+    }
+}
+
+/// Faults noticed in interrupts, waiting for the main loop to write them to
+/// a `FaultLog`. Writing flash stalls the CPU for as long as an erase takes,
+/// which no interrupt handler should do. `L` is how the boards keep
+/// interrupts out.
+pub struct PendingFaults<L> {
+    /// Only touched with `L` held
+    records: UnsafeCell<Queue<FaultRecord, FAULT_QUEUE_LEN>>,
+    clear: AtomicBool,
+    lock: PhantomData<L>,
+}
+
+// The queue is only touched under the lock
+unsafe impl<L> Sync for PendingFaults<L> {}
+
+impl<L> PendingFaults<L> {
+    pub const fn new() -> PendingFaults<L> {
+        PendingFaults {
+            records: UnsafeCell::new(Queue::new()),
+            clear: AtomicBool::new(false),
+            lock: PhantomData,
+        }
+    }
+}
+
+impl<L> Default for PendingFaults<L> {
+    fn default() -> PendingFaults<L> {
+        PendingFaults::new()
+    }
+}
+
+impl<L: Lock> PendingFaults<L> {
+    /// Can be called from any interrupt. A record that does not fit is
+    /// dropped and counted.
+    pub fn push(&self, record: FaultRecord) {
+        self.records(|records| records.push(record)).ok();
+    }
+
+    /// Asks for the log to be emptied. Records still waiting go with it.
+    pub fn clear(&self) {
+        self.clear.store(true, Ordering::Relaxed);
+    }
+
+    /// True if `commit` has something to do
+    pub fn is_pending(&self) -> bool {
+        self.clear.load(Ordering::Relaxed) || !self.records(|records| records.is_empty())
+    }
+
+    /// Records that were dropped because the queue was full
+    pub fn dropped(&self) -> u32 {
+        self.records(|records| records.dropped())
+    }
+
+    /// Writes what is waiting to `log`. Only call it from one place, the
+    /// main loop on the boards.
+    pub fn commit<F: Flash>(&self, log: &mut FaultLog<F>) -> Result<(), FlashError> {
+        // The lock is only held to take a record out, not while writing
+        if self.clear.swap(false, Ordering::Relaxed) {
+            self.records(|records| while records.pop().is_some() {});
+            log.clear()?;
+        }
+        while let Some(record) = self.records(|records| records.pop()) {
+            log.record(record)?;
+        }
+        Ok(())
+    }
+
+    /// Runs `f` on the queue with `L` held
+    fn records<R>(&self, f: impl FnOnce(&mut Queue<FaultRecord, FAULT_QUEUE_LEN>) -> R) -> R {
+        L::lock(|| f(unsafe { &mut *self.records.get() }))
+    }
+}
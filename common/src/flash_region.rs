@@ -0,0 +1,123 @@
+//! Whole pages of the internal flash of an STM32F1, written through its flash
+//! interface. Each board reaches the registers and the flash itself through
+//! its own peripheral access crate, see `FlashRegisters`.
+
+use crate::{Flash, FlashError};
+
+// FLASH_KEYR keys
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+
+// FLASH_SR bits
+const SR_BSY: u32 = 1 << 0;
+const SR_PGERR: u32 = 1 << 2;
+const SR_WRPRTERR: u32 = 1 << 4;
+const SR_EOP: u32 = 1 << 5;
+
+// FLASH_CR bits
+const CR_PG: u32 = 1 << 0;
+const CR_PER: u32 = 1 << 1;
+const CR_STRT: u32 = 1 << 6;
+const CR_LOCK: u32 = 1 << 7;
+
+/// The flash interface registers, and the flash behind them
+pub trait FlashRegisters {
+    /// Bytes in a page, 1 KiB on medium density parts like the STM32F103C8
+    const PAGE_SIZE: usize;
+
+    fn write_keyr(&self, key: u32);
+    fn write_cr(&self, cr: u32);
+    fn write_ar(&self, address: u32);
+    fn read_sr(&self) -> u32;
+    /// Flags are cleared by writing 1
+    fn write_sr(&self, sr: u32);
+
+    fn read_byte(&self, address: usize) -> u8;
+    /// Stores a half word, which programs it while `CR_PG` is set
+    fn write_half(&self, address: usize, half: u16);
+}
+
+/// Whole pages of the internal flash. The CPU stalls while they are written
+/// or erased, as the program runs from the same flash.
+pub struct FlashRegion<R> {
+    regs: R,
+    start: usize,
+    pages: usize,
+}
+
+impl<R> FlashRegion<R> {
+    /// # Safety
+    ///
+    /// `start` has to be the start of a page, and nothing else may use the
+    /// pages.
+    pub const unsafe fn new(regs: R, start: usize, pages: usize) -> FlashRegion<R> {
+        FlashRegion { regs, start, pages }
+    }
+}
+
+impl<R: FlashRegisters> FlashRegion<R> {
+    fn unlock(&self) {
+        self.regs.write_keyr(KEY1);
+        self.regs.write_keyr(KEY2);
+    }
+
+    fn lock(&self) {
+        self.regs.write_cr(CR_LOCK);
+    }
+
+    /// Waits for the operation to end and clears its flags
+    fn wait(&self) -> Result<(), FlashError> {
+        while self.regs.read_sr() & SR_BSY != 0 {}
+        let sr = self.regs.read_sr();
+        self.regs.write_sr(SR_PGERR | SR_WRPRTERR | SR_EOP);
+        if sr & SR_WRPRTERR != 0 {
+            Err(FlashError::Protected)
+        }
+        else if sr & SR_PGERR != 0 {
+            Err(FlashError::Program)
+        }
+        else {
+            Ok(())
+        }
+    }
+}
+
+impl<R: FlashRegisters> Flash for FlashRegion<R> {
+    const PAGE_SIZE: usize = R::PAGE_SIZE;
+
+    fn size(&self) -> usize {
+        self.pages * R::PAGE_SIZE
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = self.regs.read_byte(self.start + offset + i);
+        }
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+        self.unlock();
+        self.regs.write_cr(CR_PG);
+        let mut result = Ok(());
+        // Programmed a half word at a time
+        for (i, half) in data.chunks(2).enumerate() {
+            self.regs.write_half(self.start + offset + 2 * i, half[0] as u16 | (half[1] as u16) << 8);
+            result = self.wait();
+            if result.is_err() {
+                break;
+            }
+        }
+        self.lock();
+        result
+    }
+
+    fn erase(&mut self, page: usize) -> Result<(), FlashError> {
+        self.unlock();
+        self.regs.write_cr(CR_PER);
+        self.regs.write_ar((self.start + page * R::PAGE_SIZE) as u32);
+        self.regs.write_cr(CR_PER | CR_STRT);
+        let result = self.wait();
+        self.lock();
+        result
+    }
+}
@@ -0,0 +1,152 @@
+//! Records kept in flash over power cycles. A ring of fixed size slots over a
+//! few flash pages. Slots are written in turn, so every page wears the same,
+//! and a page is erased just before the ring comes back around to it,
+//! dropping the oldest records.
+//!
+//! Slot layout, erased flash reads 0xff:
+//!
+//! seq:      u32, counts the records written, 0xffffffff is an empty slot
+//! data:     slot length - 6 bytes
+//! crc:      u16, CRC-16/CCITT of the bytes before it, a slot that does not
+//!           match was cut off by a reset while being written
+
+use byteorder::{ByteOrder, LE};
+
+use crate::packet::crc16;
+
+/// Longest slot
+pub(crate) const MAX_SLOT_LEN: usize = 32;
+
+const EMPTY: u32 = 0xffff_ffff;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum FlashError {
+    /// The flash was not erased where it was written
+    Program,
+    /// The page is write protected
+    Protected,
+}
+
+/// Some whole pages of flash, addressed from the start of the first
+pub trait Flash {
+    /// Bytes in a page, the smallest part that can be erased
+    const PAGE_SIZE: usize;
+
+    /// Bytes in all the pages
+    fn size(&self) -> usize;
+
+    fn read(&self, offset: usize, buf: &mut [u8]);
+
+    /// Writes erased flash, `offset` and the length of `data` are even
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError>;
+
+    /// Sets a whole page to 0xff
+    fn erase(&mut self, page: usize) -> Result<(), FlashError>;
+}
+
+pub(crate) struct FlashRing<F> {
+    flash: F,
+    /// A power of two up to `MAX_SLOT_LEN`, so slots never cross a page
+    slot_len: usize,
+    /// Slot the next record goes to
+    next: usize,
+    /// Sequence number of the next record
+    seq: u32,
+}
+
+impl<F: Flash> FlashRing<F> {
+    /// Finds where the ring left off, the flash should only ever have been
+    /// written by a ring with the same slot length
+    pub fn open(flash: F, slot_len: usize) -> FlashRing<F> {
+        let mut ring = FlashRing { flash, slot_len, next: 0, seq: 0 };
+        let mut buf = [0; MAX_SLOT_LEN];
+        let mut newest = None;
+        for slot in 0..ring.slots() {
+            if let Some(seq) = ring.read_slot(slot, &mut buf) {
+                if newest.map_or(true, |(_, s)| seq > s) {
+                    newest = Some((slot, seq));
+                }
+            }
+        }
+        if let Some((slot, seq)) = newest {
+            ring.next = (slot + 1) % ring.slots();
+            ring.seq = seq.wrapping_add(1);
+        }
+        ring
+    }
+
+    /// Adds a record of `slot_len - 6` bytes, blocking for a page erase
+    /// whenever the ring moves on to the next page
+    pub fn push(&mut self, data: &[u8]) -> Result<(), FlashError> {
+        let len = self.slot_len;
+        let mut buf = [0xff; MAX_SLOT_LEN];
+        LE::write_u32(&mut buf[0..4], self.seq);
+        buf[4..len - 2].copy_from_slice(data);
+        let crc = crc16(&buf[..len - 2]);
+        LE::write_u16(&mut buf[len - 2..len], crc);
+
+        for _ in 0..self.slots() {
+            let slot = self.next;
+            self.next = (slot + 1) % self.slots();
+            let offset = slot * len;
+            if offset % F::PAGE_SIZE == 0 {
+                self.flash.erase(offset / F::PAGE_SIZE)?;
+            }
+            else if !self.is_empty(slot) {
+                // Cut off by a reset, or not ours
+                continue;
+            }
+            self.flash.write(offset, &buf[..len])?;
+            self.seq = self.seq.wrapping_add(1);
+            return Ok(());
+        }
+        Err(FlashError::Program)
+    }
+
+    /// Reads a record into `data`, 0 is the newest. False if it is gone.
+    pub fn get(&self, index: usize, data: &mut [u8]) -> bool {
+        let seq = self.seq.wrapping_sub(1).wrapping_sub(index as u32);
+        let mut buf = [0; MAX_SLOT_LEN];
+        for slot in 0..self.slots() {
+            if self.read_slot(slot, &mut buf) == Some(seq) {
+                data.copy_from_slice(&buf[4..self.slot_len - 2]);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Erases every page
+    pub fn clear(&mut self) -> Result<(), FlashError> {
+        for page in 0..self.flash.size() / F::PAGE_SIZE {
+            self.flash.erase(page)?;
+        }
+        self.next = 0;
+        self.seq = 0;
+        Ok(())
+    }
+
+    fn slots(&self) -> usize {
+        self.flash.size() / self.slot_len
+    }
+
+    fn is_empty(&self, slot: usize) -> bool {
+        let mut buf = [0; MAX_SLOT_LEN];
+        let buf = &mut buf[..self.slot_len];
+        self.flash.read(slot * self.slot_len, buf);
+        buf.iter().all(|&b| b == 0xff)
+    }
+
+    /// The sequence number of a slot that holds a record
+    fn read_slot(&self, slot: usize, buf: &mut [u8; MAX_SLOT_LEN]) -> Option<u32> {
+        let len = self.slot_len;
+        self.flash.read(slot * len, &mut buf[..len]);
+        let seq = LE::read_u32(&buf[0..4]);
+        if seq == EMPTY || LE::read_u16(&buf[len - 2..len]) != crc16(&buf[..len - 2]) {
+            None
+        }
+        else {
+            Some(seq)
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use embedded_hal::serial::{Read, Write};
+
+use crate::packet::{Encode, MAX_BODY_LEN, MAX_PACKET_LEN};
+use crate::{Frame, Packet};
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ParseError {
+    /// Length field bigger than `MAX_PAYLOAD`, or a packet that never ends
+    BadLength(u8),
+    BadChecksum,
+    /// Bytes between the delimiters that are not valid for the framing
+    BadEncoding,
+    /// Checksum was fine but the tag or payload did not decode
+    UnknownPacket(u8),
+}
+
+/// How packets are delimited on a link. Every framing is also the parser for
+/// its own wire format, pick one per link by choosing the type.
+pub trait Framing: Default {
+    /// Wraps an encoded packet for the wire, returns the number of bytes
+    /// written. `buf` has to hold `MAX_PACKET_LEN`.
+    fn frame(body: &[u8], buf: &mut [u8]) -> usize;
+
+    /// Returns a packet when `byte` completes one
+    fn push(&mut self, byte: u8) -> Result<Option<Packet>, ParseError>;
+
+    /// Returns a packet that was already complete in the bytes pushed so
+    /// far. Call it after every packet until it returns None, or the packet
+    /// waits for the next byte.
+    fn pending(&mut self) -> Option<Packet> {
+        None
+    }
+
+    /// Number of parse errors seen so far
+    fn errors(&self) -> u32;
+
+    fn write<E: Encode>(msg: &E, buf: &mut [u8]) -> usize {
+        let mut body = [0; MAX_BODY_LEN];
+        let len = msg.encode(&mut body);
+        Self::frame(&body[..len], buf)
+    }
+
+    /// Blocks until the whole packet is written
+    fn send<E: Encode, W: Write<u8>>(msg: &E, writer: &mut W) {
+        let mut buf = [0; MAX_PACKET_LEN];
+        let len = Self::write(msg, &mut buf);
+        for b in &buf[..len] {
+            nb::block!(writer.write(*b)).ok();
+        }
+        nb::block!(writer.flush()).ok();
+    }
+
+    /// Reads the first packet in `buf`
+    fn read(buf: &[u8]) -> Option<Packet> {
+        let mut parser = Self::default();
+        buf.iter().filter_map(|b| parser.feed_packet(*b)).next()
+    }
+
+    fn feed(&mut self, byte: u8) -> Option<Frame> {
+        Frame::from_packet(&self.feed_packet(byte)?)
+    }
+
+    fn recv<R: Read<u8>>(&mut self, reader: &mut R) -> Option<Frame> {
+        match reader.read() {
+            Ok(x) => self.feed(x),
+            _ => None
+        }
+    }
+
+    fn feed_packet(&mut self, byte: u8) -> Option<Packet> {
+        self.push(byte).ok().and_then(|p| p)
+    }
+
+    /// Packets already complete come first, so calling it until it returns
+    /// None drains them
+    fn recv_packet<R: Read<u8>>(&mut self, reader: &mut R) -> Option<Packet> {
+        if let Some(packet) = self.pending() {
+            return Some(packet);
+        }
+        match reader.read() {
+            Ok(x) => self.feed_packet(x),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::ResetReason;
+
+/// What a node is for, every kind runs its own firmware
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum NodeKind {
+    LeftDriver,
+    RightDriver,
+    Steering,
+}
+
+impl NodeKind {
+    pub(crate) fn code(self) -> u8 {
+        match self {
+            NodeKind::LeftDriver => 1,
+            NodeKind::RightDriver => 2,
+            NodeKind::Steering => 3,
+        }
+    }
+
+    pub(crate) fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(NodeKind::LeftDriver),
+            2 => Some(NodeKind::RightDriver),
+            3 => Some(NodeKind::Steering),
+            _ => None,
+        }
+    }
+}
+
+/// Firmware version, the version of the firmware crate
+#[derive(Eq, PartialEq, Ord, PartialOrd, Debug, Clone, Copy)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl Version {
+    /// Parses `major.minor.patch` as in `env!("CARGO_PKG_VERSION")`, any pre
+    /// release or build suffix is ignored
+    pub fn parse(s: &str) -> Option<Version> {
+        let s = s.split(['-', '+']).next()?;
+        let mut parts = s.split('.').map(|p| p.parse().ok());
+        let version = Version {
+            major: parts.next()??,
+            minor: parts.next()??,
+            patch: parts.next()??,
+        };
+        match parts.next() {
+            None => Some(version),
+            Some(_) => None,
+        }
+    }
+
+    /// True if firmware of this version can stand in for `min`, the same
+    /// major version and no older. Below 1.0 the minor version has to match
+    /// too.
+    pub fn supports(self, min: Version) -> bool {
+        self.major == min.major && (self.major > 0 || self.minor == min.minor) && self >= min
+    }
+}
+
+/// Parses the commit the firmware was built from, as hex. Returns 0 when it
+/// is not known.
+pub fn git_hash(s: &str) -> u32 {
+    let s = s.get(..8).unwrap_or(s);
+    u32::from_str_radix(s, 16).unwrap_or(0)
+}
+
+/// Sent by a node when asked who it is
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Identity {
+    pub kind: NodeKind,
+    /// Address of the node
+    pub id: u8,
+    pub version: Version,
+    /// First 8 hex digits of the commit, 0 if not known
+    pub git_hash: u32,
+    /// Bumped whenever the calibration built into the firmware changes
+    pub calibration: u16,
+    /// Seconds since the node started
+    pub uptime: u32,
+    /// Why it started
+    pub reset: ResetReason,
+}
@@ -1,15 +1,77 @@
 #![no_std]
 
-use byteorder::{ByteOrder, LE};
-use embedded_hal::serial::{Read, Write};
+use embedded_hal::serial::Write;
 
 use core::ops::{Add, Div, Sub, Mul};
 
+// The pins of embedded-hal 0.2 are its deprecated v1 digital traits, which
+// the HAL still implements. Only the modules marked use them.
+#[allow(deprecated)]
 mod actuator;
 mod adc;
-
-pub use actuator::{Actuator};
+mod baud;
+mod bindings;
+#[allow(deprecated)]
+mod bus;
+#[allow(deprecated)]
+mod button;
+mod capture;
+mod clock;
+mod cobs;
+mod controls;
+mod cruise;
+mod fault_log;
+mod flash_region;
+mod flash_ring;
+mod framing;
+mod identity;
+mod packet;
+mod panic_log;
+mod parser;
+mod protocol;
+mod queue;
+#[allow(deprecated)]
+mod relays;
+#[allow(deprecated)]
+mod stepper;
+#[allow(deprecated)]
+mod stepper_controller;
+#[cfg(feature = "stm32f103")]
+pub mod stm32f103;
+mod tx_queue;
+mod usage;
+mod watchdog;
+
+pub use actuator::{Actuator, ActuatorConfig, End, Limit, NoPin, Polarity, DEBOUNCE_TICKS, FULL_TRAVEL, STALL_TICKS};
 pub use adc::{Adc, RefAdc};
+pub use baud::{Autobaud, BAUD_RATES, DEFAULT_BAUD};
+pub use bindings::{Action, Binding, BINDINGS};
+pub use bus::{BusMaster, HalfDuplex, BROADCAST, HOST, MASTER, MAX_NODE, REPLY_TICKS};
+pub use button::{Button, Event, HOLD_TICKS};
+pub use capture::{
+    write_capture_header, CaptureError, CaptureReader, Direction, Record, CAPTURE_HEADER_LEN,
+    CAPTURE_MAGIC, CAPTURE_VERSION, MAX_RECORD_LEN,
+};
+pub use clock::{Clock, RESTART_MS};
+pub use cobs::CobsParser;
+pub use controls::{Controls, Mode, Side};
+pub use cruise::CruiseHold;
+pub use fault_log::{FaultKind, FaultLog, FaultRecord, PendingFaults, FAULT_QUEUE_LEN, FAULT_RECORD_LEN};
+pub use flash_region::{FlashRegion, FlashRegisters};
+pub use flash_ring::{Flash, FlashError};
+pub use framing::{Framing, ParseError};
+pub use identity::{git_hash, Identity, NodeKind, Version};
+pub use packet::{Encode, RawPacket, MAX_BODY_LEN, MAX_PACKET_LEN, MAX_PAYLOAD};
+pub use panic_log::{PanicLog, PanicText, PANIC_TEXT_LEN};
+pub use parser::{FrameParser, PREAMBLE};
+pub use protocol::{Fault, Message, Packet};
+pub use queue::Queue;
+pub use relays::{EngineRelays, RelayState};
+pub use stepper::{tick_rate, Stepper, StepperCommand, StepperConfig, StepperState, StepperStatus};
+pub use stepper_controller::{Lock, StepperController, COMMAND_QUEUE_LEN};
+pub use tx_queue::{Full, TxQueue, TX_QUEUE_LEN};
+pub use usage::{Usage, UsageMeter, UsageStore, USAGE_LEN};
+pub use watchdog::{feed_watchdog, start_watchdog, CheckIns, Iwdg, ResetReason};
 
 pub fn remap<T: Copy + Add<T, Output = T> + Sub<T, Output = T> + Mul<T, Output = T> + Div<T, Output = T>>(val: T, in_l: T, in_h: T, out_l: T, out_h: T) -> T {
     ((val - in_l) * (out_h - out_l) / (in_h - in_l)) + out_l
@@ -34,109 +96,100 @@ impl MotorState {
     }
 }
 
+/// Shorthand for a packet carrying `Message::Drive`
 #[derive(Eq, PartialEq, Debug, Clone, Copy)]
 pub struct Frame {
+    /// Node the frame is for, it always comes from `MASTER`
     pub id: u8,
+    /// Incremented by the controller for every round of frames, lets a driver
+    /// drop the copies it gets when frames are broadcast on several links
+    pub seq: u8,
     pub motor_state: MotorState,
     pub motor_direction: u8,
 }
 
-impl Frame {
-    pub fn write(&self, buf: &mut [u8]) {
-        let buf = &mut buf[0..12];
-        buf[0] = 0xa3;
-        buf[1] = 0xc9;
-        buf[2] = 0x3d;
-        buf[3] = self.id;
-        match self.motor_state {
-            MotorState::Idle(p) => {
-                buf[4] = 2;
-                buf[5] = p;
-            }
-            MotorState::Fwd(p) => {
-                buf[4] = 3;
-                buf[5] = p;
-            }
-            MotorState::Rev(p) => {
-                buf[4] = 4;
-                buf[5] = p;
-            }
-        };
-        buf[6] = self.motor_direction;
-
-        buf[7] = buf[3];
-        buf[8] = buf[4];
-        buf[9] = buf[5];
-        buf[10] = buf[6];
-        buf[11] = 0x65;
+impl Encode for Frame {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        self.packet().encode(buf)
     }
+}
 
-    pub fn read(buf: &[u8]) -> Option<Self> {
-        let buf = &buf[0..12];
-        if &buf[0..3] != &[0xa3, 0xc9, 0x3d] {
-            return None;
-        }
-        let id = buf[3];
-        let motor_state = match buf[4] {
-            2 => MotorState::Idle(buf[5]),
-            3 => MotorState::Fwd(buf[5]),
-            4 => MotorState::Rev(buf[5]),
-            _ => return None,
-        };
-        let motor_direction = buf[6];
-        if &buf[3..7] != &buf[7..11] {
-            return None;
+impl Frame {
+    pub fn packet(&self) -> Packet {
+        Packet {
+            dst: self.id,
+            src: MASTER,
+            seq: self.seq,
+            message: Message::Drive {
+                motor_state: self.motor_state,
+                direction: self.motor_direction,
+            },
         }
-        if buf[11] != 0x65 {
-            return None;
+    }
+
+    pub fn from_packet(packet: &Packet) -> Option<Self> {
+        match packet.message {
+            Message::Drive { motor_state, direction } => Some(Frame {
+                id: packet.dst,
+                seq: packet.seq,
+                motor_state,
+                motor_direction: direction,
+            }),
+            _ => None,
         }
+    }
 
-        Some(Frame {
-            id,
-            motor_state,
-            motor_direction,
-        })
+    /// Writes the frame with the preamble framing, returns the number of bytes
+    /// written. `buf` has to hold `MAX_PACKET_LEN`
+    pub fn write(&self, buf: &mut [u8]) -> usize {
+        FrameParser::write(self, buf)
+    }
+
+    pub fn read(buf: &[u8]) -> Option<Self> {
+        Self::from_packet(&Packet::read(buf)?)
     }
 
     pub fn send<W: Write<u8>>(&self, writer: &mut W) {
-        let mut buf: [u8; 12] = [0; 12];
-        self.write(&mut buf);
-        for b in &buf {
-            nb::block!(writer.write(*b)).ok();
-        }
+        FrameParser::send(self, writer);
     }
 }
 
-pub struct FrameParser {
-    circ_buf: [u8; 12],
-    i: u8,
+/// A sequence number this far behind the last one is not a late copy, the
+/// controller started counting again
+const DEDUP_WINDOW: i8 = 16;
+
+/// Drops packets that have already been seen on another link
+pub struct Dedup {
+    last_seq: Option<u8>,
 }
 
-impl FrameParser {
-    pub const fn new() -> FrameParser {
-        FrameParser {
-            circ_buf: [0; 12],
-            i: 0,
+impl Dedup {
+    pub const fn new() -> Dedup {
+        Dedup {
+            last_seq: None,
         }
     }
 
-    pub fn feed(&mut self, byte: u8) -> Option<Frame> {
-        self.circ_buf[self.i as usize] = byte;
-        self.i = (self.i + 1) % 12;
-
-        let mut copy = [0; 12];
-        for j in 0..12 {
-            copy[j] = self.circ_buf[(self.i as usize + j) % 12];
+    /// Returns true for a sequence number newer than any seen so far. The
+    /// numbers wrap, so newer is up to half way round ahead.
+    pub fn accept(&mut self, packet: &Packet) -> bool {
+        let newer = match self.last_seq {
+            Some(last) => {
+                let ahead = packet.seq.wrapping_sub(last) as i8;
+                !(-DEDUP_WINDOW..=0).contains(&ahead)
+            }
+            None => true,
+        };
+        if newer {
+            self.last_seq = Some(packet.seq);
         }
-
-        Frame::read(&copy)
+        newer
     }
+}
 
-    pub fn recv<R: Read<u8>>(&mut self, reader: &mut R) -> Option<Frame> {
-        match reader.read() {
-            Ok(x) => self.feed(x),
-            _ => None
-        }
+impl Default for Dedup {
+    fn default() -> Dedup {
+        Dedup::new()
     }
 }
 
@@ -0,0 +1,93 @@
+/* *****************
+ * Packet layout
+ * *****************
+ *
+ * dst:      u8, node the packet is for, see bus.rs
+ * src:      u8, node that sent it
+ * seq:      u8
+ * tag:      u8, what kind of packet this is
+ * len:      u8, length of the payload
+ * payload:  len bytes, at most MAX_PAYLOAD
+ * crc:      u16 little endian, CRC-16/CCITT of dst up to the end of payload
+ *
+ * How a packet is delimited on the wire is up to the framing, see framing.rs
+ */
+
+pub const HEADER_LEN: usize = 5;
+pub const MAX_PAYLOAD: usize = 32;
+pub const MAX_BODY_LEN: usize = HEADER_LEN + MAX_PAYLOAD + 2;
+/// Longest packet on the wire with any of the framings
+pub const MAX_PACKET_LEN: usize = MAX_BODY_LEN + 3;
+
+/// A packet that passed the checksum but has not been decoded yet
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct RawPacket<'a> {
+    pub dst: u8,
+    pub src: u8,
+    pub seq: u8,
+    pub tag: u8,
+    pub payload: &'a [u8],
+}
+
+/// Anything that can be sent as a packet
+pub trait Encode {
+    /// Returns the number of bytes written, `buf` has to hold `MAX_BODY_LEN`
+    fn encode(&self, buf: &mut [u8]) -> usize;
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for b in data {
+        crc ^= (*b as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            }
+            else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+impl<'a> RawPacket<'a> {
+    /// Returns the number of bytes written, `buf` has to hold `MAX_BODY_LEN`
+    pub fn write(&self, buf: &mut [u8]) -> usize {
+        let len = self.payload.len();
+        let end = HEADER_LEN + len;
+        buf[0] = self.dst;
+        buf[1] = self.src;
+        buf[2] = self.seq;
+        buf[3] = self.tag;
+        buf[4] = len as u8;
+        buf[HEADER_LEN..end].copy_from_slice(self.payload);
+        let crc = crc16(&buf[0..end]);
+        buf[end] = crc as u8;
+        buf[end + 1] = (crc >> 8) as u8;
+        end + 2
+    }
+
+    /// Reads a packet that fills all of `buf`
+    pub fn read(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < HEADER_LEN + 2 {
+            return None;
+        }
+        let len = buf[4] as usize;
+        let end = HEADER_LEN + len;
+        if len > MAX_PAYLOAD || buf.len() != end + 2 {
+            return None;
+        }
+        let crc = buf[end] as u16 | (buf[end + 1] as u16) << 8;
+        if crc != crc16(&buf[0..end]) {
+            return None;
+        }
+        Some(RawPacket {
+            dst: buf[0],
+            src: buf[1],
+            seq: buf[2],
+            tag: buf[3],
+            payload: &buf[HEADER_LEN..end],
+        })
+    }
+}
@@ -0,0 +1,135 @@
+use core::fmt::{self, Display, Write};
+use core::panic::{Location, PanicInfo};
+use core::str;
+
+use crate::MAX_PAYLOAD;
+
+/// Bytes of a panic message that are kept, as much as fits in a packet next
+/// to the length
+pub const PANIC_TEXT_LEN: usize = MAX_PAYLOAD - 1;
+
+/// Marks a `PanicLog` that holds a message, anything else is left over RAM
+const MAGIC: u32 = 0x5041_4e43;
+
+/// The start of a panic message, longer messages are cut
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct PanicText {
+    len: u8,
+    buf: [u8; PANIC_TEXT_LEN],
+}
+
+impl PanicText {
+    pub const fn empty() -> PanicText {
+        PanicText {
+            len: 0,
+            buf: [0; PANIC_TEXT_LEN],
+        }
+    }
+
+    pub fn new(s: &str) -> PanicText {
+        let mut text = PanicText::empty();
+        text.write_str(s).ok();
+        text
+    }
+
+    pub fn as_str(&self) -> &str {
+        let bytes = &self.buf[..(self.len as usize).min(PANIC_TEXT_LEN)];
+        match str::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(e) => str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Length byte followed by `PANIC_TEXT_LEN` bytes
+    pub(crate) fn write(&self, buf: &mut [u8]) {
+        buf[0] = self.len;
+        buf[1..=PANIC_TEXT_LEN].copy_from_slice(&self.buf);
+    }
+
+    pub(crate) fn read(buf: &[u8]) -> Option<PanicText> {
+        if buf[0] as usize > PANIC_TEXT_LEN {
+            return None;
+        }
+        let mut text = PanicText::empty();
+        text.len = buf[0];
+        text.buf.copy_from_slice(&buf[1..=PANIC_TEXT_LEN]);
+        Some(text)
+    }
+}
+
+impl Write for PanicText {
+    /// Appends what fits, never cutting a character in half
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = self.len as usize;
+        let mut n = s.len().min(PANIC_TEXT_LEN - len);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.buf[len..len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n as u8;
+        Ok(())
+    }
+}
+
+/// Keeps a panic message over a reset. Meant to live in RAM the runtime
+/// does not initialise, so it is only trusted once `record` marked it.
+#[repr(C)]
+pub struct PanicLog {
+    magic: u32,
+    text: PanicText,
+}
+
+impl PanicLog {
+    pub const fn new() -> PanicLog {
+        PanicLog {
+            magic: 0,
+            text: PanicText::empty(),
+        }
+    }
+}
+
+impl Default for PanicLog {
+    fn default() -> PanicLog {
+        PanicLog::new()
+    }
+}
+
+impl PanicLog {
+    /// Call from the panic handler with the `PanicInfo`
+    pub fn record<D: Display>(&mut self, message: D) {
+        self.text = PanicText::empty();
+        write!(self.text, "{}", message).ok();
+        self.magic = MAGIC;
+    }
+
+    /// The message first, so a long file name does not push it out, and
+    /// what fits of where it came from after it
+    pub fn record_at<D: Display>(&mut self, message: D, location: Option<&Location>) {
+        match location {
+            Some(location) => self.record(format_args!("{} at {}", message, location)),
+            None => self.record(message),
+        }
+    }
+
+    /// Call from the panic handler. Nothing in here panics again.
+    pub fn record_panic(&mut self, info: &PanicInfo) {
+        self.record_at(info.message(), info.location());
+    }
+
+    /// The message recorded before the last reset, if there was a panic.
+    /// It is only returned once.
+    pub fn take(&mut self) -> Option<PanicText> {
+        let valid = self.magic == MAGIC && self.text.len as usize <= PANIC_TEXT_LEN;
+        self.magic = 0;
+        if valid {
+            Some(self.text)
+        }
+        else {
+            None
+        }
+    }
+}
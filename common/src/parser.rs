@@ -0,0 +1,196 @@
+use crate::framing::{Framing, ParseError};
+use crate::packet::{crc16, RawPacket, HEADER_LEN, MAX_PACKET_LEN, MAX_PAYLOAD};
+use crate::Packet;
+
+/// Framing that marks the start of a packet with a preamble, the crc tells
+/// where it ends
+pub const PREAMBLE: [u8; 3] = [0xa3, 0xc9, 0x3d];
+
+/// Preamble and header, the payload length is the last byte
+const HEADER_END: usize = 3 + HEADER_LEN;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+enum State {
+    Sync,
+    Header,
+    Payload,
+    Checksum,
+}
+
+/// Room for the bytes of a failed packet plus whatever was still waiting
+const BACKLOG_LEN: usize = 2 * MAX_PACKET_LEN;
+
+/// Parser for the preamble framing, fed one byte at a time from a uart
+/// interrupt
+pub struct FrameParser {
+    state: State,
+    buf: [u8; MAX_PACKET_LEN],
+    n: usize,
+    /// Bytes that still have to go through the state machine, after an error
+    /// the real start of a packet may be hidden in the bytes of the failed one
+    backlog: [u8; BACKLOG_LEN],
+    backlog_len: usize,
+    errors: u32,
+}
+
+impl FrameParser {
+    pub const fn new() -> FrameParser {
+        FrameParser {
+            state: State::Sync,
+            buf: [0; MAX_PACKET_LEN],
+            n: 0,
+            backlog: [0; BACKLOG_LEN],
+            backlog_len: 0,
+            errors: 0,
+        }
+    }
+
+    /// An error is only returned when no packet was found after it
+    fn parse(&mut self, byte: u8) -> Result<Option<Packet>, ParseError> {
+        if self.backlog_len == BACKLOG_LEN {
+            // Only happens on a stream of errors, drop the oldest byte
+            self.backlog.copy_within(1.., 0);
+            self.backlog_len -= 1;
+        }
+        self.backlog[self.backlog_len] = byte;
+        self.backlog_len += 1;
+        self.run()
+    }
+
+    /// Runs the backlog through the state machine until a packet completes.
+    /// The bytes after it stay in the backlog for `pending`.
+    fn run(&mut self) -> Result<Option<Packet>, ParseError> {
+        let mut result = Ok(None);
+        let mut i = 0;
+        while i < self.backlog_len {
+            let b = self.backlog[i];
+            i += 1;
+            match self.step(b) {
+                Ok(Some(len)) => {
+                    self.backlog.copy_within(i..self.backlog_len, 0);
+                    self.backlog_len -= i;
+                    return self.decode(len);
+                }
+                Ok(None) => (),
+                Err(e) => {
+                    self.errors = self.errors.wrapping_add(1);
+                    result = Err(e);
+                    // Run everything but the first byte of the failed packet
+                    // through again
+                    let mut backlog = [0; BACKLOG_LEN];
+                    let failed = self.n - 1;
+                    let rest = self.backlog_len - i;
+                    let failed = failed.min(BACKLOG_LEN - rest);
+                    backlog[..failed].copy_from_slice(&self.buf[self.n - failed..self.n]);
+                    backlog[failed..failed + rest].copy_from_slice(&self.backlog[i..self.backlog_len]);
+                    self.backlog = backlog;
+                    self.backlog_len = failed + rest;
+                    i = 0;
+                    self.reset();
+                }
+            }
+        }
+        self.backlog_len = 0;
+        result
+    }
+
+    fn decode(&mut self, len: usize) -> Result<Option<Packet>, ParseError> {
+        self.reset();
+        let raw = RawPacket::read(&self.buf[PREAMBLE.len()..len]).ok_or(ParseError::BadChecksum)?;
+        match Packet::from_raw(&raw) {
+            Some(packet) => Ok(Some(packet)),
+            None => {
+                self.errors = self.errors.wrapping_add(1);
+                Err(ParseError::UnknownPacket(raw.tag))
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = State::Sync;
+        self.n = 0;
+    }
+
+    /// Returns the length of the packet in `buf` when it is complete
+    fn step(&mut self, byte: u8) -> Result<Option<usize>, ParseError> {
+        match self.state {
+            State::Sync => {
+                if byte == PREAMBLE[self.n] {
+                    self.buf[self.n] = byte;
+                    self.n += 1;
+                    if self.n == PREAMBLE.len() {
+                        self.state = State::Header;
+                    }
+                }
+                else if byte == PREAMBLE[0] {
+                    self.buf[0] = byte;
+                    self.n = 1;
+                }
+                else {
+                    self.n = 0;
+                }
+                Ok(None)
+            }
+            State::Header => {
+                self.buf[self.n] = byte;
+                self.n += 1;
+                if self.n == HEADER_END {
+                    if byte as usize > MAX_PAYLOAD {
+                        return Err(ParseError::BadLength(byte));
+                    }
+                    self.state = if byte == 0 { State::Checksum } else { State::Payload };
+                }
+                Ok(None)
+            }
+            State::Payload => {
+                self.buf[self.n] = byte;
+                self.n += 1;
+                if self.n == HEADER_END + self.buf[HEADER_END - 1] as usize {
+                    self.state = State::Checksum;
+                }
+                Ok(None)
+            }
+            State::Checksum => {
+                self.buf[self.n] = byte;
+                self.n += 1;
+                let end = HEADER_END + self.buf[HEADER_END - 1] as usize;
+                if self.n < end + 2 {
+                    return Ok(None);
+                }
+                let crc = self.buf[end] as u16 | (self.buf[end + 1] as u16) << 8;
+                if crc != crc16(&self.buf[PREAMBLE.len()..end]) {
+                    return Err(ParseError::BadChecksum);
+                }
+                Ok(Some(self.n))
+            }
+        }
+    }
+}
+
+impl Default for FrameParser {
+    fn default() -> Self {
+        FrameParser::new()
+    }
+}
+
+impl Framing for FrameParser {
+    fn frame(body: &[u8], buf: &mut [u8]) -> usize {
+        buf[0..PREAMBLE.len()].copy_from_slice(&PREAMBLE);
+        buf[PREAMBLE.len()..PREAMBLE.len() + body.len()].copy_from_slice(body);
+        PREAMBLE.len() + body.len()
+    }
+
+    fn push(&mut self, byte: u8) -> Result<Option<Packet>, ParseError> {
+        self.parse(byte)
+    }
+
+    /// After an error the bytes of the failed packet can hold more than one
+    /// packet, `push` returns the first
+    fn pending(&mut self) -> Option<Packet> {
+        self.run().ok().and_then(|p| p)
+    }
+
+    fn errors(&self) -> u32 {
+        self.errors
+    }
+}
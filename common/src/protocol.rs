@@ -0,0 +1,467 @@
+use byteorder::{ByteOrder, LE};
+
+use crate::framing::Framing;
+use crate::packet::{Encode, RawPacket, MAX_PAYLOAD};
+use crate::bus::{BROADCAST, MASTER};
+use crate::{
+    FaultRecord, FrameParser, Identity, MotorState, NodeKind, PanicText, ResetReason, StepperState, Usage,
+    Version, FAULT_RECORD_LEN, PANIC_TEXT_LEN, USAGE_LEN,
+};
+
+/* *****************
+ * Message tags
+ * *****************
+ *
+ * The tag picks the message, the payload layout is given next to each one.
+ * Numbers are little endian, a motor state is [kind, power] with kind 0 for
+ * idle, 1 for forward and 2 for reverse. A stepper state is 0 unzeroed,
+ * 1 zeroing, 2 ready and 3 alarm. A node kind is 1 for the left driver,
+ * 2 for the right driver and 3 for the steering node. A reset reason is
+ * 0 unknown, 1 power on, 2 pin, 3 watchdog, 4 software and 5 low power.
+ * A fault record is [fault kind, u32 time, i32 context] with the kind 1 for
+ * a stall, 2 stepper alarm, 3 link lost, 4 e-stop, 5 watchdog reset and
+ * 6 panic.
+ */
+
+/// [motor state, direction]
+const TAG_DRIVE: u8 = 0x01;
+/// empty
+const TAG_ESTOP: u8 = 0x02;
+/// empty
+const TAG_ENGINE_START: u8 = 0x03;
+/// empty
+const TAG_ENGINE_STOP: u8 = 0x04;
+/// empty
+const TAG_CALIBRATE: u8 = 0x05;
+/// empty
+const TAG_STEER_ZERO: u8 = 0x08;
+/// [i32 position]
+const TAG_STEER_GOTO: u8 = 0x09;
+/// [u32 baud]
+const TAG_BAUD_PROPOSE: u8 = 0x10;
+/// [u32 baud]
+const TAG_BAUD_ACK: u8 = 0x11;
+/// [u32 time]
+const TAG_TIME_SYNC: u8 = 0x12;
+/// [motor state, i32 steering, u32 time]
+const TAG_TELEMETRY: u8 = 0x20;
+/// [stepper state, i32 position, u32 time]
+const TAG_STEER_STATUS: u8 = 0x21;
+/// [fault]
+const TAG_ERROR: u8 = 0x28;
+/// empty
+const TAG_PING: u8 = 0x30;
+/// empty
+const TAG_PONG: u8 = 0x31;
+/// empty
+const TAG_IDENTIFY: u8 = 0x32;
+/// [node kind, id, major, minor, patch, u32 git hash, u16 calibration,
+/// u32 uptime, reset reason]
+const TAG_IDENTITY: u8 = 0x33;
+/// empty
+const TAG_GET_PANIC: u8 = 0x34;
+/// [len, PANIC_TEXT_LEN bytes of utf-8]
+const TAG_PANIC: u8 = 0x35;
+/// [index]
+const TAG_GET_FAULT: u8 = 0x36;
+/// [index, 1 if there is a record else 0, fault record or zeros]
+const TAG_LOGGED_FAULT: u8 = 0x37;
+/// empty
+const TAG_CLEAR_FAULTS: u8 = 0x38;
+/// empty
+const TAG_GET_USAGE: u8 = 0x39;
+/// [u32 run seconds, u32 starts, u32 shifts, u32 steps]
+const TAG_USAGE: u8 = 0x3a;
+
+/// Everything that can be sent between the controller and the nodes
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Message {
+    /// Throttle and gear for an engine, `direction` is the steering lever
+    Drive { motor_state: MotorState, direction: u8 },
+    /// Idles the engine and stops the steering where it is
+    EStop,
+    EngineStart,
+    EngineStop,
+    /// Runs the actuators end to end to find their limits
+    Calibrate,
+    /// Runs the steering to the left limit and calls that zero
+    SteerZero,
+    /// Steering position in steps from zero
+    SteerGoto(i32),
+    /// Sent by the controller to ask the nodes on a link to switch speed
+    BaudPropose(u32),
+    /// The node acknowledges at the old speed before switching
+    BaudAck(u32),
+    /// Broadcast by the controller with its system time, see clock.rs
+    TimeSync(u32),
+    /// State reported back by a node, `time` is the system time it was
+    /// taken at and `reset` why the node last started. `dropped` counts the
+    /// stepper commands and faults it had no room to queue since then.
+    Telemetry { motor_state: MotorState, steering: i32, time: u32, reset: ResetReason, dropped: u16 },
+    /// Reported back by a steering node, `position` in steps from zero at
+    /// system time `time`
+    SteerStatus { state: StepperState, position: i32, time: u32, reset: ResetReason, dropped: u16 },
+    Error(Fault),
+    /// Sent by the controller to find the nodes on a bus
+    Ping,
+    /// Answer to `Ping`
+    Pong,
+    /// Asks a node who it is
+    Identify,
+    /// Answer to `Identify`
+    Identity(Identity),
+    /// Asks a node how it last panicked
+    GetPanic,
+    /// Answer to `GetPanic`, the start of the message of the panic before
+    /// the last reset. Empty if there was none.
+    Panic(PanicText),
+    /// Asks a node for a record from its fault log, 0 is the newest
+    GetFault(u8),
+    /// Answer to `GetFault`, None past the oldest record
+    LoggedFault { index: u8, record: Option<FaultRecord> },
+    /// Empties the fault log of a node, answered with the same
+    ClearFaults,
+    /// Asks a driver for its usage counters
+    GetUsage,
+    /// Answer to `GetUsage`
+    Usage(Usage),
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Fault {
+    /// The stepper driver raised its alarm line
+    StepperAlarm,
+    /// A limit switch was hit while moving
+    Limit,
+    /// Nothing decoded on any link for too long
+    LinkLost,
+}
+
+impl Fault {
+    fn code(self) -> u8 {
+        match self {
+            Fault::StepperAlarm => 1,
+            Fault::Limit => 2,
+            Fault::LinkLost => 3,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Fault::StepperAlarm),
+            2 => Some(Fault::Limit),
+            3 => Some(Fault::LinkLost),
+            _ => None,
+        }
+    }
+}
+
+fn write_motor_state(state: MotorState, buf: &mut [u8]) {
+    let (kind, p) = match state {
+        MotorState::Idle(p) => (0, p),
+        MotorState::Fwd(p) => (1, p),
+        MotorState::Rev(p) => (2, p),
+    };
+    buf[0] = kind;
+    buf[1] = p;
+}
+
+fn read_motor_state(buf: &[u8]) -> Option<MotorState> {
+    match buf[0] {
+        0 => Some(MotorState::Idle(buf[1])),
+        1 => Some(MotorState::Fwd(buf[1])),
+        2 => Some(MotorState::Rev(buf[1])),
+        _ => None,
+    }
+}
+
+fn write_stepper_state(state: StepperState) -> u8 {
+    match state {
+        StepperState::Unzeroed => 0,
+        StepperState::Zeroing => 1,
+        StepperState::Ready => 2,
+        StepperState::Alarm => 3,
+    }
+}
+
+fn read_stepper_state(b: u8) -> Option<StepperState> {
+    match b {
+        0 => Some(StepperState::Unzeroed),
+        1 => Some(StepperState::Zeroing),
+        2 => Some(StepperState::Ready),
+        3 => Some(StepperState::Alarm),
+        _ => None,
+    }
+}
+
+impl Message {
+    pub fn tag(&self) -> u8 {
+        match self {
+            Message::Drive { .. } => TAG_DRIVE,
+            Message::EStop => TAG_ESTOP,
+            Message::EngineStart => TAG_ENGINE_START,
+            Message::EngineStop => TAG_ENGINE_STOP,
+            Message::Calibrate => TAG_CALIBRATE,
+            Message::SteerZero => TAG_STEER_ZERO,
+            Message::SteerGoto(_) => TAG_STEER_GOTO,
+            Message::BaudPropose(_) => TAG_BAUD_PROPOSE,
+            Message::BaudAck(_) => TAG_BAUD_ACK,
+            Message::TimeSync(_) => TAG_TIME_SYNC,
+            Message::Telemetry { .. } => TAG_TELEMETRY,
+            Message::SteerStatus { .. } => TAG_STEER_STATUS,
+            Message::Error(_) => TAG_ERROR,
+            Message::Ping => TAG_PING,
+            Message::Pong => TAG_PONG,
+            Message::Identify => TAG_IDENTIFY,
+            Message::Identity(_) => TAG_IDENTITY,
+            Message::GetPanic => TAG_GET_PANIC,
+            Message::Panic(_) => TAG_PANIC,
+            Message::GetFault(_) => TAG_GET_FAULT,
+            Message::LoggedFault { .. } => TAG_LOGGED_FAULT,
+            Message::ClearFaults => TAG_CLEAR_FAULTS,
+            Message::GetUsage => TAG_GET_USAGE,
+            Message::Usage(_) => TAG_USAGE,
+        }
+    }
+
+    /// Writes the payload, returns its length. `buf` has to hold `MAX_PAYLOAD`
+    pub fn write(&self, buf: &mut [u8]) -> usize {
+        match *self {
+            Message::Drive { motor_state, direction } => {
+                write_motor_state(motor_state, buf);
+                buf[2] = direction;
+                3
+            }
+            Message::EStop
+            | Message::EngineStart
+            | Message::EngineStop
+            | Message::Calibrate
+            | Message::SteerZero
+            | Message::Ping
+            | Message::Pong
+            | Message::Identify
+            | Message::GetPanic
+            | Message::ClearFaults
+            | Message::GetUsage => 0,
+            Message::SteerGoto(pos) => {
+                LE::write_i32(&mut buf[0..4], pos);
+                4
+            }
+            Message::BaudPropose(baud) | Message::BaudAck(baud) => {
+                LE::write_u32(&mut buf[0..4], baud);
+                4
+            }
+            Message::TimeSync(time) => {
+                LE::write_u32(&mut buf[0..4], time);
+                4
+            }
+            Message::Telemetry { motor_state, steering, time, reset, dropped } => {
+                write_motor_state(motor_state, buf);
+                LE::write_i32(&mut buf[2..6], steering);
+                LE::write_u32(&mut buf[6..10], time);
+                buf[10] = reset.code();
+                LE::write_u16(&mut buf[11..13], dropped);
+                13
+            }
+            Message::SteerStatus { state, position, time, reset, dropped } => {
+                buf[0] = write_stepper_state(state);
+                LE::write_i32(&mut buf[1..5], position);
+                LE::write_u32(&mut buf[5..9], time);
+                buf[9] = reset.code();
+                LE::write_u16(&mut buf[10..12], dropped);
+                12
+            }
+            Message::Error(fault) => {
+                buf[0] = fault.code();
+                1
+            }
+            Message::Identity(identity) => {
+                buf[0] = identity.kind.code();
+                buf[1] = identity.id;
+                buf[2] = identity.version.major;
+                buf[3] = identity.version.minor;
+                buf[4] = identity.version.patch;
+                LE::write_u32(&mut buf[5..9], identity.git_hash);
+                LE::write_u16(&mut buf[9..11], identity.calibration);
+                LE::write_u32(&mut buf[11..15], identity.uptime);
+                buf[15] = identity.reset.code();
+                16
+            }
+            Message::Panic(text) => {
+                text.write(buf);
+                PANIC_TEXT_LEN + 1
+            }
+            Message::GetFault(index) => {
+                buf[0] = index;
+                1
+            }
+            Message::LoggedFault { index, record } => {
+                buf[0] = index;
+                match record {
+                    Some(record) => {
+                        buf[1] = 1;
+                        record.write(&mut buf[2..]);
+                    }
+                    None => {
+                        buf[1] = 0;
+                        for b in &mut buf[2..2 + FAULT_RECORD_LEN] {
+                            *b = 0;
+                        }
+                    }
+                }
+                FAULT_RECORD_LEN + 2
+            }
+            Message::Usage(usage) => {
+                usage.write(buf);
+                USAGE_LEN
+            }
+        }
+    }
+
+    /// Returns None for an unknown tag or a payload of the wrong length
+    pub fn read(tag: u8, payload: &[u8]) -> Option<Self> {
+        let len = match tag {
+            TAG_DRIVE => 3,
+            TAG_ESTOP | TAG_ENGINE_START | TAG_ENGINE_STOP | TAG_CALIBRATE | TAG_STEER_ZERO | TAG_PING
+            | TAG_PONG | TAG_IDENTIFY | TAG_GET_PANIC | TAG_CLEAR_FAULTS
+            | TAG_GET_USAGE => 0,
+            TAG_STEER_GOTO | TAG_BAUD_PROPOSE | TAG_BAUD_ACK | TAG_TIME_SYNC => 4,
+            TAG_STEER_STATUS => 12,
+            TAG_TELEMETRY => 13,
+            TAG_ERROR | TAG_GET_FAULT => 1,
+            TAG_IDENTITY => 16,
+            TAG_PANIC => PANIC_TEXT_LEN + 1,
+            TAG_LOGGED_FAULT => FAULT_RECORD_LEN + 2,
+            TAG_USAGE => USAGE_LEN,
+            _ => return None,
+        };
+        if payload.len() != len {
+            return None;
+        }
+
+        let msg = match tag {
+            TAG_DRIVE => Message::Drive {
+                motor_state: read_motor_state(payload)?,
+                direction: payload[2],
+            },
+            TAG_ESTOP => Message::EStop,
+            TAG_ENGINE_START => Message::EngineStart,
+            TAG_ENGINE_STOP => Message::EngineStop,
+            TAG_CALIBRATE => Message::Calibrate,
+            TAG_STEER_ZERO => Message::SteerZero,
+            TAG_PING => Message::Ping,
+            TAG_PONG => Message::Pong,
+            TAG_IDENTIFY => Message::Identify,
+            TAG_GET_PANIC => Message::GetPanic,
+            TAG_PANIC => Message::Panic(PanicText::read(payload)?),
+            TAG_GET_FAULT => Message::GetFault(payload[0]),
+            TAG_LOGGED_FAULT => Message::LoggedFault {
+                index: payload[0],
+                record: match payload[1] {
+                    0 => None,
+                    1 => Some(FaultRecord::read(&payload[2..])?),
+                    _ => return None,
+                },
+            },
+            TAG_CLEAR_FAULTS => Message::ClearFaults,
+            TAG_GET_USAGE => Message::GetUsage,
+            TAG_USAGE => Message::Usage(Usage::read(payload)),
+            TAG_STEER_GOTO => Message::SteerGoto(LE::read_i32(payload)),
+            TAG_BAUD_PROPOSE => Message::BaudPropose(LE::read_u32(payload)),
+            TAG_BAUD_ACK => Message::BaudAck(LE::read_u32(payload)),
+            TAG_TIME_SYNC => Message::TimeSync(LE::read_u32(payload)),
+            TAG_TELEMETRY => Message::Telemetry {
+                motor_state: read_motor_state(payload)?,
+                steering: LE::read_i32(&payload[2..6]),
+                time: LE::read_u32(&payload[6..10]),
+                reset: ResetReason::from_code(payload[10])?,
+                dropped: LE::read_u16(&payload[11..13]),
+            },
+            TAG_STEER_STATUS => Message::SteerStatus {
+                state: read_stepper_state(payload[0])?,
+                position: LE::read_i32(&payload[1..5]),
+                time: LE::read_u32(&payload[5..9]),
+                reset: ResetReason::from_code(payload[9])?,
+                dropped: LE::read_u16(&payload[10..12]),
+            },
+            TAG_IDENTITY => Message::Identity(Identity {
+                kind: NodeKind::from_code(payload[0])?,
+                id: payload[1],
+                version: Version {
+                    major: payload[2],
+                    minor: payload[3],
+                    patch: payload[4],
+                },
+                git_hash: LE::read_u32(&payload[5..9]),
+                calibration: LE::read_u16(&payload[9..11]),
+                uptime: LE::read_u32(&payload[11..15]),
+                reset: ResetReason::from_code(payload[15])?,
+            }),
+            _ => Message::Error(Fault::from_code(payload[0])?),
+        };
+        Some(msg)
+    }
+}
+
+/// A message with its addresses, see bus.rs
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Packet {
+    /// Node the packet is for, `BROADCAST` for every node
+    pub dst: u8,
+    /// Node that sent it, `MASTER` for the controller
+    pub src: u8,
+    /// Incremented by the controller for every round of packets, lets a node
+    /// drop the copies it gets when packets are broadcast on several links
+    pub seq: u8,
+    pub message: Message,
+}
+
+impl Packet {
+    pub fn read(buf: &[u8]) -> Option<Self> {
+        FrameParser::read(buf)
+    }
+
+    pub fn from_raw(raw: &RawPacket) -> Option<Self> {
+        Some(Packet {
+            dst: raw.dst,
+            src: raw.src,
+            seq: raw.seq,
+            message: Message::read(raw.tag, raw.payload)?,
+        })
+    }
+
+    /// True if a node with address `node` should act on the packet
+    pub fn is_for(&self, node: u8) -> bool {
+        self.dst == node || self.dst == BROADCAST
+    }
+
+    /// True if the packet has to be answered, broadcasts never are
+    pub fn wants_reply(&self) -> bool {
+        self.dst != BROADCAST && self.src == MASTER
+    }
+
+    /// Answer from `node` to this packet, with the same sequence number
+    pub fn reply(&self, node: u8, message: Message) -> Packet {
+        Packet {
+            dst: self.src,
+            src: node,
+            seq: self.seq,
+            message,
+        }
+    }
+}
+
+impl Encode for Packet {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        let mut payload = [0; MAX_PAYLOAD];
+        let len = self.message.write(&mut payload);
+        RawPacket {
+            dst: self.dst,
+            src: self.src,
+            seq: self.seq,
+            tag: self.message.tag(),
+            payload: &payload[..len],
+        }
+        .write(buf)
+    }
+}
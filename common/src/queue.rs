@@ -0,0 +1,75 @@
+//! Bounded first in, first out queue. It does no locking of its own, a queue
+//! shared between interrupts is only touched with them kept out, as
+//! `StepperController` and `PendingFaults` do with their `Lock`.
+
+use core::mem::MaybeUninit;
+
+/// Holds up to `N` values and counts the ones that did not fit
+pub struct Queue<T, const N: usize> {
+    /// Slots from `head` on for `len` values are written
+    buf: MaybeUninit<[T; N]>,
+    head: usize,
+    len: usize,
+    /// Values pushed while it was full
+    dropped: u32,
+}
+
+impl<T, const N: usize> Queue<T, N> {
+    pub const fn new() -> Queue<T, N> {
+        Queue {
+            buf: MaybeUninit::uninit(),
+            head: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl<T, const N: usize> Default for Queue<T, N> {
+    fn default() -> Queue<T, N> {
+        Queue::new()
+    }
+}
+
+impl<T: Copy, const N: usize> Queue<T, N> {
+    /// Gives the value back if the queue is full
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.len == N {
+            self.dropped = self.dropped.saturating_add(1);
+            return Err(value);
+        }
+        let slot = self.slot((self.head + self.len) % N);
+        // Nothing to drop in the slot, values are Copy
+        unsafe { slot.write(value) };
+        self.len += 1;
+        Ok(())
+    }
+
+    /// The oldest value
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let value = unsafe { self.slot(self.head).read() };
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Values pushed while the queue was full, since it was made
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    fn slot(&mut self, index: usize) -> *mut T {
+        (self.buf.as_mut_ptr() as *mut T).wrapping_add(index)
+    }
+}
@@ -0,0 +1,81 @@
+use embedded_hal::digital::OutputPin;
+
+/// The starter and stop relays of an engine. A relay closes for a fixed time
+/// when asked and opens by itself after, so a lost link never leaves the
+/// starter engaged.
+pub struct EngineRelays<S, T> {
+    start: S,
+    stop: T,
+    /// Ticks the starter is engaged for
+    crank_ticks: u16,
+    /// Ticks the stop relay is closed for
+    stop_ticks: u16,
+    /// Ticks left for the relay that is closed
+    left: u16,
+    state: RelayState,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum RelayState {
+    Open,
+    Cranking,
+    Stopping,
+}
+
+impl<S: OutputPin, T: OutputPin> EngineRelays<S, T> {
+    /// Times are counted in calls to `tick`
+    pub fn new(mut start: S, mut stop: T, crank_ticks: u16, stop_ticks: u16) -> Self {
+        start.set_low();
+        stop.set_low();
+        EngineRelays {
+            start,
+            stop,
+            crank_ticks,
+            stop_ticks,
+            left: 0,
+            state: RelayState::Open,
+        }
+    }
+
+    pub fn state(&self) -> RelayState {
+        self.state
+    }
+
+    /// Engages the starter. Returns false if a relay is already closed, a
+    /// start is not cut short or run into a stop.
+    pub fn start(&mut self) -> bool {
+        if self.state != RelayState::Open {
+            return false;
+        }
+        self.start.set_high();
+        self.left = self.crank_ticks;
+        self.state = RelayState::Cranking;
+        true
+    }
+
+    /// Closes the stop relay, letting go of the starter first
+    pub fn stop(&mut self) {
+        self.start.set_low();
+        self.stop.set_high();
+        self.left = self.stop_ticks;
+        self.state = RelayState::Stopping;
+    }
+
+    /// Opens both relays straight away
+    pub fn open(&mut self) {
+        self.start.set_low();
+        self.stop.set_low();
+        self.left = 0;
+        self.state = RelayState::Open;
+    }
+
+    pub fn tick(&mut self) {
+        if self.state == RelayState::Open {
+            return;
+        }
+        self.left = self.left.saturating_sub(1);
+        if self.left == 0 {
+            self.open();
+        }
+    }
+}
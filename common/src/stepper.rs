@@ -0,0 +1,205 @@
+//! Step and direction control of a stepper motor driver with a limit switch at
+//! each end of the travel. Positions are in steps counted from the left limit,
+//! which `StepperCommand::Zero` finds.
+//!
+//! The stepper does not own a timer, `tick` is called at the step rate and
+//! does half a step each time, see `tick_rate`.
+
+use embedded_hal::digital::{InputPin, OutputPin};
+
+use crate::actuator::{Limit, Polarity};
+
+/// How a stepper is wired
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct StepperConfig {
+    /// Steps from the left limit to the furthest right a move may go
+    pub travel: i32,
+    /// Level on `ena` that enables the motor driver
+    pub enable: Polarity,
+    /// `dir` high steps right, instead of left
+    pub invert_dir: bool,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum StepperCommand {
+    /// Finds the left limit and counts positions from there
+    Zero,
+    /// Moves to a position, clamped to the travel. Held until zeroed.
+    Goto(i32),
+    /// Holds the current position
+    Stop,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum StepperState {
+    /// Position unknown, nothing moves until zeroed
+    Unzeroed,
+    Zeroing,
+    /// Following the target
+    Ready,
+    /// The motor driver raised its alarm, disabled until zeroed again
+    Alarm,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct StepperStatus {
+    pub state: StepperState,
+    pub position: i32,
+    pub target: i32,
+}
+
+impl StepperStatus {
+    /// Status of a stepper that has not started
+    pub const fn new() -> StepperStatus {
+        StepperStatus {
+            state: StepperState::Unzeroed,
+            position: 0,
+            target: 0,
+        }
+    }
+}
+
+impl Default for StepperStatus {
+    fn default() -> StepperStatus {
+        StepperStatus::new()
+    }
+}
+
+/// Ticks per second for `rpm` on a motor driver taking `ppr` pulses per
+/// revolution
+pub fn tick_rate(ppr: u32, rpm: u32) -> u32 {
+    2 * ppr * rpm / 60
+}
+
+pub struct Stepper<ENA, DIR, PUL, ALM, LimL, LimR> {
+    ena: ENA,
+    dir: DIR,
+    pul: PUL,
+    alm: Limit<ALM>,
+    lim_l: Limit<LimL>,
+    lim_r: Limit<LimR>,
+    config: StepperConfig,
+    state: StepperState,
+    position: i32,
+    target: i32,
+    /// `pul` is high, the next tick ends the pulse
+    pulse: bool,
+}
+
+impl<ENA, DIR, PUL, ALM, LimL, LimR> Stepper<ENA, DIR, PUL, ALM, LimL, LimR>
+where
+    ENA: OutputPin,
+    DIR: OutputPin,
+    PUL: OutputPin,
+    ALM: InputPin,
+    LimL: InputPin,
+    LimR: InputPin,
+{
+    /// The motor driver starts disabled. `alm` is the alarm output of the
+    /// motor driver, `Limit::none()` when not wired.
+    pub fn new(
+        ena: ENA,
+        dir: DIR,
+        pul: PUL,
+        alm: Limit<ALM>,
+        lim_l: Limit<LimL>,
+        lim_r: Limit<LimR>,
+        config: StepperConfig,
+    ) -> Self {
+        let mut stepper = Stepper {
+            ena,
+            dir,
+            pul,
+            alm,
+            lim_l,
+            lim_r,
+            config,
+            state: StepperState::Unzeroed,
+            position: 0,
+            target: 0,
+            pulse: false,
+        };
+        stepper.pul.set_low();
+        stepper.enable(false);
+        stepper
+    }
+
+    pub fn command(&mut self, command: StepperCommand) {
+        match command {
+            StepperCommand::Zero => {
+                self.enable(true);
+                self.state = StepperState::Zeroing;
+            }
+            StepperCommand::Goto(pos) => self.target = pos.max(0).min(self.config.travel),
+            StepperCommand::Stop => self.target = self.position,
+        }
+    }
+
+    pub fn status(&self) -> StepperStatus {
+        StepperStatus {
+            state: self.state,
+            position: self.position,
+            target: self.target,
+        }
+    }
+
+    /// Does half a step toward the target
+    pub fn tick(&mut self) {
+        self.alm.update();
+        self.lim_l.update();
+        self.lim_r.update();
+
+        if self.pulse {
+            self.pul.set_low();
+            self.pulse = false;
+            return;
+        }
+
+        if self.alm.is_active() && self.state != StepperState::Alarm {
+            self.enable(false);
+            self.state = StepperState::Alarm;
+        }
+
+        match self.state {
+            StepperState::Unzeroed | StepperState::Alarm => (),
+            StepperState::Zeroing => {
+                if self.lim_l.is_active() {
+                    self.position = 0;
+                    self.state = StepperState::Ready;
+                }
+                else {
+                    self.step(false);
+                }
+            }
+            StepperState::Ready => {
+                if self.position < self.target && !self.lim_r.is_active() {
+                    self.step(true);
+                }
+                else if self.position > self.target && !self.lim_l.is_active() {
+                    self.step(false);
+                }
+            }
+        }
+    }
+
+    fn step(&mut self, right: bool) {
+        if right != self.config.invert_dir {
+            self.dir.set_low();
+        }
+        else {
+            self.dir.set_high();
+        }
+        self.pul.set_high();
+        self.pulse = true;
+        self.position += if right { 1 } else { -1 };
+    }
+
+    fn enable(&mut self, on: bool) {
+        if on == (self.config.enable == Polarity::ActiveHigh) {
+            self.ena.set_high();
+        }
+        else {
+            self.ena.set_low();
+        }
+    }
+}
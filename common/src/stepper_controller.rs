@@ -0,0 +1,132 @@
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::timer::{CountDown, Periodic};
+
+use crate::{CheckIns, Queue, Stepper, StepperCommand, StepperStatus};
+
+/// Keeps every interrupt that could touch a shared value out while `f` runs,
+/// `cortex_m::interrupt::free` on the boards
+pub trait Lock {
+    fn lock<R, F: FnOnce() -> R>(f: F) -> R;
+}
+
+/// Commands that can wait for the stepper. The loop takes one every tick, so
+/// the queue only fills if it hangs.
+pub const COMMAND_QUEUE_LEN: usize = 8;
+
+/// Hands commands from the interrupts to the loop running the stepper, in
+/// order, and its status back. `L` is how the boards keep interrupts out.
+pub struct StepperController<L> {
+    /// Only touched with `L` held
+    commands: UnsafeCell<Queue<StepperCommand, COMMAND_QUEUE_LEN>>,
+    /// Only touched with `L` held
+    status: UnsafeCell<StepperStatus>,
+    lock: PhantomData<L>,
+}
+
+// The queue and the status are only touched under the lock
+unsafe impl<L> Sync for StepperController<L> {}
+
+impl<L> StepperController<L> {
+    pub const fn new() -> StepperController<L> {
+        StepperController {
+            commands: UnsafeCell::new(Queue::new()),
+            status: UnsafeCell::new(StepperStatus::new()),
+            lock: PhantomData,
+        }
+    }
+}
+
+impl<L> Default for StepperController<L> {
+    fn default() -> StepperController<L> {
+        StepperController::new()
+    }
+}
+
+impl<L: Lock> StepperController<L> {
+    pub fn zero(&self) {
+        self.send(StepperCommand::Zero);
+    }
+
+    pub fn goto(&self, pos: i32) {
+        self.send(StepperCommand::Goto(pos));
+    }
+
+    /// Holds the steering where it is
+    pub fn stop(&self) {
+        self.send(StepperCommand::Stop);
+    }
+
+    /// Commands can come from the main loop and from interrupts. A command
+    /// that does not fit is counted and dropped.
+    fn send(&self, command: StepperCommand) {
+        self.commands(|commands| commands.push(command)).ok();
+    }
+
+    /// Runs `f` on the command queue with `L` held
+    fn commands<R>(&self, f: impl FnOnce(&mut Queue<StepperCommand, COMMAND_QUEUE_LEN>) -> R) -> R {
+        L::lock(|| f(unsafe { &mut *self.commands.get() }))
+    }
+
+    pub fn status(&self) -> StepperStatus {
+        L::lock(|| unsafe { *self.status.get() })
+    }
+
+    /// Commands that were dropped because the queue was full
+    pub fn dropped(&self) -> u32 {
+        self.commands(|commands| commands.dropped())
+    }
+
+    /// Takes the next command and ticks `stepper` once. Only call it from
+    /// one place, `run` on the boards.
+    pub fn step<ENA, DIR, PUL, ALM, LimL, LimR>(&self, stepper: &mut Stepper<ENA, DIR, PUL, ALM, LimL, LimR>)
+    where
+        ENA: OutputPin,
+        DIR: OutputPin,
+        PUL: OutputPin,
+        ALM: InputPin,
+        LimL: InputPin,
+        LimR: InputPin,
+    {
+        if let Some(command) = self.commands(|commands| commands.pop()) {
+            stepper.command(command);
+        }
+
+        stepper.tick();
+        let status = stepper.status();
+        L::lock(|| unsafe { *self.status.get() = status });
+    }
+
+    /// Steps `stepper` every time `timer` runs out, and checks in as `part`
+    /// every tick. `background` runs after every step, for work that is too
+    /// slow for an interrupt like writing flash. Blocks forever.
+    pub fn run<ENA, DIR, PUL, ALM, LimL, LimR, TIM, B>(
+        &self,
+        stepper: &mut Stepper<ENA, DIR, PUL, ALM, LimL, LimR>,
+        timer: &mut TIM,
+        check_ins: &CheckIns,
+        part: u8,
+        mut background: B,
+    ) -> !
+    where
+        ENA: OutputPin,
+        DIR: OutputPin,
+        PUL: OutputPin,
+        ALM: InputPin,
+        LimL: InputPin,
+        LimR: InputPin,
+        TIM: CountDown + Periodic,
+        B: FnMut(),
+    {
+        loop {
+            self.step(stepper);
+            check_ins.check_in(part);
+            background();
+
+            // Also gives time to other interrupts
+            nb::block!(timer.wait()).ok();
+        }
+    }
+}
@@ -0,0 +1,8 @@
+//! Register glue for the STM32F103C8 every board is built on, only there
+//! with the `stm32f103` feature. Where things live in flash is up to each
+//! firmware.
+
+mod flash;
+pub mod watchdog;
+
+pub use flash::FlashRegs;
@@ -0,0 +1,45 @@
+use core::ptr;
+
+use stm32f1xx_hal::pac::flash::RegisterBlock;
+use stm32f1xx_hal::pac::FLASH;
+
+use crate::FlashRegisters;
+
+/// The flash interface of the STM32F103C8
+pub struct FlashRegs;
+
+impl FlashRegisters for FlashRegs {
+    const PAGE_SIZE: usize = 1024;
+
+    fn write_keyr(&self, key: u32) {
+        flash().keyr.write(|w| unsafe { w.bits(key) });
+    }
+
+    fn write_cr(&self, cr: u32) {
+        flash().cr.write(|w| unsafe { w.bits(cr) });
+    }
+
+    fn write_ar(&self, address: u32) {
+        flash().ar.write(|w| unsafe { w.bits(address) });
+    }
+
+    fn read_sr(&self) -> u32 {
+        flash().sr.read().bits()
+    }
+
+    fn write_sr(&self, sr: u32) {
+        flash().sr.write(|w| unsafe { w.bits(sr) });
+    }
+
+    fn read_byte(&self, address: usize) -> u8 {
+        unsafe { ptr::read_volatile(address as *const u8) }
+    }
+
+    fn write_half(&self, address: usize, half: u16) {
+        unsafe { ptr::write_volatile(address as *mut u16, half) };
+    }
+}
+
+fn flash() -> &'static RegisterBlock {
+    unsafe { &*FLASH::ptr() }
+}
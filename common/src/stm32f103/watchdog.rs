@@ -0,0 +1,41 @@
+use stm32f1xx_hal::pac::iwdg::RegisterBlock;
+use stm32f1xx_hal::pac::{IWDG, RCC};
+
+use crate::{Iwdg, ResetReason};
+
+struct Regs<'a>(&'a RegisterBlock);
+
+impl Iwdg for Regs<'_> {
+    fn write_key(&self, key: u32) {
+        self.0.kr.write(|w| unsafe { w.bits(key) });
+    }
+
+    fn write_prescaler(&self, prescaler: u32) {
+        self.0.pr.write(|w| unsafe { w.bits(prescaler) });
+    }
+
+    fn write_reload(&self, reload: u32) {
+        self.0.rlr.write(|w| unsafe { w.bits(reload) });
+    }
+
+    fn status(&self) -> u32 {
+        self.0.sr.read().bits()
+    }
+}
+
+/// Starts the independent watchdog, the chip resets unless `feed` is called
+/// at least every `ms`. It keeps running until the next reset.
+pub fn start(iwdg: &IWDG, ms: u32) {
+    crate::start_watchdog(&Regs(iwdg), ms);
+}
+
+pub fn feed() {
+    crate::feed_watchdog(&Regs(unsafe { &*IWDG::ptr() }));
+}
+
+/// Why the chip last reset. Clears the flags, so call it once at boot.
+pub fn reset_reason(rcc: &RCC) -> ResetReason {
+    let csr = rcc.csr.read().bits();
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+    ResetReason::from_csr(csr)
+}
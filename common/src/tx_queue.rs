@@ -0,0 +1,126 @@
+use embedded_hal::serial::Write;
+
+use crate::{Frame, MAX_PACKET_LEN};
+
+pub const TX_QUEUE_LEN: usize = 64;
+
+/// There was no room for all of the bytes, none of them were queued
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Full;
+
+/// Ring buffer of bytes waiting to be sent, meant to be filled by the main
+/// loop and drained from the TXE interrupt
+pub struct TxQueue {
+    buf: [u8; TX_QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl TxQueue {
+    pub const fn new() -> TxQueue {
+        TxQueue {
+            buf: [0; TX_QUEUE_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn free(&self) -> usize {
+        TX_QUEUE_LEN - self.len
+    }
+
+    pub fn push(&mut self, byte: u8) -> Result<(), u8> {
+        if self.len == TX_QUEUE_LEN {
+            return Err(byte);
+        }
+        self.buf[(self.head + self.len) % TX_QUEUE_LEN] = byte;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Queues all of `data` or nothing at all
+    pub fn push_slice(&mut self, data: &[u8]) -> Result<(), Full> {
+        if self.free() < data.len() {
+            return Err(Full);
+        }
+        for b in data {
+            self.push(*b).ok();
+        }
+        Ok(())
+    }
+
+    pub fn push_frame(&mut self, frame: &Frame) -> Result<(), Full> {
+        let mut buf = [0; MAX_PACKET_LEN];
+        let len = frame.write(&mut buf);
+        self.push_slice(&buf[..len])
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % TX_QUEUE_LEN;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    /// Writes bytes until the writer would block or the queue is empty
+    pub fn drain<W: Write<u8>>(&mut self, writer: &mut W) {
+        while let Some(byte) = self.pop() {
+            match writer.write(byte) {
+                Ok(()) => (),
+                Err(_) => {
+                    // Put it back in front
+                    self.head = (self.head + TX_QUEUE_LEN - 1) % TX_QUEUE_LEN;
+                    self.len += 1;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Default for TxQueue {
+    fn default() -> TxQueue {
+        TxQueue::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_around() {
+        let mut queue = TxQueue::new();
+        for i in 0..(3 * TX_QUEUE_LEN) {
+            queue.push(i as u8).unwrap();
+            assert_eq!(queue.pop(), Some(i as u8));
+        }
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn frame_is_all_or_nothing() {
+        let frame = Frame {
+            id: 1,
+            seq: 0,
+            motor_state: crate::MotorState::Idle(0),
+            motor_direction: 128,
+        };
+        let mut queue = TxQueue::new();
+        let len = frame.write(&mut [0; MAX_PACKET_LEN]);
+        while queue.push_frame(&frame).is_ok() {}
+        assert_eq!(queue.len(), TX_QUEUE_LEN / len * len);
+        assert_eq!(queue.push_frame(&frame), Err(Full));
+    }
+}
@@ -0,0 +1,143 @@
+//! Engine and steering usage for maintenance, counted on a driver and saved
+//! now and then in a `FlashRing`. A reset loses what was counted since the
+//! last save.
+
+use byteorder::{ByteOrder, LE};
+
+use crate::flash_ring::{Flash, FlashError, FlashRing};
+use crate::{MotorState, StepperState, StepperStatus};
+
+/// Bytes of `Usage` in a packet or a slot
+pub const USAGE_LEN: usize = 16;
+
+const SLOT_LEN: usize = 32;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct Usage {
+    /// Seconds the throttle was open
+    pub run_seconds: u32,
+    /// Times the engine was started
+    pub starts: u32,
+    /// Times the gear was put in another position
+    pub shifts: u32,
+    /// Steps the stepper moved, either way
+    pub steps: u32,
+}
+
+impl Usage {
+    /// [u32 run seconds, u32 starts, u32 shifts, u32 steps]
+    pub(crate) fn write(&self, buf: &mut [u8]) {
+        LE::write_u32(&mut buf[0..4], self.run_seconds);
+        LE::write_u32(&mut buf[4..8], self.starts);
+        LE::write_u32(&mut buf[8..12], self.shifts);
+        LE::write_u32(&mut buf[12..16], self.steps);
+    }
+
+    pub(crate) fn read(buf: &[u8]) -> Usage {
+        Usage {
+            run_seconds: LE::read_u32(&buf[0..4]),
+            starts: LE::read_u32(&buf[4..8]),
+            shifts: LE::read_u32(&buf[8..12]),
+            steps: LE::read_u32(&buf[12..16]),
+        }
+    }
+}
+
+/// Counts usage from what a driver is told to do and what its stepper does
+pub struct UsageMeter {
+    usage: Usage,
+    /// Milliseconds toward the next run second
+    run_ms: u16,
+    /// Gear position asked for last, idle, forward or reverse
+    gear: Option<u8>,
+    stepper: Option<(StepperState, i32)>,
+}
+
+impl UsageMeter {
+    /// Carries on from `usage`, as saved before the reset
+    pub const fn new(usage: Usage) -> UsageMeter {
+        UsageMeter {
+            usage,
+            run_ms: 0,
+            gear: None,
+            stepper: None,
+        }
+    }
+
+    /// Call every millisecond with the motor state the driver follows
+    pub fn tick(&mut self, motor_state: MotorState, stepper: StepperStatus) {
+        let (gear, power) = match motor_state {
+            MotorState::Idle(p) => (0, p),
+            MotorState::Fwd(p) => (1, p),
+            MotorState::Rev(p) => (2, p),
+        };
+        if power > 0 {
+            self.run_ms += 1;
+            if self.run_ms == 1000 {
+                self.run_ms = 0;
+                self.usage.run_seconds = self.usage.run_seconds.wrapping_add(1);
+            }
+        }
+        if self.gear.is_some() && self.gear != Some(gear) {
+            self.usage.shifts = self.usage.shifts.wrapping_add(1);
+        }
+        self.gear = Some(gear);
+
+        // Zeroing starts counting again, that jump is not travel
+        if let Some((state, position)) = self.stepper {
+            if state == stepper.state {
+                let moved = (stepper.position - position).unsigned_abs();
+                self.usage.steps = self.usage.steps.wrapping_add(moved);
+            }
+        }
+        self.stepper = Some((stepper.state, stepper.position));
+    }
+
+    /// Call every time the starter is engaged
+    pub fn started(&mut self) {
+        self.usage.starts = self.usage.starts.wrapping_add(1);
+    }
+
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+}
+
+/// Keeps the newest `Usage` in flash
+pub struct UsageStore<F> {
+    ring: FlashRing<F>,
+    /// What the flash holds
+    saved: Usage,
+}
+
+impl<F: Flash> UsageStore<F> {
+    pub fn open(flash: F) -> UsageStore<F> {
+        let ring = FlashRing::open(flash, SLOT_LEN);
+        let mut data = [0; SLOT_LEN - 6];
+        let saved = if ring.get(0, &mut data) {
+            Usage::read(&data)
+        }
+        else {
+            Usage::default()
+        };
+        UsageStore { ring, saved }
+    }
+
+    /// The last saved usage, all zero on a new board
+    pub fn saved(&self) -> Usage {
+        self.saved
+    }
+
+    /// Writes `usage` unless it is what was saved last. Blocks for a page
+    /// erase whenever the ring moves on to the next page.
+    pub fn save(&mut self, usage: Usage) -> Result<(), FlashError> {
+        if usage == self.saved {
+            return Ok(());
+        }
+        let mut data = [0xff; SLOT_LEN - 6];
+        usage.write(&mut data);
+        self.ring.push(&data)?;
+        self.saved = usage;
+        Ok(())
+    }
+}
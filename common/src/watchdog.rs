@@ -0,0 +1,160 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Collects check-ins from the parts of a firmware that have to keep running.
+/// The watchdog is only fed once every part checked in since the last feed,
+/// so a part that hangs resets the board even if the others carry on.
+pub struct CheckIns {
+    /// One bit per part
+    required: u8,
+    seen: AtomicU8,
+}
+
+impl CheckIns {
+    pub const fn new(required: u8) -> CheckIns {
+        CheckIns {
+            required,
+            seen: AtomicU8::new(0),
+        }
+    }
+
+    /// Can be called from any interrupt
+    pub fn check_in(&self, part: u8) {
+        self.seen.fetch_or(part, Ordering::Relaxed);
+    }
+
+    /// True if every part checked in, they all have to check in again
+    /// before the next true
+    pub fn all_in(&self) -> bool {
+        let seen = self.seen.swap(0, Ordering::Relaxed);
+        if seen & self.required == self.required {
+            true
+        }
+        else {
+            // Keep what came in, a part may check in while we look
+            self.seen.fetch_or(seen, Ordering::Relaxed);
+            false
+        }
+    }
+}
+
+/// The watchdog counts the 40 kHz LSI divided by 64
+const PRESCALER: u32 = 4;
+const COUNTS_PER_SECOND: u32 = 40_000 / 64;
+/// Largest reload value
+const MAX_RELOAD: u32 = 0xfff;
+/// Reads of IWDG_SR before giving up on the new values, they take a few
+/// LSI periods to get through
+const STATUS_POLLS: u32 = 10_000;
+
+// IWDG_KR keys
+const KEY_UNLOCK: u32 = 0x5555;
+const KEY_FEED: u32 = 0xaaaa;
+const KEY_START: u32 = 0xcccc;
+
+/// The registers of the independent watchdog, each board reaches them
+/// through its own peripheral access crate
+pub trait Iwdg {
+    /// Writes IWDG_KR
+    fn write_key(&self, key: u32);
+    /// Writes IWDG_PR
+    fn write_prescaler(&self, prescaler: u32);
+    /// Writes IWDG_RLR
+    fn write_reload(&self, reload: u32);
+    /// Reads IWDG_SR, a bit is set while a new value is on its way to the
+    /// watchdog clock domain
+    fn status(&self) -> u32;
+}
+
+/// Starts the independent watchdog, the chip resets unless `feed_watchdog`
+/// is called at least every `ms`. It keeps running until the next reset.
+pub fn start_watchdog<W: Iwdg>(iwdg: &W, ms: u32) {
+    let reload = (ms * COUNTS_PER_SECOND / 1000).min(MAX_RELOAD);
+    // Starting it turns on the LSI, without it the new values never get
+    // through. Until they do it runs out after the default 400 ms.
+    iwdg.write_key(KEY_START);
+    iwdg.write_key(KEY_UNLOCK);
+    iwdg.write_prescaler(PRESCALER);
+    iwdg.write_reload(reload);
+    // Wait for the new values to reach the watchdog clock domain. If they
+    // never do the board resets with the defaults, it does not hang here.
+    for _ in 0..STATUS_POLLS {
+        if iwdg.status() == 0 {
+            break;
+        }
+    }
+    iwdg.write_key(KEY_FEED);
+}
+
+pub fn feed_watchdog<W: Iwdg>(iwdg: &W) {
+    iwdg.write_key(KEY_FEED);
+}
+
+// Reset flags in RCC_CSR
+const PINRSTF: u32 = 1 << 26;
+const PORRSTF: u32 = 1 << 27;
+const SFTRSTF: u32 = 1 << 28;
+const IWDGRSTF: u32 = 1 << 29;
+const WWDGRSTF: u32 = 1 << 30;
+const LPWRRSTF: u32 = 1 << 31;
+
+/// Why a board last started
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ResetReason {
+    PowerOn,
+    /// The reset pin, a programmer or the button
+    Pin,
+    /// A watchdog was not fed in time
+    Watchdog,
+    /// The firmware asked for it
+    Software,
+    LowPower,
+    Unknown,
+}
+
+impl ResetReason {
+    /// Decodes the RCC_CSR register. The pin flag is set by every reset, so
+    /// it only counts when nothing else is.
+    pub fn from_csr(csr: u32) -> ResetReason {
+        if csr & (IWDGRSTF | WWDGRSTF) != 0 {
+            ResetReason::Watchdog
+        }
+        else if csr & SFTRSTF != 0 {
+            ResetReason::Software
+        }
+        else if csr & LPWRRSTF != 0 {
+            ResetReason::LowPower
+        }
+        else if csr & PORRSTF != 0 {
+            ResetReason::PowerOn
+        }
+        else if csr & PINRSTF != 0 {
+            ResetReason::Pin
+        }
+        else {
+            ResetReason::Unknown
+        }
+    }
+
+    pub(crate) fn code(self) -> u8 {
+        match self {
+            ResetReason::Unknown => 0,
+            ResetReason::PowerOn => 1,
+            ResetReason::Pin => 2,
+            ResetReason::Watchdog => 3,
+            ResetReason::Software => 4,
+            ResetReason::LowPower => 5,
+        }
+    }
+
+    pub(crate) fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(ResetReason::Unknown),
+            1 => Some(ResetReason::PowerOn),
+            2 => Some(ResetReason::Pin),
+            3 => Some(ResetReason::Watchdog),
+            4 => Some(ResetReason::Software),
+            5 => Some(ResetReason::LowPower),
+            _ => None,
+        }
+    }
+}
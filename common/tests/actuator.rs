@@ -0,0 +1,299 @@
+#![allow(deprecated)]
+
+mod mock;
+
+use common::{Actuator, ActuatorConfig, End, Limit, Polarity, DEBOUNCE_TICKS, STALL_TICKS};
+use mock::{AdcChannel, Drive, Pin, Plant, Switch};
+
+type TestActuator = Actuator<Pin, Pin, AdcChannel, Pin, Pin>;
+
+/// Wiring of the plant as it comes, forward lowers the reading so it moves
+/// toward zero travel. A per-mille is 4 sensor counts.
+const CONFIG: ActuatorConfig = ActuatorConfig {
+    min: 0,
+    max: 4000,
+    invert_sensor: false,
+    invert_motor: true,
+};
+
+struct Rig {
+    actuator: TestActuator,
+    plant: Plant,
+    fwd: Switch,
+    rev: Switch,
+}
+
+impl Rig {
+    fn new(position: u16, fwd: Option<u16>, rev: Option<u16>) -> Rig {
+        Rig::with_switches(position, Switch::fwd(fwd), Switch::rev(rev))
+    }
+
+    fn with_switches(position: u16, fwd: Switch, rev: Switch) -> Rig {
+        Rig::with_config(Plant::new(position), fwd, rev, CONFIG)
+    }
+
+    fn with_config(plant: Plant, fwd: Switch, rev: Switch, config: ActuatorConfig) -> Rig {
+        fwd.update(&plant);
+        rev.update(&plant);
+        let polarity = |s: &Switch| if s.active_high { Polarity::ActiveHigh } else { Polarity::ActiveLow };
+        let actuator = Actuator::new(
+            plant.in1.clone(),
+            plant.in2.clone(),
+            AdcChannel,
+            Limit::new(fwd.pin.clone(), polarity(&fwd)),
+            Limit::new(rev.pin.clone(), polarity(&rev)),
+            config,
+        );
+        Rig { actuator, plant, fwd, rev }
+    }
+
+    /// Runs the control loop until the actuator stops or `ticks` run out,
+    /// returns the number of ticks it took
+    fn run(&mut self, ticks: usize) -> usize {
+        let mut adc = self.plant.adc.clone();
+        for i in 0..ticks {
+            self.actuator.tick(&mut adc);
+            if self.actuator.stopped() {
+                return i;
+            }
+            self.plant.step();
+            self.fwd.update(&self.plant);
+            self.rev.update(&self.plant);
+        }
+        ticks
+    }
+
+    /// Distance of the plant from a target, in per-mille
+    fn distance(&self, target: u16) -> i32 {
+        (self.plant.position() as i32 / 4 - target as i32).abs()
+    }
+}
+
+#[test]
+fn goto_rev() {
+    let mut rig = Rig::new(1000, None, None);
+    rig.actuator.goto(750);
+    assert!(!rig.actuator.stopped());
+    assert!(rig.run(1000) < 1000);
+
+    assert!(rig.actuator.stopped());
+    assert_eq!(rig.plant.drive(), Drive::Stop);
+    assert!(rig.distance(750) <= 10);
+    assert_eq!(rig.actuator.tripped(), None);
+}
+
+#[test]
+fn goto_fwd() {
+    let mut rig = Rig::new(3000, None, None);
+    rig.actuator.goto(250);
+    assert!(rig.run(1000) < 1000);
+
+    assert_eq!(rig.plant.drive(), Drive::Stop);
+    assert!(rig.distance(250) <= 10);
+}
+
+#[test]
+fn deadband() {
+    let mut rig = Rig::new(2000, None, None);
+    rig.run(1);
+
+    // Close enough already, nothing moves
+    rig.actuator.goto(540);
+    assert!(rig.actuator.stopped());
+    assert_eq!(rig.run(100), 0);
+    assert_eq!(rig.plant.position(), 2000);
+
+    // Further out it moves, and stops within 10 of the target
+    rig.actuator.goto(600);
+    assert!(!rig.actuator.stopped());
+    rig.run(100);
+    assert!(rig.actuator.stopped());
+    assert!(rig.distance(600) <= 10);
+}
+
+#[test]
+fn limit_stops_fwd() {
+    let mut rig = Rig::new(3000, Some(1500), None);
+    rig.actuator.goto(250);
+    assert!(rig.run(1000) < 1000);
+
+    assert!(rig.actuator.stopped());
+    assert_eq!(rig.plant.drive(), Drive::Stop);
+    assert_eq!(rig.actuator.tripped(), Some(End::Fwd));
+    assert!(rig.actuator.at_limit(End::Fwd));
+    // Debouncing lets it run on for a few ticks
+    let overrun = (DEBOUNCE_TICKS as u16 + 1) * rig.plant.speed;
+    assert!(rig.plant.position() <= 1500 && rig.plant.position() >= 1500 - overrun);
+}
+
+#[test]
+fn limit_stops_rev() {
+    let mut rig = Rig::new(1000, None, Some(2500));
+    rig.actuator.goto(750);
+    assert!(rig.run(1000) < 1000);
+
+    assert_eq!(rig.plant.drive(), Drive::Stop);
+    assert_eq!(rig.actuator.tripped(), Some(End::Rev));
+    assert!(rig.plant.position() >= 2500 && rig.plant.position() < 2600);
+
+    // A move away from the switch clears it
+    rig.actuator.goto(250);
+    assert_eq!(rig.actuator.tripped(), None);
+    assert!(rig.run(1000) < 1000);
+    assert!(rig.distance(250) <= 10);
+    assert!(!rig.actuator.at_limit(End::Rev));
+}
+
+#[test]
+fn limit_blocks_only_its_end() {
+    let mut rig = Rig::new(1000, Some(1500), None);
+    assert!(rig.actuator.at_limit(End::Fwd));
+
+    rig.actuator.go_fwd();
+    assert_eq!(rig.plant.drive(), Drive::Stop);
+
+    // Backing off the switch is allowed
+    rig.actuator.go_rev();
+    assert_eq!(rig.plant.drive(), Drive::Rev);
+}
+
+#[test]
+fn goto_into_closed_limit() {
+    let mut rig = Rig::new(1000, Some(1500), None);
+    rig.actuator.goto(125);
+    assert_eq!(rig.run(10), 0);
+    assert_eq!(rig.plant.drive(), Drive::Stop);
+    assert_eq!(rig.actuator.tripped(), Some(End::Fwd));
+    assert_eq!(rig.plant.position(), 1000);
+}
+
+#[test]
+fn active_high_switch() {
+    let mut fwd = Switch::fwd(Some(1500));
+    fwd.active_high = true;
+    let mut rig = Rig::with_switches(3000, fwd, Switch::rev(None));
+    assert!(!rig.actuator.at_limit(End::Fwd));
+
+    rig.actuator.goto(250);
+    assert!(rig.run(1000) < 1000);
+    assert_eq!(rig.actuator.tripped(), Some(End::Fwd));
+    assert!(rig.fwd.pin.get());
+}
+
+#[test]
+fn switch_bounce_is_ignored() {
+    let mut rig = Rig::new(3000, None, None);
+    rig.actuator.goto(250);
+    rig.run(5);
+
+    // Single glitches shorter than the debounce never stop the move
+    let mut adc = rig.plant.adc.clone();
+    for _ in 0..20 {
+        rig.fwd.pin.set(false);
+        rig.actuator.tick(&mut adc);
+        rig.fwd.pin.set(true);
+        rig.actuator.tick(&mut adc);
+    }
+    assert_eq!(rig.plant.drive(), Drive::Fwd);
+    assert!(!rig.actuator.at_limit(End::Fwd));
+}
+
+#[test]
+fn stop_clears_target() {
+    let mut rig = Rig::new(1000, None, None);
+    rig.actuator.goto(750);
+    rig.run(10);
+    assert!(!rig.actuator.stopped());
+    assert_eq!(rig.plant.drive(), Drive::Rev);
+
+    rig.actuator.stop();
+    assert!(rig.actuator.stopped());
+    assert_eq!(rig.plant.drive(), Drive::Stop);
+
+    // Without a target tick only reads the position
+    let before = rig.plant.position();
+    rig.run(10);
+    assert_eq!(rig.plant.position(), before);
+    assert_eq!(rig.actuator.position(), before / 4);
+    assert_eq!(rig.actuator.raw_position(), before);
+}
+
+#[test]
+fn per_mille() {
+    let config = ActuatorConfig {
+        min: 1000,
+        max: 3000,
+        invert_sensor: false,
+        invert_motor: false,
+    };
+    assert_eq!(config.per_mille(1000), 0);
+    assert_eq!(config.per_mille(2000), 500);
+    assert_eq!(config.per_mille(3000), 1000);
+    // Readings outside the span clamp to its ends
+    assert_eq!(config.per_mille(0), 0);
+    assert_eq!(config.per_mille(4095), 1000);
+
+    let inverted = ActuatorConfig { invert_sensor: true, ..config };
+    assert_eq!(inverted.per_mille(1000), 1000);
+    assert_eq!(inverted.per_mille(2500), 250);
+    assert_eq!(inverted.per_mille(4095), 0);
+}
+
+#[test]
+fn any_wiring_reaches_the_target() {
+    for &reversed in &[false, true] {
+        for &invert_sensor in &[false, true] {
+            // Forward moves toward full travel when the motor raises the
+            // reading and the sensor reads up, or both the other way
+            let config = ActuatorConfig {
+                min: 1000,
+                max: 3000,
+                invert_sensor,
+                invert_motor: !(reversed ^ invert_sensor),
+            };
+            let mut plant = Plant::new(2000);
+            plant.reversed = reversed;
+            let mut rig = Rig::with_config(plant, Switch::fwd(None), Switch::rev(None), config);
+
+            for &target in &[900, 100, 1000, 0] {
+                rig.actuator.goto(target);
+                assert!(rig.run(1000) < 1000, "{:?} {}", config, reversed);
+                let pos = rig.actuator.position() as i32;
+                assert!((pos - target as i32).abs() <= 10, "{:?} {} {}", config, reversed, pos);
+                assert_eq!(config.per_mille(rig.plant.position()), rig.actuator.position());
+            }
+        }
+    }
+}
+
+#[test]
+fn target_is_clamped_to_full_travel() {
+    let mut rig = Rig::new(1000, None, None);
+    rig.actuator.goto(5000);
+    assert!(rig.run(2000) < 2000);
+    assert!(rig.distance(1000) <= 10);
+}
+
+#[test]
+fn stall_is_reported() {
+    // Moving, however slowly, is not a stall
+    let mut rig = Rig::new(1000, None, None);
+    rig.plant.speed = 1;
+    rig.actuator.goto(500);
+    rig.run(STALL_TICKS as usize * 2);
+    assert!(!rig.actuator.stalled());
+
+    let mut rig = Rig::new(1000, None, None);
+    rig.plant.speed = 0;
+    rig.actuator.goto(500);
+    rig.run(STALL_TICKS as usize / 2);
+    assert!(!rig.actuator.stalled());
+    rig.run(STALL_TICKS as usize / 2 + 1);
+    assert!(rig.actuator.stalled());
+    // Still driven
+    assert_eq!(rig.plant.drive(), Drive::Rev);
+
+    rig.actuator.stop();
+    rig.run(1);
+    assert!(!rig.actuator.stalled());
+}
@@ -0,0 +1,154 @@
+#![allow(deprecated)]
+
+mod mock;
+
+use common::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+use embedded_hal::serial::Write;
+use mock::Pin;
+
+fn request(dst: u8, message: Message) -> Packet {
+    Packet { dst, src: MASTER, seq: 4, message }
+}
+
+#[test]
+fn addressing() {
+    let packet = request(2, Message::EStop);
+    assert!(packet.is_for(2));
+    assert!(!packet.is_for(1));
+    assert!(packet.wants_reply());
+
+    let broadcast = request(BROADCAST, Message::EStop);
+    assert!(broadcast.is_for(1) && broadcast.is_for(2));
+    assert!(!broadcast.wants_reply());
+
+    let reply = packet.reply(2, Message::Pong);
+    assert_eq!(reply, Packet { dst: MASTER, src: 2, seq: 4, message: Message::Pong });
+    // Nodes never answer each other
+    assert!(!reply.wants_reply());
+}
+
+#[test]
+fn one_request_at_a_time() {
+    let mut bus = BusMaster::new(3);
+    assert!(bus.is_idle());
+
+    bus.expect(1);
+    assert!(!bus.is_idle());
+    // Another node, or a packet not for the master, is not the answer
+    assert!(!bus.received(&request(1, Message::Pong).reply(2, Message::Pong)));
+    assert!(!bus.received(&request(1, Message::Pong)));
+    assert!(!bus.is_idle());
+
+    assert!(bus.received(&request(1, Message::Ping).reply(1, Message::Pong)));
+    assert!(bus.is_idle());
+    assert!(bus.is_present(1));
+    assert!(!bus.is_present(2));
+}
+
+#[test]
+fn timeout_frees_the_bus() {
+    let mut bus = BusMaster::new(3);
+    bus.expect(1);
+    bus.received(&request(1, Message::Ping).reply(1, Message::Pong));
+
+    bus.expect(1);
+    assert_eq!(bus.tick(), None);
+    assert_eq!(bus.tick(), None);
+    assert_eq!(bus.tick(), Some(1));
+    assert!(bus.is_idle());
+    assert!(!bus.is_present(1));
+    // Nothing to time out
+    assert_eq!(bus.tick(), None);
+}
+
+#[test]
+fn answer_one_tick_late_still_counts() {
+    let mut bus = BusMaster::new(REPLY_TICKS);
+    // Sent just before the tick, answered just after it
+    bus.expect(1);
+    assert_eq!(bus.tick(), None);
+    assert!(bus.received(&request(1, Message::Ping).reply(1, Message::Pong)));
+    assert!(bus.is_present(1));
+}
+
+#[test]
+fn discovery() {
+    let mut bus = BusMaster::new(1);
+    let mut pinged = Vec::new();
+    for _ in 0..2 * MAX_NODE {
+        let node = bus.next_scan().unwrap();
+        pinged.push(node);
+        bus.expect(node);
+        if node == 2 || node == 3 {
+            assert!(bus.received(&request(node, Message::Ping).reply(node, Message::Pong)));
+        }
+        else {
+            assert_eq!(bus.tick(), Some(node));
+        }
+    }
+
+    assert!(pinged[..MAX_NODE as usize].iter().copied().eq(1..=MAX_NODE));
+    // Once found a node is no longer pinged
+    assert!(!pinged[MAX_NODE as usize..].contains(&2));
+    assert!(!pinged[MAX_NODE as usize..].contains(&3));
+    assert!(bus.is_present(2) && bus.is_present(3));
+    assert!(!bus.is_present(0) && !bus.is_present(MASTER));
+}
+
+#[test]
+fn discovery_stops_when_every_node_answers() {
+    let mut bus = BusMaster::new(1);
+    while let Some(node) = bus.next_scan() {
+        bus.expect(node);
+        bus.received(&request(node, Message::Ping).reply(node, Message::Pong));
+    }
+    assert!((1..=MAX_NODE).all(|n| bus.is_present(n)));
+}
+
+/// Uart that records every byte with the level of the driver enable pin
+/// when it was written. The last byte takes one more `flush` to go out.
+struct Wire {
+    de: Pin,
+    bytes: Rc<RefCell<Vec<(u8, bool)>>>,
+    sending: bool,
+}
+
+impl Write<u8> for Wire {
+    type Error = ();
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), ()> {
+        self.bytes.borrow_mut().push((byte, self.de.get()));
+        self.sending = true;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), ()> {
+        if self.sending {
+            self.sending = false;
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn half_duplex_holds_the_bus_while_sending() {
+    let de = Pin::new(true);
+    let bytes = Rc::new(RefCell::new(Vec::new()));
+    let wire = Wire { de: de.clone(), bytes: bytes.clone(), sending: false };
+    let mut tx = HalfDuplex::new(wire, de.clone());
+    assert!(!de.get());
+
+    let packet = request(1, Message::SteerGoto(300));
+    FrameParser::send(&packet, &mut tx);
+    // Released once the last byte is out
+    assert!(!de.get());
+
+    let mut buf = [0; MAX_PACKET_LEN];
+    let len = FrameParser::write(&packet, &mut buf);
+    let bytes = bytes.borrow();
+    assert!(bytes.iter().map(|&(b, _)| b).eq(buf[..len].iter().copied()));
+    assert!(bytes.iter().all(|&(_, de)| de));
+}
@@ -0,0 +1,76 @@
+use common::{Clock, RESTART_MS};
+
+fn run(clock: &mut Clock, ms: u32) {
+    for _ in 0..ms {
+        clock.tick();
+    }
+}
+
+#[test]
+fn runs_from_the_start_until_synced() {
+    let mut clock = Clock::new();
+    run(&mut clock, 250);
+    assert_eq!(clock.uptime(), 250);
+    assert_eq!(clock.now(), 250);
+}
+
+#[test]
+fn jumps_ahead_to_the_controller() {
+    let mut clock = Clock::new();
+    run(&mut clock, 100);
+    clock.sync(5000);
+    assert_eq!(clock.now(), 5000);
+    run(&mut clock, 10);
+    assert_eq!(clock.now(), 5010);
+    // Only the system time is synced
+    assert_eq!(clock.uptime(), 110);
+}
+
+#[test]
+fn never_goes_back() {
+    let mut clock = Clock::new();
+    run(&mut clock, 1000);
+    clock.sync(990);
+
+    let mut last = clock.now();
+    for _ in 0..20 {
+        clock.tick();
+        assert!(clock.now() >= last);
+        last = clock.now();
+    }
+    // Stood still for 10 ms, then runs with the controller again
+    assert_eq!(clock.now(), 1010);
+    clock.sync(1010);
+    run(&mut clock, 5);
+    assert_eq!(clock.now(), 1015);
+}
+
+#[test]
+fn syncs_over_the_wrap() {
+    let mut clock = Clock::new();
+    clock.sync(u32::MAX - 2);
+    run(&mut clock, 5);
+    assert_eq!(clock.now(), 2);
+    // Still ahead of a controller that has not wrapped yet
+    clock.sync(u32::MAX);
+    run(&mut clock, 3);
+    assert_eq!(clock.now(), 2);
+}
+
+#[test]
+fn follows_a_restarted_controller() {
+    let mut clock = Clock::default();
+    clock.sync(3_600_000);
+    run(&mut clock, 100);
+    // The controller came back up and counts from its start again
+    clock.sync(200);
+    assert_eq!(clock.now(), 200);
+    run(&mut clock, 10);
+    assert_eq!(clock.now(), 210);
+
+    // Just inside the limit it is still taken for drift
+    run(&mut clock, RESTART_MS);
+    clock.sync(210);
+    run(&mut clock, 5);
+    assert_eq!(clock.now(), 210 + RESTART_MS);
+}
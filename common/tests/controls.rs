@@ -0,0 +1,115 @@
+#![allow(deprecated)]
+
+mod mock;
+
+use common::{Button, Controls, EngineRelays, Event, Message, RelayState, Side, HOLD_TICKS};
+use mock::Pin;
+
+/// Main loop passes per control tick, enough for the debounce to settle
+const LOOPS_PER_TICK: usize = 20;
+
+/// Ticks the relays stay closed, in control ticks as the driver does in ms
+const CRANK_TICKS: u16 = 15;
+const STOP_TICKS: u16 = 30;
+
+/// btn_2 of the default layout and the left driver it starts and stops
+struct Boat {
+    pin: Pin,
+    button: Button<Pin>,
+    controls: Controls,
+    relays: EngineRelays<Pin, Pin>,
+    sent: Vec<Message>,
+}
+
+impl Boat {
+    fn new() -> Boat {
+        let pin = Pin::new(true);
+        Boat {
+            button: Button::new(pin.clone()),
+            pin,
+            controls: Controls::new(),
+            relays: EngineRelays::new(Pin::new(false), Pin::new(false), CRANK_TICKS, STOP_TICKS),
+            sent: Vec::new(),
+        }
+    }
+
+    /// One control tick of the controller, and the driver acting on what it
+    /// was sent
+    fn tick(&mut self) {
+        for _ in 0..LOOPS_PER_TICK {
+            if let Some(event) = self.button.event() {
+                self.controls.handle(2, event);
+            }
+            self.button.tick();
+        }
+        if self.button.held() {
+            self.controls.handle(2, Event::Hold);
+        }
+
+        self.relays.tick();
+        if let Some(message) = self.controls.take_command(Side::Left) {
+            match message {
+                Message::EngineStart => {
+                    self.relays.start();
+                }
+                Message::EngineStop => self.relays.stop(),
+                _ => (),
+            }
+            self.sent.push(message);
+        }
+    }
+
+    /// Buttons are pulled up, down is low
+    fn push(&mut self, ticks: u8) {
+        self.pin.set(false);
+        for _ in 0..ticks {
+            self.tick();
+        }
+        self.pin.set(true);
+        for _ in 0..2 {
+            self.tick();
+        }
+    }
+}
+
+#[test]
+fn holding_the_button_starts_the_engine() {
+    let mut boat = Boat::new();
+    boat.pin.set(false);
+    let mut cranked = false;
+    for _ in 0..HOLD_TICKS + 2 {
+        boat.tick();
+        cranked |= boat.relays.state() == RelayState::Cranking;
+    }
+    boat.pin.set(true);
+    boat.tick();
+    cranked |= boat.relays.state() == RelayState::Cranking;
+
+    assert_eq!(boat.sent, vec![Message::EngineStart]);
+    assert!(cranked);
+    assert!(boat.controls.is_running(Side::Left));
+}
+
+#[test]
+fn tapping_the_button_stops_the_engine() {
+    let mut boat = Boat::new();
+    boat.push(HOLD_TICKS + 2);
+    for _ in 0..CRANK_TICKS {
+        boat.tick();
+    }
+    assert_eq!(boat.relays.state(), RelayState::Open);
+
+    boat.push(2);
+    assert_eq!(boat.sent, vec![Message::EngineStart, Message::EngineStop]);
+    assert_eq!(boat.relays.state(), RelayState::Stopping);
+    assert!(!boat.controls.is_running(Side::Left));
+}
+
+#[test]
+fn nothing_is_sent_at_boot() {
+    let mut boat = Boat::new();
+    for _ in 0..5 {
+        boat.tick();
+    }
+    assert!(boat.sent.is_empty());
+}
@@ -0,0 +1,43 @@
+use common::{CruiseHold, MotorState};
+
+#[test]
+fn adjusting_down_keeps_the_hold() {
+    let mut hold = CruiseHold::latch(MotorState::Fwd(100));
+    hold.adjust(-16);
+    hold.adjust(-4);
+    assert_eq!(hold.state(), MotorState::Fwd(80));
+    // The lever is still where it was when the hold was taken
+    assert!(!hold.releases(MotorState::Fwd(100)));
+    assert!(!hold.releases(MotorState::Fwd(90)));
+
+    let mut hold = CruiseHold::latch(MotorState::Rev(60));
+    hold.adjust(-16);
+    assert_eq!(hold.state(), MotorState::Rev(44));
+    assert!(!hold.releases(MotorState::Rev(60)));
+}
+
+#[test]
+fn adjusting_up_keeps_the_hold() {
+    let mut hold = CruiseHold::latch(MotorState::Fwd(100));
+    hold.adjust(16);
+    assert_eq!(hold.state(), MotorState::Fwd(116));
+    assert!(!hold.releases(MotorState::Fwd(100)));
+    hold.adjust(127);
+    hold.adjust(127);
+    assert_eq!(hold.state(), MotorState::Fwd(255));
+}
+
+#[test]
+fn released_by_the_lever() {
+    let hold = CruiseHold::latch(MotorState::Fwd(100));
+    assert!(hold.releases(MotorState::Fwd(101)));
+    assert!(hold.releases(MotorState::Rev(1)));
+
+    let hold = CruiseHold::latch(MotorState::Rev(100));
+    assert!(hold.releases(MotorState::Rev(101)));
+    assert!(hold.releases(MotorState::Fwd(1)));
+
+    let hold = CruiseHold::latch(MotorState::Idle(0));
+    assert!(!hold.releases(MotorState::Idle(0)));
+    assert!(hold.releases(MotorState::Fwd(1)));
+}
@@ -0,0 +1,148 @@
+#![allow(deprecated)]
+
+mod mock;
+
+use common::{FaultKind, FaultLog, FaultRecord, Flash, PendingFaults, FAULT_QUEUE_LEN};
+use mock::{MemFlash, TestLock};
+
+/// Slots in a `MemFlash` page
+const PER_PAGE: usize = MemFlash::PAGE_SIZE / 16;
+
+fn record(time: u32) -> FaultRecord {
+    FaultRecord {
+        kind: FaultKind::Stall,
+        time,
+        context: -(time as i32),
+    }
+}
+
+#[test]
+fn newest_first() {
+    let mut log = FaultLog::open(MemFlash::new(2));
+    assert_eq!(log.get(0), None);
+
+    for time in 0..3 {
+        log.record(record(time)).unwrap();
+    }
+    assert_eq!(log.get(0), Some(record(2)));
+    assert_eq!(log.get(2), Some(record(0)));
+    assert_eq!(log.get(3), None);
+}
+
+#[test]
+fn kept_over_a_reset() {
+    let flash = MemFlash::new(2);
+    let mut log = FaultLog::open(flash.clone());
+    log.record(record(1)).unwrap();
+    log.record(record(2)).unwrap();
+
+    let mut log = FaultLog::open(flash);
+    assert_eq!(log.get(0), Some(record(2)));
+    log.record(record(3)).unwrap();
+    assert_eq!(log.get(0), Some(record(3)));
+    assert_eq!(log.get(2), Some(record(1)));
+}
+
+#[test]
+fn ring_drops_the_oldest_page() {
+    let flash = MemFlash::new(3);
+    let mut log = FaultLog::open(flash.clone());
+    let total = 10 * PER_PAGE as u32 + 1;
+    for time in 0..total {
+        log.record(record(time)).unwrap();
+    }
+
+    // Two full pages and the one record in the page being filled
+    let kept = 2 * PER_PAGE + 1;
+    assert_eq!(log.get(0), Some(record(total - 1)));
+    assert_eq!(log.get(kept - 1), Some(record(total - kept as u32)));
+    assert_eq!(log.get(kept), None);
+
+    // Every page is erased as often as the others, give or take one
+    let erases = flash.erases.borrow();
+    assert!(erases.iter().max().unwrap() - erases.iter().min().unwrap() <= 1);
+
+    let log = FaultLog::open(flash.clone());
+    assert_eq!(log.get(0), Some(record(total - 1)));
+}
+
+#[test]
+fn cut_off_record_is_skipped() {
+    let mut flash = MemFlash::new(2);
+    let mut log = FaultLog::open(flash.clone());
+    log.record(record(1)).unwrap();
+    // A reset while the next slot was written
+    flash.write(16, &[0, 0, 0, 0, 4, 0]).unwrap();
+
+    let mut log = FaultLog::open(flash.clone());
+    assert_eq!(log.get(0), Some(record(1)));
+    log.record(record(2)).unwrap();
+    assert_eq!(log.get(0), Some(record(2)));
+    assert_eq!(log.get(1), Some(record(1)));
+}
+
+#[test]
+fn clear() {
+    let flash = MemFlash::new(2);
+    let mut log = FaultLog::open(flash.clone());
+    log.record(record(1)).unwrap();
+    log.clear().unwrap();
+    assert_eq!(log.get(0), None);
+    assert_eq!(FaultLog::open(flash).get(0), None);
+
+    log.record(record(2)).unwrap();
+    assert_eq!(log.get(0), Some(record(2)));
+}
+
+#[test]
+fn pending_faults_are_written_in_order() {
+    let pending = PendingFaults::<TestLock>::new();
+    let mut log = FaultLog::open(MemFlash::new(2));
+    assert!(!pending.is_pending());
+
+    pending.push(record(1));
+    pending.push(record(2));
+    // Nothing reaches the flash until the main loop commits
+    assert_eq!(log.get(0), None);
+    assert!(pending.is_pending());
+
+    pending.commit(&mut log).unwrap();
+    assert!(!pending.is_pending());
+    assert_eq!(log.get(0), Some(record(2)));
+    assert_eq!(log.get(1), Some(record(1)));
+}
+
+#[test]
+fn pending_faults_drop_what_does_not_fit() {
+    let pending = PendingFaults::<TestLock>::default();
+    let mut log = FaultLog::open(MemFlash::new(2));
+    for time in 0..FAULT_QUEUE_LEN as u32 + 2 {
+        pending.push(record(time));
+    }
+    assert_eq!(pending.dropped(), 2);
+
+    pending.commit(&mut log).unwrap();
+    assert_eq!(log.get(0), Some(record(FAULT_QUEUE_LEN as u32 - 1)));
+    assert_eq!(log.get(FAULT_QUEUE_LEN), None);
+}
+
+#[test]
+fn pending_clear_empties_the_log() {
+    let pending = PendingFaults::<TestLock>::new();
+    let mut log = FaultLog::open(MemFlash::new(2));
+    log.record(record(1)).unwrap();
+
+    pending.push(record(2));
+    pending.clear();
+    assert!(pending.is_pending());
+    // Still readable until the main loop gets to it
+    assert_eq!(log.get(0), Some(record(1)));
+
+    pending.commit(&mut log).unwrap();
+    assert_eq!(log.get(0), None);
+
+    pending.push(record(3));
+    pending.commit(&mut log).unwrap();
+    assert_eq!(log.get(0), Some(record(3)));
+    assert_eq!(log.get(1), None);
+}
@@ -0,0 +1,64 @@
+#![allow(deprecated)]
+
+mod mock;
+
+use common::{FaultKind, FaultLog, FaultRecord, Flash, FlashError, FlashRegion};
+use mock::FlashRegs;
+
+const BASE: usize = 0x0800_f800;
+
+fn region(pages: usize) -> (FlashRegion<FlashRegs>, FlashRegs) {
+    let regs = FlashRegs::new(BASE, pages);
+    (unsafe { FlashRegion::new(regs.clone(), BASE, pages) }, regs)
+}
+
+#[test]
+fn writes_half_words_and_locks_again() {
+    let (mut flash, regs) = region(2);
+    assert_eq!(flash.size(), 2048);
+    flash.write(1030, &[1, 2, 3, 4]).unwrap();
+    assert!(regs.is_locked());
+
+    let mut buf = [0; 6];
+    flash.read(1028, &mut buf);
+    assert_eq!(buf, [0xff, 0xff, 1, 2, 3, 4]);
+}
+
+#[test]
+fn erases_one_page() {
+    let (mut flash, regs) = region(2);
+    flash.write(0, &[0; 4]).unwrap();
+    flash.write(1024, &[0; 4]).unwrap();
+    flash.erase(1).unwrap();
+    assert!(regs.is_locked());
+
+    let mut buf = [0; 4];
+    flash.read(1024, &mut buf);
+    assert_eq!(buf, [0xff; 4]);
+    flash.read(0, &mut buf);
+    assert_eq!(buf, [0; 4]);
+}
+
+#[test]
+fn reports_errors_and_clears_them() {
+    let (mut flash, regs) = region(1);
+    flash.write(8, &[7, 7]).unwrap();
+    assert_eq!(flash.write(8, &[1, 1]), Err(FlashError::Program));
+    assert_eq!(regs.sr.get(), 0);
+    assert!(regs.is_locked());
+
+    regs.protected.set(true);
+    assert_eq!(flash.erase(0), Err(FlashError::Protected));
+    assert_eq!(flash.write(16, &[1, 1]), Err(FlashError::Protected));
+    assert!(regs.is_locked());
+}
+
+#[test]
+fn keeps_a_fault_log() {
+    let (flash, _) = region(2);
+    let mut log = FaultLog::open(flash);
+    let record = FaultRecord { kind: FaultKind::LinkLost, time: 1234, context: 3 };
+    log.record(record).unwrap();
+
+    assert_eq!(log.get(0), Some(record));
+}
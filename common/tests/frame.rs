@@ -3,23 +3,93 @@ use common::*;
 #[test]
 fn read_write() {
     for state in &[
-        MotorState::Off,
+        MotorState::Idle(0),
         MotorState::Idle(54),
         MotorState::Fwd(32),
         MotorState::Rev(154),
     ] {
-        for dir in &[213, 4355, 17000, 33556, 50000] {
+        for dir in &[0, 13, 128, 200, 255] {
             let frame = Frame {
                 id: 1,
+                seq: 9,
                 motor_state: *state,
-                motor_direction: MotorDirection::from_pot(*dir),
+                motor_direction: *dir,
             };
 
-            let mut buf = [0; 12];
+            let mut buf = [0; MAX_PACKET_LEN];
 
-            frame.write(&mut buf);
+            let len = frame.write(&mut buf);
 
-            assert_eq!(Some(frame), Frame::read(&buf));
+            assert_eq!(Some(frame), Frame::read(&buf[..len]));
         }
     }
 }
+
+#[test]
+fn from_pot() {
+    assert_eq!(MotorState::from_pot(0), MotorState::Rev(128));
+    assert_eq!(MotorState::from_pot(999), MotorState::Rev(0));
+    assert_eq!(MotorState::from_pot(1500), MotorState::Idle(0));
+    assert_eq!(MotorState::from_pot(2000), MotorState::Fwd(0));
+    assert_eq!(MotorState::from_pot(4096), MotorState::Fwd(255));
+}
+
+#[test]
+fn only_drive_packets_are_frames() {
+    let packet = Packet {
+        dst: 1,
+        src: MASTER,
+        seq: 0,
+        message: Message::SteerGoto(10),
+    };
+    let mut buf = [0; MAX_PACKET_LEN];
+    let len = FrameParser::write(&packet, &mut buf);
+    assert_eq!(Frame::read(&buf[..len]), None);
+    assert_eq!(Packet::read(&buf[..len]), Some(packet));
+}
+
+fn drive(seq: u8) -> Packet {
+    Frame {
+        id: 1,
+        seq,
+        motor_state: MotorState::Fwd(10),
+        motor_direction: 128,
+    }
+    .packet()
+}
+
+#[test]
+fn dedup_drops_copies() {
+    let mut dedup = Dedup::new();
+    assert!(dedup.accept(&drive(4)));
+    assert!(!dedup.accept(&drive(4)));
+    assert!(dedup.accept(&drive(5)));
+}
+
+#[test]
+fn dedup_drops_older_packets() {
+    let mut dedup = Dedup::new();
+    assert!(dedup.accept(&drive(7)));
+    assert!(dedup.accept(&drive(9)));
+    // Late on the slower link
+    assert!(!dedup.accept(&drive(8)));
+    assert!(!dedup.accept(&drive(7)));
+    assert!(dedup.accept(&drive(10)));
+}
+
+#[test]
+fn dedup_over_the_wrap() {
+    let mut dedup = Dedup::new();
+    assert!(dedup.accept(&drive(254)));
+    assert!(dedup.accept(&drive(1)));
+    assert!(!dedup.accept(&drive(255)));
+    assert!(dedup.accept(&drive(2)));
+}
+
+#[test]
+fn dedup_follows_a_restarted_controller() {
+    let mut dedup = Dedup::new();
+    assert!(dedup.accept(&drive(100)));
+    assert!(dedup.accept(&drive(0)));
+    assert!(dedup.accept(&drive(1)));
+}
@@ -0,0 +1,40 @@
+use common::*;
+
+fn v(major: u8, minor: u8, patch: u8) -> Version {
+    Version { major, minor, patch }
+}
+
+#[test]
+fn parse_version() {
+    assert_eq!(Version::parse("0.1.0"), Some(v(0, 1, 0)));
+    assert_eq!(Version::parse("12.3.45"), Some(v(12, 3, 45)));
+    assert_eq!(Version::parse("1.2.3-beta.1"), Some(v(1, 2, 3)));
+    assert_eq!(Version::parse("1.2.3+build"), Some(v(1, 2, 3)));
+
+    assert_eq!(Version::parse(""), None);
+    assert_eq!(Version::parse("1.2"), None);
+    assert_eq!(Version::parse("1.2.3.4"), None);
+    assert_eq!(Version::parse("1.x.3"), None);
+    assert_eq!(Version::parse("256.0.0"), None);
+}
+
+#[test]
+fn supported_versions() {
+    assert!(v(1, 2, 3).supports(v(1, 2, 3)));
+    assert!(v(1, 4, 0).supports(v(1, 2, 3)));
+    assert!(!v(1, 2, 2).supports(v(1, 2, 3)));
+    assert!(!v(2, 0, 0).supports(v(1, 2, 3)));
+
+    // Every minor version breaks before 1.0
+    assert!(v(0, 1, 4).supports(v(0, 1, 0)));
+    assert!(!v(0, 2, 0).supports(v(0, 1, 0)));
+}
+
+#[test]
+fn parse_git_hash() {
+    assert_eq!(git_hash("1a2b3c4d"), 0x1a2b_3c4d);
+    // Longer hashes are cut to 8 digits
+    assert_eq!(git_hash("1a2b3c4d5e6f"), 0x1a2b_3c4d);
+    assert_eq!(git_hash(""), 0);
+    assert_eq!(git_hash("unknown"), 0);
+}
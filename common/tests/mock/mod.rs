@@ -0,0 +1,421 @@
+//! Host stand-ins for the embedded-hal traits the firmware is generic over,
+//! plus a simple model of a linear actuator to drive them, flash kept in
+//! memory and the flash interface registers in front of it.
+
+#![allow(dead_code)]
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::Mutex;
+
+use common::{Flash, FlashError, FlashRegisters, Lock};
+use embedded_hal::adc::{Channel, OneShot};
+use embedded_hal::digital::{InputPin, OutputPin};
+
+/// Takes turns with the other tests instead of holding off interrupts
+pub struct TestLock;
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+impl Lock for TestLock {
+    fn lock<R, F: FnOnce() -> R>(f: F) -> R {
+        let _guard = LOCK.lock().unwrap();
+        f()
+    }
+}
+
+/// A pin whose level is shared with the test, clones see the same level
+#[derive(Clone, Default)]
+pub struct Pin(Rc<Cell<bool>>);
+
+impl Pin {
+    pub fn new(high: bool) -> Pin {
+        Pin(Rc::new(Cell::new(high)))
+    }
+
+    pub fn set(&self, high: bool) {
+        self.0.set(high);
+    }
+
+    pub fn get(&self) -> bool {
+        self.0.get()
+    }
+}
+
+impl OutputPin for Pin {
+    fn set_low(&mut self) {
+        self.0.set(false);
+    }
+
+    fn set_high(&mut self) {
+        self.0.set(true);
+    }
+}
+
+impl InputPin for Pin {
+    fn is_high(&self) -> bool {
+        self.0.get()
+    }
+
+    fn is_low(&self) -> bool {
+        !self.0.get()
+    }
+}
+
+/// Adc that reads whatever the test puts in `value`
+#[derive(Clone, Default)]
+pub struct Adc {
+    pub value: Rc<Cell<u16>>,
+}
+
+pub struct AdcChannel;
+
+impl Channel<Adc> for AdcChannel {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        0
+    }
+}
+
+impl OneShot<Adc, u16, AdcChannel> for Adc {
+    type Error = ();
+
+    fn read(&mut self, _pin: &mut AdcChannel) -> nb::Result<u16, ()> {
+        Ok(self.value.get())
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Drive {
+    Stop,
+    Fwd,
+    Rev,
+    /// Both bridge inputs high, never allowed
+    Short,
+}
+
+/// An actuator with a position sensor. `in1` high drives it forward, which
+/// lowers the sensor reading, `in2` high drives it back up. `reversed` swaps
+/// the two, as a motor wired the other way round would.
+pub struct Plant {
+    pub in1: Pin,
+    pub in2: Pin,
+    pub adc: Adc,
+    /// Sensor counts moved per step
+    pub speed: u16,
+    /// Mechanical ends of travel in sensor counts
+    pub travel: (u16, u16),
+    pub reversed: bool,
+}
+
+impl Plant {
+    pub fn new(position: u16) -> Plant {
+        let plant = Plant {
+            in1: Pin::new(false),
+            in2: Pin::new(false),
+            adc: Adc::default(),
+            speed: 8,
+            travel: (0, 4095),
+            reversed: false,
+        };
+        plant.adc.value.set(position);
+        plant
+    }
+
+    pub fn position(&self) -> u16 {
+        self.adc.value.get()
+    }
+
+    pub fn drive(&self) -> Drive {
+        match (self.in1.get(), self.in2.get()) {
+            (false, false) => Drive::Stop,
+            (true, false) => Drive::Fwd,
+            (false, true) => Drive::Rev,
+            (true, true) => Drive::Short,
+        }
+    }
+
+    /// Moves the actuator as far as it gets in one tick
+    pub fn step(&self) {
+        let pos = self.position();
+        let down = match self.drive() {
+            Drive::Fwd => !self.reversed,
+            Drive::Rev => self.reversed,
+            Drive::Stop => return,
+            Drive::Short => panic!("both bridge inputs high"),
+        };
+        let pos = if down {
+            pos.saturating_sub(self.speed).max(self.travel.0)
+        }
+        else {
+            (pos + self.speed).min(self.travel.1)
+        };
+        self.adc.value.set(pos);
+    }
+}
+
+/// Limit switch at one end of a plant's travel. It closes when the plant is at
+/// or past `at`, closed pulls the pin low unless `active_high` is set.
+pub struct Switch {
+    pub pin: Pin,
+    /// Position of the switch, `None` when not fitted
+    pub at: Option<u16>,
+    /// The switch sits at the high end of the sensor range
+    pub high_end: bool,
+    pub active_high: bool,
+}
+
+impl Switch {
+    /// Switch at the forward end, where the sensor reads low
+    pub fn fwd(at: Option<u16>) -> Switch {
+        Switch::new(at, false)
+    }
+
+    /// Switch at the reverse end, where the sensor reads high
+    pub fn rev(at: Option<u16>) -> Switch {
+        Switch::new(at, true)
+    }
+
+    fn new(at: Option<u16>, high_end: bool) -> Switch {
+        Switch {
+            pin: Pin::new(true),
+            at,
+            high_end,
+            active_high: false,
+        }
+    }
+
+    pub fn is_closed(&self, plant: &Plant) -> bool {
+        match self.at {
+            Some(at) if self.high_end => plant.position() >= at,
+            Some(at) => plant.position() <= at,
+            None => false,
+        }
+    }
+
+    pub fn update(&self, plant: &Plant) {
+        self.pin.set(self.is_closed(plant) == self.active_high);
+    }
+}
+
+/// A stepper motor driver. Each rising edge on `pul` while `ena` is high moves
+/// it a step, right with `dir` low. The limit switches pull their pins high at
+/// or past the ends of `travel`.
+pub struct Motor {
+    pub ena: Pin,
+    pub dir: Pin,
+    pub pul: Pin,
+    pub lim_l: Pin,
+    pub lim_r: Pin,
+    pub position: i32,
+    /// Positions of the limit switches
+    pub travel: (i32, i32),
+    /// Pulses seen while disabled
+    pub lost: u32,
+    last_pul: bool,
+}
+
+impl Motor {
+    pub fn new(position: i32, travel: (i32, i32)) -> Motor {
+        let motor = Motor {
+            ena: Pin::new(false),
+            dir: Pin::new(false),
+            pul: Pin::new(false),
+            lim_l: Pin::new(false),
+            lim_r: Pin::new(false),
+            position,
+            travel,
+            lost: 0,
+            last_pul: false,
+        };
+        motor.update_switches();
+        motor
+    }
+
+    /// Follows the pins after a tick
+    pub fn update(&mut self) {
+        let pul = self.pul.get();
+        if pul && !self.last_pul {
+            if !self.ena.get() {
+                self.lost += 1;
+            }
+            else if self.dir.get() {
+                self.position -= 1;
+            }
+            else {
+                self.position += 1;
+            }
+        }
+        self.last_pul = pul;
+        self.update_switches();
+    }
+
+    fn update_switches(&self) {
+        self.lim_l.set(self.position <= self.travel.0);
+        self.lim_r.set(self.position >= self.travel.1);
+    }
+}
+
+/// Flash kept in memory, clones share it. Like real flash a write can only
+/// clear bits, so writing anything but erased flash fails.
+#[derive(Clone)]
+pub struct MemFlash {
+    pub bytes: Rc<RefCell<Vec<u8>>>,
+    /// Times each page was erased
+    pub erases: Rc<RefCell<Vec<u32>>>,
+}
+
+impl MemFlash {
+    pub const PAGE_SIZE: usize = 64;
+
+    pub fn new(pages: usize) -> MemFlash {
+        MemFlash {
+            bytes: Rc::new(RefCell::new(vec![0xff; pages * Self::PAGE_SIZE])),
+            erases: Rc::new(RefCell::new(vec![0; pages])),
+        }
+    }
+}
+
+impl Flash for MemFlash {
+    const PAGE_SIZE: usize = MemFlash::PAGE_SIZE;
+
+    fn size(&self) -> usize {
+        self.bytes.borrow().len()
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.bytes.borrow()[offset..offset + buf.len()]);
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+        assert!(offset % 2 == 0 && data.len() % 2 == 0);
+        let mut bytes = self.bytes.borrow_mut();
+        for (i, &b) in data.iter().enumerate() {
+            if bytes[offset + i] != 0xff {
+                return Err(FlashError::Program);
+            }
+            bytes[offset + i] = b;
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, page: usize) -> Result<(), FlashError> {
+        let start = page * Self::PAGE_SIZE;
+        for b in &mut self.bytes.borrow_mut()[start..start + Self::PAGE_SIZE] {
+            *b = 0xff;
+        }
+        self.erases.borrow_mut()[page] += 1;
+        Ok(())
+    }
+}
+
+/// The flash interface of an STM32F1 in front of `pages` of flash at `base`.
+/// Clones share the flash and the registers.
+#[derive(Clone)]
+pub struct FlashRegs {
+    pub base: usize,
+    pub bytes: Rc<RefCell<Vec<u8>>>,
+    pub cr: Rc<Cell<u32>>,
+    pub ar: Rc<Cell<u32>>,
+    pub sr: Rc<Cell<u32>>,
+    /// Keys written since the last lock
+    pub keys: Rc<RefCell<Vec<u32>>>,
+    /// Makes every erase and program fail with WRPRTERR
+    pub protected: Rc<Cell<bool>>,
+}
+
+impl FlashRegs {
+    const PG: u32 = 1 << 0;
+    const PER: u32 = 1 << 1;
+    const STRT: u32 = 1 << 6;
+    const LOCK: u32 = 1 << 7;
+    const PGERR: u32 = 1 << 2;
+    const WRPRTERR: u32 = 1 << 4;
+    const EOP: u32 = 1 << 5;
+
+    pub fn new(base: usize, pages: usize) -> FlashRegs {
+        FlashRegs {
+            base,
+            bytes: Rc::new(RefCell::new(vec![0xff; pages * Self::PAGE_SIZE])),
+            cr: Rc::new(Cell::new(Self::LOCK)),
+            ar: Rc::new(Cell::new(0)),
+            sr: Rc::new(Cell::new(0)),
+            keys: Rc::new(RefCell::new(Vec::new())),
+            protected: Rc::new(Cell::new(false)),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.cr.get() & Self::LOCK != 0
+    }
+
+    fn finish(&self, flags: u32) {
+        self.sr.set(self.sr.get() | flags);
+    }
+}
+
+impl FlashRegisters for FlashRegs {
+    const PAGE_SIZE: usize = 1024;
+
+    fn write_keyr(&self, key: u32) {
+        let mut keys = self.keys.borrow_mut();
+        keys.push(key);
+        if keys[..] == [0x4567_0123, 0xcdef_89ab] {
+            self.cr.set(self.cr.get() & !Self::LOCK);
+        }
+    }
+
+    fn write_cr(&self, cr: u32) {
+        assert!(!self.is_locked(), "CR written while locked");
+        self.cr.set(cr);
+        if cr & Self::LOCK != 0 {
+            self.keys.borrow_mut().clear();
+        }
+        if cr & (Self::PER | Self::STRT) == Self::PER | Self::STRT {
+            if self.protected.get() {
+                return self.finish(Self::WRPRTERR);
+            }
+            let start = self.ar.get() as usize - self.base;
+            assert_eq!(start % Self::PAGE_SIZE, 0);
+            for b in &mut self.bytes.borrow_mut()[start..start + Self::PAGE_SIZE] {
+                *b = 0xff;
+            }
+            self.finish(Self::EOP);
+        }
+    }
+
+    fn write_ar(&self, address: u32) {
+        self.ar.set(address);
+    }
+
+    fn read_sr(&self) -> u32 {
+        self.sr.get()
+    }
+
+    fn write_sr(&self, sr: u32) {
+        self.sr.set(self.sr.get() & !sr);
+    }
+
+    fn read_byte(&self, address: usize) -> u8 {
+        self.bytes.borrow()[address - self.base]
+    }
+
+    fn write_half(&self, address: usize, half: u16) {
+        assert_eq!(self.cr.get(), Self::PG, "programmed without PG");
+        assert_eq!(address % 2, 0);
+        if self.protected.get() {
+            return self.finish(Self::WRPRTERR);
+        }
+        let mut bytes = self.bytes.borrow_mut();
+        let i = address - self.base;
+        if bytes[i] != 0xff || bytes[i + 1] != 0xff {
+            drop(bytes);
+            return self.finish(Self::PGERR);
+        }
+        bytes[i] = half as u8;
+        bytes[i + 1] = (half >> 8) as u8;
+        drop(bytes);
+        self.finish(Self::EOP);
+    }
+}
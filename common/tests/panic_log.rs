@@ -0,0 +1,57 @@
+use std::panic::Location;
+
+use common::*;
+
+#[test]
+fn keeps_the_message_once() {
+    let mut log = PanicLog::new();
+    assert_eq!(log.take(), None);
+
+    log.record(format_args!("index out of bounds: {}", 7));
+    assert_eq!(log.take().unwrap().as_str(), "index out of bounds: 7");
+    // A reset after that is not a panic
+    assert_eq!(log.take(), None);
+}
+
+#[test]
+fn long_messages_are_cut() {
+    let mut log = PanicLog::new();
+    let long = "x".repeat(100);
+    log.record(&long);
+    assert_eq!(log.take().unwrap().as_str(), &long[..PANIC_TEXT_LEN]);
+
+    // Never in the middle of a character
+    let text = PanicText::new(&"å".repeat(PANIC_TEXT_LEN));
+    assert_eq!(text.as_str(), "å".repeat(PANIC_TEXT_LEN / 2));
+}
+
+#[test]
+fn message_before_location() {
+    let mut log = PanicLog::default();
+    let location = Location::caller();
+    log.record_at("stall", Some(location));
+    // As much of the location as fits
+    let whole = format!("stall at {}", location);
+    assert_eq!(log.take().unwrap().as_str(), &whole[..whole.len().min(PANIC_TEXT_LEN)]);
+
+    log.record_at("stall", None);
+    assert_eq!(log.take().unwrap().as_str(), "stall");
+}
+
+#[test]
+fn short_message_survives_a_long_location() {
+    let mut log = PanicLog::new();
+    // Cut in the middle of the location, the message is whole
+    let file = "src/".repeat(PANIC_TEXT_LEN);
+    log.record(format_args!("{} at {}:12:5", "alarm", file));
+    let text = log.take().unwrap();
+    assert_eq!(text.as_str().len(), PANIC_TEXT_LEN);
+    assert!(text.as_str().starts_with("alarm at src/"));
+}
+
+#[test]
+fn text() {
+    assert!(PanicText::empty().is_empty());
+    assert_eq!(PanicText::empty().as_str(), "");
+    assert_eq!(PanicText::new("stepper alarm").as_str(), "stepper alarm");
+}
@@ -0,0 +1,238 @@
+use common::*;
+use proptest::prelude::*;
+
+fn motor_state() -> impl Strategy<Value = MotorState> {
+    prop_oneof![
+        any::<u8>().prop_map(MotorState::Idle),
+        any::<u8>().prop_map(MotorState::Fwd),
+        any::<u8>().prop_map(MotorState::Rev),
+    ]
+}
+
+fn frame() -> impl Strategy<Value = Frame> {
+    (any::<u8>(), any::<u8>(), motor_state(), any::<u8>()).prop_map(|(id, seq, motor_state, motor_direction)| Frame {
+        id,
+        seq,
+        motor_state,
+        motor_direction,
+    })
+}
+
+fn encode(frame: &Frame) -> Vec<u8> {
+    let mut buf = [0; MAX_PACKET_LEN];
+    let len = frame.write(&mut buf);
+    buf[..len].to_vec()
+}
+
+fn encode_cobs(frame: &Frame) -> Vec<u8> {
+    let mut buf = [0; MAX_PACKET_LEN];
+    let len = CobsParser::write(frame, &mut buf);
+    buf[..len].to_vec()
+}
+
+/// Anything that comes out has to be a packet that was fed, framed the same way
+fn never_misread<F: Framing>(bytes: &[u8]) -> Result<(), TestCaseError> {
+    let mut parser = F::default();
+    for (i, b) in bytes.iter().enumerate() {
+        if let Ok(Some(packet)) = parser.push(*b) {
+            let mut buf = [0; MAX_PACKET_LEN];
+            let len = F::write(&packet, &mut buf);
+            let encoded = &buf[..len];
+            prop_assert!(bytes[..=i].windows(len).any(|w| w == encoded));
+        }
+    }
+    Ok(())
+}
+
+/// Feeds `bytes` and returns every frame that came out
+fn parse(parser: &mut FrameParser, bytes: &[u8]) -> Vec<Frame> {
+    bytes.iter().filter_map(|b| parser.feed(*b)).collect()
+}
+
+proptest! {
+    #[test]
+    fn frame_round_trip(frame in frame()) {
+        let bytes = encode(&frame);
+        prop_assert_eq!(Frame::read(&bytes), Some(frame));
+
+        let mut parser = FrameParser::new();
+        let (last, rest) = bytes.split_last().unwrap();
+        prop_assert!(parse(&mut parser, rest).is_empty());
+        prop_assert_eq!(parser.feed(*last), Some(frame));
+    }
+
+    #[test]
+    fn random_bytes_never_misread(bytes in proptest::collection::vec(any::<u8>(), 0..512)) {
+        never_misread::<FrameParser>(&bytes)?;
+        never_misread::<CobsParser>(&bytes)?;
+    }
+
+    #[test]
+    fn frames_in_noise_never_misread(
+        chunks in proptest::collection::vec((proptest::collection::vec(any::<u8>(), 0..16), frame()), 0..8),
+    ) {
+        let mut bytes = Vec::new();
+        let mut cobs = Vec::new();
+        for (noise, frame) in &chunks {
+            bytes.extend(noise);
+            bytes.extend(encode(frame));
+            cobs.extend(noise);
+            cobs.extend(encode_cobs(frame));
+        }
+        never_misread::<FrameParser>(&bytes)?;
+        never_misread::<CobsParser>(&cobs)?;
+    }
+
+    #[test]
+    fn starts_mid_frame(cut in 1usize..11, a in frame(), b in frame(), c in frame()) {
+        let a = encode(&a);
+        let mut stream = a[cut..].to_vec();
+        stream.extend(encode(&b));
+        stream.extend(encode(&c));
+        stream.extend(&[0; MAX_PACKET_LEN]);
+
+        let mut parser = FrameParser::new();
+        prop_assert_eq!(parse(&mut parser, &stream), vec![b, c]);
+    }
+
+    #[test]
+    fn cobs_starts_mid_frame(cut in 1usize..11, a in frame(), b in frame()) {
+        let a = encode_cobs(&a);
+        let mut stream = a[cut..].to_vec();
+        stream.extend(encode_cobs(&b));
+
+        let mut parser = CobsParser::new();
+        let frames: Vec<_> = stream.iter().filter_map(|x| parser.feed(*x)).collect();
+        prop_assert_eq!(frames, vec![b]);
+    }
+
+    #[test]
+    fn finds_frame_after_noise(
+        noise in proptest::collection::vec(any::<u8>().prop_filter("no preamble", |b| *b != 0xa3), 0..64),
+        frame in frame(),
+    ) {
+        let mut parser = FrameParser::new();
+        prop_assert!(parse(&mut parser, &noise).is_empty());
+        prop_assert_eq!(parse(&mut parser, &encode(&frame)), vec![frame]);
+    }
+
+    #[test]
+    fn resyncs_after_truncated_frame(cut in 1usize..11, a in frame(), b in frame(), c in frame()) {
+        let a = encode(&a);
+        let mut stream = a[..cut].to_vec();
+        stream.extend(encode(&b));
+        stream.extend(encode(&c));
+        // The start of b can happen to be the missing end of a
+        prop_assume!(stream[..a.len()] != a[..]);
+        // A bogus header can leave the parser waiting for more bytes, on a
+        // real link the next frames push it through
+        stream.extend(&[0; MAX_PACKET_LEN]);
+
+        let mut parser = FrameParser::new();
+        prop_assert_eq!(parse(&mut parser, &stream), vec![b, c]);
+    }
+}
+
+#[test]
+fn reports_errors() {
+    let frame = Frame {
+        id: 1,
+        seq: 7,
+        motor_state: MotorState::Fwd(100),
+        motor_direction: 128,
+    };
+    let mut bytes = encode(&frame);
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+
+    let mut parser = FrameParser::new();
+    let results: Vec<_> = bytes.iter().map(|b| parser.push(*b)).collect();
+    assert_eq!(results[last], Err(ParseError::BadChecksum));
+    assert_eq!(parser.errors(), 1);
+
+    let mut parser = FrameParser::new();
+    assert_eq!(parser.push(0xa3), Ok(None));
+    assert_eq!(parser.push(0xc9), Ok(None));
+    assert_eq!(parser.push(0x3d), Ok(None));
+    assert_eq!(parser.push(1), Ok(None));
+    assert_eq!(parser.push(MASTER), Ok(None));
+    assert_eq!(parser.push(0), Ok(None));
+    assert_eq!(parser.push(2), Ok(None));
+    assert_eq!(parser.push(200), Err(ParseError::BadLength(200)));
+}
+
+#[test]
+fn drains_frames_found_in_a_failed_one() {
+    let b = Frame {
+        id: 1,
+        seq: 7,
+        motor_state: MotorState::Fwd(100),
+        motor_direction: 128,
+    };
+    let c = Frame { id: 2, ..b };
+    let (b_bytes, c_bytes) = (encode(&b), encode(&c));
+
+    // A header whose length swallows b and c, and a checksum that fails
+    let mut stream = PREAMBLE.to_vec();
+    stream.extend(&[1, MASTER, 0, 0, (b_bytes.len() + c_bytes.len()) as u8]);
+    stream.extend(&b_bytes);
+    stream.extend(&c_bytes);
+    stream.extend(&[0, 0]);
+
+    let mut parser = FrameParser::new();
+    let mut packets: Vec<_> = stream.iter().filter_map(|x| parser.feed_packet(*x)).collect();
+    assert_eq!(packets, vec![b.packet()]);
+    // c is already complete, it does not wait for more bytes
+    packets.extend(std::iter::from_fn(|| parser.pending()));
+    assert_eq!(packets, vec![b.packet(), c.packet()]);
+    assert_eq!(parser.errors(), 1);
+}
+
+proptest! {
+    #[test]
+    fn cobs_round_trip(frame in frame()) {
+        let bytes = encode_cobs(&frame);
+        prop_assert_eq!(bytes.iter().position(|b| *b == 0), Some(bytes.len() - 1));
+        prop_assert_eq!(CobsParser::read(&bytes), Some(frame.packet()));
+    }
+
+    #[test]
+    fn cobs_resyncs_after_noise(
+        noise in proptest::collection::vec(any::<u8>(), 0..64),
+        a in frame(),
+        b in frame(),
+    ) {
+        let mut stream = noise;
+        stream.push(0);
+        stream.extend(encode_cobs(&a));
+        stream.extend(encode_cobs(&b));
+
+        let mut parser = CobsParser::new();
+        let frames: Vec<_> = stream.iter().filter_map(|b| parser.feed(*b)).collect();
+        prop_assert_eq!(&frames[frames.len().saturating_sub(2)..], &[a, b][..]);
+    }
+}
+
+#[test]
+fn cobs_reports_errors() {
+    let ack = Packet { dst: MASTER, src: 2, seq: 0, message: Message::BaudAck(57_600) };
+    let mut buf = [0; MAX_PACKET_LEN];
+    let len = CobsParser::write(&ack, &mut buf);
+    let mut parser = CobsParser::new();
+    let packets: Vec<_> = buf[..len].iter().filter_map(|b| parser.feed_packet(*b)).collect();
+    assert_eq!(packets, vec![ack]);
+
+    // A code byte that points past the delimiter
+    assert_eq!(parser.push(9), Ok(None));
+    assert_eq!(parser.push(1), Ok(None));
+    assert_eq!(parser.push(0), Err(ParseError::BadEncoding));
+
+    // A packet that never ends is dropped up to the next delimiter
+    for _ in 0..MAX_PACKET_LEN {
+        assert_eq!(parser.push(1), Ok(None));
+    }
+    assert_eq!(parser.push(1), Err(ParseError::BadLength(MAX_BODY_LEN as u8)));
+    assert_eq!(parser.push(1), Ok(None));
+    assert_eq!(parser.push(0), Ok(None));
+    assert_eq!(parser.errors(), 2);
+}
@@ -0,0 +1,220 @@
+use common::*;
+use proptest::prelude::*;
+
+fn motor_state() -> impl Strategy<Value = MotorState> {
+    prop_oneof![
+        any::<u8>().prop_map(MotorState::Idle),
+        any::<u8>().prop_map(MotorState::Fwd),
+        any::<u8>().prop_map(MotorState::Rev),
+    ]
+}
+
+fn fault() -> impl Strategy<Value = Fault> {
+    prop_oneof![
+        Just(Fault::StepperAlarm),
+        Just(Fault::Limit),
+        Just(Fault::LinkLost),
+    ]
+}
+
+fn stepper_state() -> impl Strategy<Value = StepperState> {
+    prop_oneof![
+        Just(StepperState::Unzeroed),
+        Just(StepperState::Zeroing),
+        Just(StepperState::Ready),
+        Just(StepperState::Alarm),
+    ]
+}
+
+fn reset_reason() -> impl Strategy<Value = ResetReason> {
+    prop_oneof![
+        Just(ResetReason::PowerOn),
+        Just(ResetReason::Pin),
+        Just(ResetReason::Watchdog),
+        Just(ResetReason::Software),
+        Just(ResetReason::LowPower),
+        Just(ResetReason::Unknown),
+    ]
+}
+
+fn identity() -> impl Strategy<Value = Identity> {
+    let kind = prop_oneof![Just(NodeKind::LeftDriver), Just(NodeKind::RightDriver), Just(NodeKind::Steering)];
+    let version = any::<(u8, u8, u8)>().prop_map(|(major, minor, patch)| Version { major, minor, patch });
+    (kind, any::<u8>(), version, any::<u32>(), any::<u16>(), any::<u32>(), reset_reason()).prop_map(
+        |(kind, id, version, git_hash, calibration, uptime, reset)| Identity {
+            kind,
+            id,
+            version,
+            git_hash,
+            calibration,
+            uptime,
+            reset,
+        },
+    )
+}
+
+fn fault_record() -> impl Strategy<Value = FaultRecord> {
+    let kind = prop_oneof![
+        Just(FaultKind::Stall),
+        Just(FaultKind::StepperAlarm),
+        Just(FaultKind::LinkLost),
+        Just(FaultKind::EStop),
+        Just(FaultKind::WatchdogReset),
+        Just(FaultKind::Panic),
+    ];
+    (kind, any::<u32>(), any::<i32>()).prop_map(|(kind, time, context)| FaultRecord { kind, time, context })
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    prop_oneof![
+        (motor_state(), any::<u8>()).prop_map(|(motor_state, direction)| Message::Drive { motor_state, direction }),
+        Just(Message::EStop),
+        Just(Message::EngineStart),
+        Just(Message::EngineStop),
+        Just(Message::Calibrate),
+        Just(Message::SteerZero),
+        Just(Message::Ping),
+        Just(Message::Pong),
+        Just(Message::Identify),
+        identity().prop_map(Message::Identity),
+        Just(Message::GetPanic),
+        ".{0,40}".prop_map(|s| Message::Panic(PanicText::new(&s))),
+        any::<u8>().prop_map(Message::GetFault),
+        (any::<u8>(), proptest::option::of(fault_record()))
+            .prop_map(|(index, record)| Message::LoggedFault { index, record }),
+        Just(Message::ClearFaults),
+        Just(Message::GetUsage),
+        any::<(u32, u32, u32, u32)>().prop_map(|(run_seconds, starts, shifts, steps)| Message::Usage(Usage {
+            run_seconds,
+            starts,
+            shifts,
+            steps,
+        })),
+        any::<i32>().prop_map(Message::SteerGoto),
+        any::<u32>().prop_map(Message::BaudPropose),
+        any::<u32>().prop_map(Message::BaudAck),
+        any::<u32>().prop_map(Message::TimeSync),
+        (motor_state(), any::<i32>(), any::<u32>(), reset_reason(), any::<u16>()).prop_map(
+            |(motor_state, steering, time, reset, dropped)| Message::Telemetry {
+                motor_state,
+                steering,
+                time,
+                reset,
+                dropped,
+            },
+        ),
+        (stepper_state(), any::<i32>(), any::<u32>(), reset_reason(), any::<u16>()).prop_map(
+            |(state, position, time, reset, dropped)| Message::SteerStatus { state, position, time, reset, dropped },
+        ),
+        fault().prop_map(Message::Error),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn message_round_trip(message in message()) {
+        // One more than a payload can hold, to try a payload that is too long
+        let mut buf = [0; MAX_PAYLOAD + 1];
+        let len = message.write(&mut buf);
+        prop_assert!(len <= MAX_PAYLOAD);
+        prop_assert_eq!(Message::read(message.tag(), &buf[..len]), Some(message));
+        // A payload of the wrong length is never accepted
+        prop_assert_eq!(Message::read(message.tag(), &buf[..len + 1]), None);
+    }
+
+    #[test]
+    fn packet_round_trip(dst in any::<u8>(), src in any::<u8>(), seq in any::<u8>(), message in message()) {
+        let packet = Packet { dst, src, seq, message };
+        let mut buf = [0; MAX_PACKET_LEN];
+        let len = FrameParser::write(&packet, &mut buf);
+        prop_assert_eq!(Packet::read(&buf[..len]), Some(packet));
+        let len = CobsParser::write(&packet, &mut buf);
+        prop_assert_eq!(CobsParser::read(&buf[..len]), Some(packet));
+    }
+}
+
+#[test]
+fn rejects_unknown() {
+    assert_eq!(Message::read(0xff, &[]), None);
+    // Motor state kind out of range
+    assert_eq!(Message::read(0x01, &[3, 0, 0]), None);
+    // Unknown fault code
+    assert_eq!(Message::read(0x28, &[0]), None);
+    // Unknown node kind
+    assert_eq!(Message::read(0x33, &[0; 16]), None);
+    // Panic text longer than it can be
+    assert_eq!(Message::read(0x35, &[PANIC_TEXT_LEN as u8 + 1; PANIC_TEXT_LEN + 1]), None);
+    // Unknown fault kind, and a record that is neither there nor not
+    assert_eq!(Message::read(0x37, &[0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+    assert_eq!(Message::read(0x37, &[0, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+}
+
+#[test]
+fn frame_is_a_drive_packet() {
+    let frame = Frame {
+        id: 2,
+        seq: 9,
+        motor_state: MotorState::Rev(40),
+        motor_direction: 200,
+    };
+    assert_eq!(Frame::from_packet(&frame.packet()), Some(frame));
+    let estop = Packet { dst: 2, src: MASTER, seq: 9, message: Message::EStop };
+    assert_eq!(Frame::from_packet(&estop), None);
+}
+
+fn record() -> impl Strategy<Value = Record> {
+    let direction = prop_oneof![Just(Direction::Sent), Just(Direction::Received)];
+    (any::<u32>(), direction, any::<u8>(), any::<u8>(), any::<u8>(), message()).prop_map(
+        |(time, direction, dst, src, seq, message)| Record {
+            time,
+            direction,
+            packet: Packet { dst, src, seq, message },
+        },
+    )
+}
+
+fn capture(records: &[Record]) -> Vec<u8> {
+    let mut bytes = vec![0; CAPTURE_HEADER_LEN];
+    write_capture_header(&mut bytes, 1_700_000_000_000);
+    for record in records {
+        let mut buf = [0; MAX_RECORD_LEN];
+        let len = record.write(&mut buf);
+        bytes.extend(&buf[..len]);
+    }
+    bytes
+}
+
+proptest! {
+    #[test]
+    fn capture_round_trip(records in proptest::collection::vec(record(), 0..16)) {
+        let bytes = capture(&records);
+        let read: Result<Vec<_>, _> = CaptureReader::new(&bytes).unwrap().collect();
+        prop_assert_eq!(read, Ok(records));
+    }
+
+    #[test]
+    fn truncated_capture(records in proptest::collection::vec(record(), 1..8), cut in 1usize..8) {
+        let bytes = capture(&records);
+        let read: Vec<_> = CaptureReader::new(&bytes[..bytes.len() - cut]).unwrap().collect();
+        prop_assert_eq!(read.len(), records.len());
+        prop_assert_eq!(read.last(), Some(&Err(CaptureError::Truncated)));
+    }
+}
+
+#[test]
+fn capture_header() {
+    let start = b"\x00\x68\xe5\xcf\x8b\x01\x00\x00";
+    let header = |version: &[u8]| [&b"BCAP"[..], version, start].concat();
+    assert_eq!(CaptureReader::new(b"BCAP").err(), Some(CaptureError::BadHeader));
+    assert_eq!(CaptureReader::new(&header(b"\x04")).err(), Some(CaptureError::BadHeader));
+    // Version 1 packets had no source address, version 2 had no start time
+    assert_eq!(CaptureReader::new(&header(b"\x01")).err(), Some(CaptureError::BadHeader));
+    assert_eq!(CaptureReader::new(b"BCAP\x02").err(), Some(CaptureError::BadHeader));
+    let bad_magic = [&b"XCAP\x03"[..], start].concat();
+    assert_eq!(CaptureReader::new(&bad_magic).err(), Some(CaptureError::BadHeader));
+
+    let good = header(b"\x03");
+    let reader = CaptureReader::new(&good).unwrap();
+    assert_eq!(reader.start(), 1_700_000_000_000);
+    assert_eq!(reader.count(), 0);
+}
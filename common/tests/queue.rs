@@ -0,0 +1,48 @@
+use common::Queue;
+
+#[test]
+fn first_in_first_out() {
+    let mut queue: Queue<u32, 4> = Queue::new();
+    assert_eq!(queue.pop(), None);
+    for round in 0..12 {
+        queue.push(round).unwrap();
+        queue.push(round + 100).unwrap();
+        assert_eq!(queue.pop(), Some(round));
+        assert_eq!(queue.pop(), Some(round + 100));
+    }
+    assert_eq!(queue.pop(), None);
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn counts_what_did_not_fit() {
+    let mut queue: Queue<usize, 8> = Queue::default();
+    for i in 0..8 {
+        queue.push(i).unwrap();
+    }
+    assert_eq!(queue.push(99), Err(99));
+    assert_eq!(queue.push(98), Err(98));
+    assert_eq!(queue.len(), 8);
+
+    // Room again once something is taken out
+    assert_eq!(queue.pop(), Some(0));
+    queue.push(8).unwrap();
+    let rest: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+    assert_eq!(rest, (1..=8).collect::<Vec<_>>());
+    assert_eq!(queue.dropped(), 2);
+}
+
+#[test]
+fn any_length() {
+    let mut queue: Queue<u8, 3> = Queue::new();
+    for round in 0..10 {
+        for i in 0..3 {
+            queue.push(round + i).unwrap();
+        }
+        assert_eq!(queue.push(0), Err(0));
+        for i in 0..3 {
+            assert_eq!(queue.pop(), Some(round + i));
+        }
+    }
+    assert_eq!(queue.dropped(), 10);
+}
@@ -0,0 +1,63 @@
+#![allow(deprecated)]
+
+mod mock;
+
+use common::{EngineRelays, RelayState};
+use mock::Pin;
+
+fn relays() -> (EngineRelays<Pin, Pin>, Pin, Pin) {
+    let start = Pin::new(true);
+    let stop = Pin::new(true);
+    let relays = EngineRelays::new(start.clone(), stop.clone(), 3, 5);
+    (relays, start, stop)
+}
+
+#[test]
+fn open_at_first() {
+    let (relays, start, stop) = relays();
+    assert_eq!(relays.state(), RelayState::Open);
+    assert!(!start.get());
+    assert!(!stop.get());
+}
+
+#[test]
+fn cranks_for_a_while() {
+    let (mut relays, start, stop) = relays();
+    assert!(relays.start());
+    for _ in 0..2 {
+        relays.tick();
+        assert!(start.get());
+        assert!(!stop.get());
+    }
+    relays.tick();
+    assert!(!start.get());
+    assert_eq!(relays.state(), RelayState::Open);
+}
+
+#[test]
+fn start_is_not_restarted() {
+    let (mut relays, start, _) = relays();
+    assert!(relays.start());
+    relays.tick();
+    relays.tick();
+    assert!(!relays.start());
+    relays.tick();
+    assert!(!start.get());
+}
+
+#[test]
+fn stop_lets_go_of_the_starter() {
+    let (mut relays, start, stop) = relays();
+    relays.start();
+    relays.stop();
+    assert!(!start.get());
+    assert!(stop.get());
+    assert_eq!(relays.state(), RelayState::Stopping);
+    // No start while stopping
+    assert!(!relays.start());
+    for _ in 0..5 {
+        relays.tick();
+    }
+    assert!(!stop.get());
+    assert!(relays.start());
+}
@@ -0,0 +1,241 @@
+#![allow(deprecated)]
+
+mod mock;
+
+use common::{
+    Limit, Polarity, Stepper, StepperCommand, StepperConfig, StepperController, StepperState, COMMAND_QUEUE_LEN,
+    DEBOUNCE_TICKS,
+};
+use mock::{Motor, Pin, TestLock};
+
+type TestStepper = Stepper<Pin, Pin, Pin, Pin, Pin, Pin>;
+
+const CONFIG: StepperConfig = StepperConfig {
+    travel: 1000,
+    enable: Polarity::ActiveHigh,
+    invert_dir: false,
+};
+
+struct Rig {
+    stepper: TestStepper,
+    motor: Motor,
+    alm: Pin,
+}
+
+impl Rig {
+    /// Motor at `position` with the limit switches at 0 and 1200
+    fn new(position: i32) -> Rig {
+        Rig::with_config(position, CONFIG)
+    }
+
+    fn with_config(position: i32, config: StepperConfig) -> Rig {
+        let motor = Motor::new(position, (0, 1200));
+        let alm = Pin::new(false);
+        let stepper = Stepper::new(
+            motor.ena.clone(),
+            motor.dir.clone(),
+            motor.pul.clone(),
+            Limit::new(alm.clone(), Polarity::ActiveHigh),
+            Limit::new(motor.lim_l.clone(), Polarity::ActiveHigh),
+            Limit::new(motor.lim_r.clone(), Polarity::ActiveHigh),
+            config,
+        );
+        Rig { stepper, motor, alm }
+    }
+
+    fn run(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.stepper.tick();
+            self.motor.update();
+        }
+    }
+
+    /// Runs until the stepper is ready and still, returns the ticks it took
+    fn settle(&mut self, ticks: usize) -> usize {
+        for i in 0..ticks {
+            let status = self.stepper.status();
+            if status.state == StepperState::Ready && status.position == status.target && !self.motor.pul.get() {
+                return i;
+            }
+            self.run(1);
+        }
+        ticks
+    }
+
+    /// Zeroes and checks the stepper counts from where the switch closed
+    fn zero(&mut self) {
+        self.stepper.command(StepperCommand::Zero);
+        for _ in 0..10_000 {
+            if self.stepper.status().state == StepperState::Ready {
+                break;
+            }
+            self.run(1);
+        }
+        // Debouncing lets it run on for a step or two
+        let overrun = (DEBOUNCE_TICKS as i32 + 1) / 2;
+        assert!(self.motor.position <= 0 && self.motor.position >= -overrun);
+        assert_eq!(self.stepper.status().position, 0);
+    }
+
+    /// Position of the motor in the stepper's count
+    fn offset(&self) -> i32 {
+        self.motor.position - self.stepper.status().position
+    }
+}
+
+#[test]
+fn starts_disabled_and_unzeroed() {
+    let mut rig = Rig::new(500);
+    assert!(!rig.motor.ena.get());
+    rig.stepper.command(StepperCommand::Goto(800));
+    rig.run(100);
+    assert_eq!(rig.stepper.status().state, StepperState::Unzeroed);
+    assert_eq!(rig.motor.position, 500);
+    assert!(!rig.motor.pul.get());
+}
+
+#[test]
+fn zero_finds_left_limit() {
+    let mut rig = Rig::new(500);
+    rig.zero();
+    assert!(rig.motor.ena.get());
+    assert_eq!(rig.stepper.status().state, StepperState::Ready);
+    assert_eq!(rig.motor.lost, 0);
+}
+
+#[test]
+fn goto_after_zero() {
+    let mut rig = Rig::new(300);
+    rig.zero();
+    let offset = rig.offset();
+
+    for &target in &[700, 250, 1000, 0] {
+        rig.stepper.command(StepperCommand::Goto(target));
+        assert!(rig.settle(10_000) < 10_000);
+        assert_eq!(rig.stepper.status().position, target);
+        assert_eq!(rig.offset(), offset);
+    }
+}
+
+#[test]
+fn goto_before_zero_is_held() {
+    let mut rig = Rig::new(300);
+    rig.stepper.command(StepperCommand::Goto(600));
+    rig.zero();
+    assert!(rig.settle(10_000) < 10_000);
+    assert_eq!(rig.stepper.status().position, 600);
+}
+
+#[test]
+fn target_is_clamped_to_travel() {
+    let mut rig = Rig::new(300);
+    rig.zero();
+    rig.stepper.command(StepperCommand::Goto(5000));
+    assert_eq!(rig.stepper.status().target, 1000);
+    rig.stepper.command(StepperCommand::Goto(-20));
+    assert_eq!(rig.stepper.status().target, 0);
+}
+
+#[test]
+fn right_limit_stops_move() {
+    let mut rig = Rig::with_config(300, StepperConfig { travel: 2000, ..CONFIG });
+    rig.zero();
+    rig.stepper.command(StepperCommand::Goto(2000));
+    rig.run(10_000);
+
+    let overrun = (DEBOUNCE_TICKS as i32 + 1) / 2;
+    assert!(rig.motor.position >= 1200 && rig.motor.position <= 1200 + overrun);
+    assert!(!rig.motor.pul.get());
+}
+
+#[test]
+fn stop_holds_position() {
+    let mut rig = Rig::new(300);
+    rig.zero();
+    rig.stepper.command(StepperCommand::Goto(1000));
+    rig.run(200);
+    rig.stepper.command(StepperCommand::Stop);
+    rig.run(2);
+
+    let position = rig.motor.position;
+    rig.run(100);
+    assert_eq!(rig.motor.position, position);
+    assert!(rig.settle(10) < 10);
+}
+
+#[test]
+fn alarm_disables_until_zeroed() {
+    let mut rig = Rig::new(300);
+    rig.zero();
+    rig.stepper.command(StepperCommand::Goto(1000));
+    rig.run(100);
+
+    rig.alm.set(true);
+    rig.run(DEBOUNCE_TICKS as usize + 2);
+    assert_eq!(rig.stepper.status().state, StepperState::Alarm);
+    assert!(!rig.motor.ena.get());
+    let position = rig.motor.position;
+    rig.run(100);
+    assert_eq!(rig.motor.position, position);
+
+    // Zeroing again while the alarm is raised does nothing
+    rig.stepper.command(StepperCommand::Zero);
+    rig.run(10);
+    assert_eq!(rig.stepper.status().state, StepperState::Alarm);
+
+    rig.alm.set(false);
+    rig.run(DEBOUNCE_TICKS as usize);
+    rig.zero();
+    assert!(rig.settle(10_000) < 10_000);
+    assert_eq!(rig.stepper.status().position, 1000);
+}
+
+#[test]
+fn inverted_dir_and_enable() {
+    let config = StepperConfig {
+        enable: Polarity::ActiveLow,
+        invert_dir: true,
+        ..CONFIG
+    };
+    let mut rig = Rig::with_config(300, config);
+    assert!(rig.motor.ena.get());
+
+    rig.stepper.command(StepperCommand::Zero);
+    rig.stepper.tick();
+    assert!(!rig.motor.ena.get());
+    // The motor steps right with dir low, so zeroing drives it the wrong way
+    assert!(!rig.motor.dir.get());
+}
+
+#[test]
+fn controller_passes_commands_in_order() {
+    let controller = StepperController::<TestLock>::new();
+    let mut rig = Rig::new(300);
+    controller.zero();
+    controller.goto(700);
+    for _ in 0..10_000 {
+        controller.step(&mut rig.stepper);
+        rig.motor.update();
+    }
+    let status = controller.status();
+    assert_eq!(status.state, StepperState::Ready);
+    assert_eq!(status.position, 700);
+    assert_eq!(status, rig.stepper.status());
+}
+
+#[test]
+fn controller_drops_what_does_not_fit() {
+    let controller = StepperController::<TestLock>::new();
+    let mut rig = Rig::new(300);
+    controller.zero();
+    for i in 0..COMMAND_QUEUE_LEN as i32 {
+        controller.goto(100 * i);
+    }
+    assert_eq!(controller.dropped(), 1);
+    // One command a step, the last goto is lost
+    for _ in 0..COMMAND_QUEUE_LEN {
+        controller.step(&mut rig.stepper);
+    }
+    assert_eq!(controller.status().target, 100 * (COMMAND_QUEUE_LEN as i32 - 2));
+    assert_eq!(controller.status().state, StepperState::Zeroing);
+}
@@ -0,0 +1,66 @@
+#![allow(deprecated)]
+
+mod mock;
+
+use common::{MotorState, StepperState, StepperStatus, Usage, UsageMeter, UsageStore};
+use mock::MemFlash;
+
+fn stepper(state: StepperState, position: i32) -> StepperStatus {
+    StepperStatus { state, position, target: position }
+}
+
+#[test]
+fn counts_run_time_and_shifts() {
+    let mut meter = UsageMeter::new(Usage::default());
+    let ready = stepper(StepperState::Ready, 0);
+
+    // Idle with the throttle closed is not running
+    for _ in 0..2000 {
+        meter.tick(MotorState::Idle(0), ready);
+    }
+    for _ in 0..2500 {
+        meter.tick(MotorState::Fwd(80), ready);
+    }
+    meter.tick(MotorState::Rev(10), ready);
+    meter.tick(MotorState::Idle(0), ready);
+    meter.started();
+
+    let usage = meter.usage();
+    assert_eq!(usage.run_seconds, 2);
+    assert_eq!(usage.shifts, 3);
+    assert_eq!(usage.starts, 1);
+}
+
+#[test]
+fn counts_steps_both_ways() {
+    let mut meter = UsageMeter::new(Usage::default());
+    let idle = MotorState::Idle(0);
+    meter.tick(idle, stepper(StepperState::Zeroing, 0));
+    meter.tick(idle, stepper(StepperState::Zeroing, -300));
+    // Zeroed, the position jumps to 0 without moving
+    meter.tick(idle, stepper(StepperState::Ready, 0));
+    meter.tick(idle, stepper(StepperState::Ready, 200));
+    meter.tick(idle, stepper(StepperState::Ready, 50));
+    assert_eq!(meter.usage().steps, 300 + 200 + 150);
+}
+
+#[test]
+fn store_keeps_the_newest() {
+    let flash = MemFlash::new(2);
+    let mut store = UsageStore::open(flash.clone());
+    assert_eq!(store.saved(), Usage::default());
+
+    for starts in 1..100 {
+        store.save(Usage { run_seconds: 60, starts, shifts: 2, steps: 4000 }).unwrap();
+    }
+    let store = UsageStore::open(flash.clone());
+    assert_eq!(store.saved(), Usage { run_seconds: 60, starts: 99, shifts: 2, steps: 4000 });
+
+    // Nothing is written when nothing changed
+    let erases: u32 = flash.erases.borrow().iter().sum();
+    let before = flash.bytes.borrow().clone();
+    let mut store = store;
+    store.save(store.saved()).unwrap();
+    assert_eq!(*flash.bytes.borrow(), before);
+    assert_eq!(flash.erases.borrow().iter().sum::<u32>(), erases);
+}
@@ -0,0 +1,100 @@
+use std::cell::{Cell, RefCell};
+
+use common::*;
+
+const LOOP: u8 = 1 << 0;
+const SERIAL: u8 = 1 << 1;
+const TICK: u8 = 1 << 2;
+
+#[test]
+fn fed_once_every_part_checked_in() {
+    let parts = CheckIns::new(LOOP | SERIAL | TICK);
+    assert!(!parts.all_in());
+
+    parts.check_in(LOOP);
+    parts.check_in(TICK);
+    assert!(!parts.all_in());
+    // What came in so far is kept
+    parts.check_in(SERIAL);
+    assert!(parts.all_in());
+
+    // Everyone has to check in again
+    assert!(!parts.all_in());
+    parts.check_in(LOOP);
+    parts.check_in(LOOP);
+    parts.check_in(SERIAL);
+    assert!(!parts.all_in());
+    parts.check_in(TICK);
+    assert!(parts.all_in());
+}
+
+#[test]
+fn a_hung_part_stops_the_feeding() {
+    let parts = CheckIns::new(LOOP | TICK);
+    for _ in 0..10 {
+        parts.check_in(TICK);
+        assert!(!parts.all_in());
+    }
+}
+
+#[test]
+fn reset_reason() {
+    let pin = 1 << 26;
+    assert_eq!(ResetReason::from_csr(0), ResetReason::Unknown);
+    assert_eq!(ResetReason::from_csr(pin), ResetReason::Pin);
+    assert_eq!(ResetReason::from_csr(pin | 1 << 27), ResetReason::PowerOn);
+    assert_eq!(ResetReason::from_csr(pin | 1 << 28), ResetReason::Software);
+    assert_eq!(ResetReason::from_csr(pin | 1 << 29), ResetReason::Watchdog);
+    assert_eq!(ResetReason::from_csr(pin | 1 << 30), ResetReason::Watchdog);
+    assert_eq!(ResetReason::from_csr(pin | 1 << 31), ResetReason::LowPower);
+    // The watchdog wins over whatever else is left set
+    assert_eq!(ResetReason::from_csr(pin | 1 << 27 | 1 << 29), ResetReason::Watchdog);
+}
+
+/// Records what is written to it, the new values get through after `busy`
+/// reads of the status register
+#[derive(Default)]
+struct Regs {
+    writes: RefCell<Vec<(&'static str, u32)>>,
+    busy: Cell<u32>,
+}
+
+impl Iwdg for Regs {
+    fn write_key(&self, key: u32) {
+        self.writes.borrow_mut().push(("kr", key));
+    }
+
+    fn write_prescaler(&self, prescaler: u32) {
+        self.writes.borrow_mut().push(("pr", prescaler));
+    }
+
+    fn write_reload(&self, reload: u32) {
+        self.writes.borrow_mut().push(("rlr", reload));
+    }
+
+    fn status(&self) -> u32 {
+        self.busy.set(self.busy.get().saturating_sub(1));
+        if self.busy.get() > 0 { 0b11 } else { 0 }
+    }
+}
+
+#[test]
+fn started_before_it_is_set_up() {
+    let regs = Regs::default();
+    regs.busy.set(5);
+    start_watchdog(&regs, 500);
+    assert_eq!(
+        *regs.writes.borrow(),
+        [("kr", 0xcccc), ("kr", 0x5555), ("pr", 4), ("rlr", 312), ("kr", 0xaaaa)]
+    );
+    assert_eq!(regs.busy.get(), 0);
+}
+
+#[test]
+fn start_does_not_hang_on_the_status() {
+    let regs = Regs::default();
+    regs.busy.set(u32::MAX);
+    start_watchdog(&regs, 100_000);
+    assert_eq!(regs.writes.borrow()[3], ("rlr", 0xfff));
+    assert_eq!(regs.writes.borrow().last(), Some(&("kr", 0xaaaa)));
+}
@@ -0,0 +1,331 @@
+use common::{
+    Autobaud, BusMaster, FrameParser, Framing, Identity, Message, MotorState, NodeKind, Packet, ResetReason,
+    StepperState, Version, DEFAULT_BAUD, HOST, MASTER, MAX_NODE, REPLY_TICKS,
+};
+
+/// How the two serial ports are wired to the drivers
+pub enum Topology {
+    /// serial_l goes to the left driver and serial_r to the right driver
+    PerEngine,
+    /// Both drivers listen on both ports and every frame is sent on both
+    Broadcast,
+    /// Every node sits on one RS-485 bus on serial_l, serial_r is unused
+    Bus,
+}
+
+pub const TOPOLOGY: Topology = Topology::Broadcast;
+
+/// Framing used on serial_l, `FrameParser` or `CobsParser`. Has to match
+/// `Framing1` on the drivers
+pub type FramingL = FrameParser;
+/// Framing used on serial_r, has to match `Framing3` on the drivers
+pub type FramingR = FrameParser;
+
+/// Address of the steering node, it listens on serial_l next to the left
+/// driver
+pub const STEERING_ID: u8 = 3;
+
+/// What the answers from a node say about it
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Health {
+    /// Never answered
+    Unknown,
+    /// Answered within the last `HEARTBEAT_TICKS`
+    Alive,
+    /// Answered before, but not any more
+    Lost,
+}
+
+/// What a driver reported in answer to its last frame
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Telemetry {
+    pub motor_state: MotorState,
+    /// Stepper position in steps from zero
+    pub steering: i32,
+    /// System time it was taken at
+    pub time: u32,
+    pub reset: ResetReason,
+    /// Commands and faults the driver had no room to queue since it started
+    pub dropped: u16,
+}
+
+/// A node the boat does not go to power without
+pub struct Required {
+    pub id: u8,
+    pub kind: NodeKind,
+    /// Oldest firmware this controller works with
+    pub version: Version,
+}
+
+pub const LEFT_DRIVER: Required = Required {
+    id: 1,
+    kind: NodeKind::LeftDriver,
+    version: Version { major: 0, minor: 1, patch: 0 },
+};
+
+pub const RIGHT_DRIVER: Required = Required {
+    id: 2,
+    kind: NodeKind::RightDriver,
+    version: Version { major: 0, minor: 1, patch: 0 },
+};
+
+/// Speed proposed to the drivers once a link is up at `DEFAULT_BAUD`, set it
+/// to `DEFAULT_BAUD` to never switch
+pub const BAUD: u32 = 115_200;
+
+/// Clock ticks without a reply before a port is considered down
+const TIMEOUT_TICKS: u8 = 5;
+
+/// Clock ticks without an answer from a node before it counts as lost
+const HEARTBEAT_TICKS: u8 = 5;
+
+/// Packets that can wait for the bus
+const OUTBOX_LEN: usize = 8;
+
+/// Sends the packets for a port one request at a time, and keeps track of
+/// the replies coming back
+pub struct Link<F> {
+    parser: F,
+    bus: BusMaster,
+    /// Packets waiting for the bus, and whether they are answered
+    outbox: [Option<(Packet, bool)>; OUTBOX_LEN],
+    /// Set once a ping or speed proposal went out this tick
+    managed: bool,
+    since_reply: u8,
+    autobaud: Autobaud,
+    /// Speed the driver has agreed to, not yet applied to our uart
+    switch_to: Option<u32>,
+    /// Set when a faster speed did not work, we stay at `DEFAULT_BAUD`
+    fell_back: bool,
+    /// Last status from a steering node on this port
+    steering: Option<(StepperState, i32)>,
+    /// What each node present answered to `Identify`
+    identities: [Option<Identity>; MAX_NODE as usize + 1],
+    /// Last telemetry from each driver that is alive
+    telemetry: [Option<Telemetry>; MAX_NODE as usize + 1],
+    /// Ticks since each node last answered, None if it never did
+    heartbeats: [Option<u8>; MAX_NODE as usize + 1],
+    /// Last packet a host sent to the controller itself, not yet answered
+    query: Option<Packet>,
+}
+
+impl<F: Framing> Link<F> {
+    pub fn new() -> Self {
+        Link {
+            parser: F::default(),
+            bus: BusMaster::new(REPLY_TICKS),
+            outbox: [None; OUTBOX_LEN],
+            managed: false,
+            since_reply: TIMEOUT_TICKS,
+            autobaud: Autobaud::new(TIMEOUT_TICKS as u16),
+            switch_to: None,
+            fell_back: false,
+            steering: None,
+            identities: [None; MAX_NODE as usize + 1],
+            telemetry: [None; MAX_NODE as usize + 1],
+            heartbeats: [None; MAX_NODE as usize + 1],
+            query: None,
+        }
+    }
+
+    /// Parses the bytes received since the last call. Returns true if an
+    /// answer came in.
+    pub fn poll(&mut self, bytes: impl Iterator<Item = u8>) -> bool {
+        let mut answered = false;
+        for byte in bytes {
+            let mut packet = self.parser.feed_packet(byte);
+            while let Some(p) = packet {
+                answered |= self.receive(p);
+                packet = self.parser.pending();
+            }
+        }
+        answered
+    }
+
+    /// Returns true if `packet` is an answer
+    fn receive(&mut self, packet: Packet) -> bool {
+        if packet.dst == MASTER && packet.src == HOST {
+            self.query = Some(packet);
+            return false;
+        }
+        // Answers that come after the timeout are dropped, the bus has moved
+        // on
+        if !self.bus.received(&packet) {
+            return false;
+        }
+        self.since_reply = 0;
+        self.autobaud.received();
+        self.heartbeats[packet.src as usize] = Some(0);
+        match packet.message {
+            Message::SteerStatus { state, position, .. } => {
+                self.steering = Some((state, position));
+            }
+            Message::Telemetry { motor_state, steering, time, reset, dropped } => {
+                let telemetry = Telemetry { motor_state, steering, time, reset, dropped };
+                self.telemetry[packet.src as usize] = Some(telemetry);
+            }
+            Message::BaudAck(baud) => {
+                if baud == BAUD && self.autobaud.baud() == DEFAULT_BAUD {
+                    self.switch_to = Some(baud);
+                }
+            }
+            Message::Identity(identity) => {
+                self.identities[packet.src as usize] = Some(identity);
+            }
+            _ => (),
+        }
+        true
+    }
+
+    /// Queues a packet that the node it is for answers. Returns false if it
+    /// was dropped, because the node is not alive or the outbox is full.
+    /// A node that is lost is only asked again once it answers a ping.
+    pub fn request(&mut self, packet: Packet) -> bool {
+        self.health(packet.dst) == Health::Alive && self.queue(packet, true)
+    }
+
+    /// Queues a packet nobody answers, a broadcast or one for a node that
+    /// cannot talk back on this port
+    pub fn send(&mut self, packet: Packet) -> bool {
+        self.queue(packet, false)
+    }
+
+    fn queue(&mut self, packet: Packet, answered: bool) -> bool {
+        match self.outbox.iter_mut().find(|p| p.is_none()) {
+            Some(slot) => {
+                *slot = Some((packet, answered));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Next packet to put on the wire, None while a node has to answer.
+    /// Once the outbox is empty a node is identified, pinged or asked to
+    /// change speed, once per tick.
+    pub fn next(&mut self) -> Option<Packet> {
+        if !self.bus.is_idle() {
+            return None;
+        }
+
+        let (packet, answered) = match self.outbox[0].take() {
+            Some(entry) => {
+                self.outbox.rotate_left(1);
+                entry
+            }
+            None if !self.managed => {
+                self.managed = true;
+                (self.management()?, true)
+            }
+            None => return None,
+        };
+        if answered {
+            self.bus.expect(packet.dst);
+        }
+        Some(packet)
+    }
+
+    /// Asks a new node who it is, proposes a speed to a node that is
+    /// present or pings one that is not. Every node on the port switches
+    /// speed, only the addressed one answers.
+    fn management(&mut self) -> Option<Packet> {
+        let unknown = (1..=MAX_NODE).find(|&n| self.bus.is_present(n) && self.identities[n as usize].is_none());
+        let switch_node = (1..=MAX_NODE).find(|&n| self.bus.is_present(n));
+        let (dst, message) = match (unknown, switch_node) {
+            (Some(node), _) => (node, Message::Identify),
+            (None, Some(node)) if self.wants_switch() => (node, Message::BaudPropose(BAUD)),
+            _ => (self.bus.next_scan()?, Message::Ping),
+        };
+        Some(Packet { dst, src: MASTER, seq: 0, message })
+    }
+
+    /// Packet a host sent to the controller itself, see `HOST`. Its answer
+    /// goes out with `send`.
+    pub fn take_query(&mut self) -> Option<Packet> {
+        self.query.take()
+    }
+
+    /// True if `node` answered its last request
+    pub fn is_present(&self, node: u8) -> bool {
+        self.bus.is_present(node)
+    }
+
+    pub fn health(&self, node: u8) -> Health {
+        match self.heartbeats.get(node as usize) {
+            Some(Some(ticks)) if *ticks < HEARTBEAT_TICKS => Health::Alive,
+            Some(Some(_)) => Health::Lost,
+            _ => Health::Unknown,
+        }
+    }
+
+    /// What `node` said it is, None until it answered `Identify`
+    pub fn identity(&self, node: u8) -> Option<Identity> {
+        self.identities.get(node as usize).and_then(|identity| *identity)
+    }
+
+    /// What `node` last reported, None until it answered a frame or once it
+    /// is lost
+    pub fn telemetry(&self, node: u8) -> Option<Telemetry> {
+        self.telemetry.get(node as usize).and_then(|telemetry| *telemetry)
+    }
+
+    /// True if the node is present and runs firmware this controller
+    /// supports
+    pub fn has(&self, node: &Required) -> bool {
+        match self.identity(node.id) {
+            Some(identity) => identity.kind == node.kind && identity.version.supports(node.version),
+            None => false,
+        }
+    }
+
+    /// Speed the uart should be switched to after the driver acknowledged it
+    pub fn take_switch(&mut self) -> Option<u32> {
+        let baud = self.switch_to.take()?;
+        self.autobaud.switch(baud);
+        Some(baud)
+    }
+
+    /// True while a faster speed should be proposed to the drivers
+    pub fn wants_switch(&self) -> bool {
+        BAUD != DEFAULT_BAUD
+            && !self.fell_back
+            && self.switch_to.is_none()
+            && self.autobaud.baud() == DEFAULT_BAUD
+            && self.is_healthy()
+    }
+
+    /// Should be called once per clock tick before queueing its packets,
+    /// returns the speed to switch to when the link has to fall back.
+    /// Packets still waiting from the last tick are dropped.
+    pub fn tick(&mut self) -> Option<u32> {
+        self.outbox = [None; OUTBOX_LEN];
+        self.managed = false;
+        self.bus.tick();
+        for node in 0..self.heartbeats.len() {
+            if let Some(ticks) = &mut self.heartbeats[node] {
+                *ticks = ticks.saturating_add(1);
+                // A node that is lost may come back with other firmware, and
+                // what it last reported no longer holds
+                if *ticks >= HEARTBEAT_TICKS {
+                    self.identities[node] = None;
+                    self.telemetry[node] = None;
+                }
+            }
+        }
+        self.since_reply = self.since_reply.saturating_add(1);
+        let baud = self.autobaud.tick();
+        if baud.is_some() {
+            self.fell_back = true;
+        }
+        baud
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.since_reply < TIMEOUT_TICKS
+    }
+
+    /// State and position the steering node last reported, if there is one
+    pub fn steering(&self) -> Option<(StepperState, i32)> {
+        self.steering
+    }
+}
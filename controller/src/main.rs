@@ -2,39 +2,77 @@
 #![no_main]
 #![allow(deprecated)]
 
-extern crate panic_halt;
-
 #[macro_use]
 extern crate cortex_m_semihosting;
 
 use embedded_hal::digital::{InputPin, OutputPin};
 use embedded_hal::timer::{CountDown, Periodic};
 
-use cortex_m_rt::entry;
+use cortex_m_rt::{entry, exception};
 
 use stm32f1::stm32f103::interrupt;
 use stm32f1xx_hal::prelude::*;
 use stm32f1xx_hal::pac;
 use stm32f1xx_hal::time::Hertz;
-use stm32f1xx_hal::timer::Timer;
-use stm32f1xx_hal::serial::{Event, Serial, Tx, Rx};
-use stm32f1xx_hal::pac::USART1;
+use stm32f1xx_hal::timer::{self, Timer};
+use stm32f1xx_hal::serial::{Serial, Tx, Rx};
+use stm32f1xx_hal::pac::{USART1, USART3};
 use stm32f1xx_hal::adc::Adc;
+use stm32f1xx_hal::gpio::{gpioa, Output, PushPull};
 
 use cortex_m::interrupt::{Mutex};
 use core::cell::{Cell, RefCell};
 
-mod button;
-use button::Button;
+mod link;
+use link::{Health, Link, Topology};
+
+mod serial;
+use serial::{SerialRx, SerialTx};
+
+use common::stm32f103::watchdog;
+
+mod panic;
+
+mod flash;
+use flash::FlashRegion;
 
 use common::*;
 
+// Only serial_l can be a half duplex bus, see `Topology::Bus`
+static TX_L: SerialTx<USART1, link::FramingL, gpioa::PA8<Output<PushPull>>> = SerialTx::new();
+static TX_R: SerialTx<USART3, link::FramingR, NoPin> = SerialTx::new();
+static RX_L: SerialRx<USART1> = SerialRx::new();
+static RX_R: SerialRx<USART3> = SerialRx::new();
+
+// Parts that have to check in before the watchdog is fed. A port checks in
+// when an answer comes in on it, serial_r only when the topology uses it.
+const CONTROL: u8 = 1 << 0;
+const SERIAL_L: u8 = 1 << 1;
+const SERIAL_R: u8 = 1 << 2;
+
+const PORTS: u8 = match link::TOPOLOGY {
+    Topology::Bus => SERIAL_L,
+    _ => SERIAL_L | SERIAL_R,
+};
+
+static CHECK_INS: CheckIns = CheckIns::new(CONTROL | PORTS);
+
+/// The controller resets if a part has not checked in for this long. Longer
+/// than a port takes to count as down, see `Link::is_healthy`.
+const WATCHDOG_MS: u32 = 1000;
+
+/// Period of the control loop
+const TICK_MS: u32 = 100;
+
+/// Control loop ticks between two time syncs
+const SYNC_TICKS: u8 = 10;
+
+// Uptime, counted by SysTick. It is the system time of every board.
+static TIME: Mutex<RefCell<Clock>> = Mutex::new(RefCell::new(Clock::new()));
+
 //mod stepper;
 //use stepper::*;
 
-//mod mpsc;
-//mod communication;
-
 /* *****************
  * Controller pinout
  * *****************
@@ -46,24 +84,38 @@ use common::*;
  * btn_2: pa3
  * btn_3: pa4
  * btn_4: pa5
- * btn_5: pa6
+ * btn_5: pa6 // E-stop
  * btn_6: pa7
+ *
+ * See common/src/bindings.rs for what the buttons do
  * 
- * serial: pa9 + pa10
+ * serial_l: pa9 + pa10, pa8 enables the transceiver on a bus
+ * serial_r: pb10 + pb11
+ *
+ * The left driver is shown on led_4 and the right one on led_5: on while it
+ * answers, blinking once it stopped answering, off if it never did
  */
 
-enum Mode {
-    MotorControl,
-    DirectionControl
+fn show_health<P: OutputPin>(led: &mut P, health: Health, blink: bool) {
+    match health {
+        Health::Alive => led.set_high(),
+        Health::Lost if blink => led.set_high(),
+        _ => led.set_low(),
+    }
 }
 
-impl Mode {
-    fn switch(&mut self) {
-        match self {
-            Mode::MotorControl => *self = Mode::DirectionControl,
-            Mode::DirectionControl => *self = Mode::MotorControl,
+/// Answers a host asking the controller about itself, only the fault log
+/// can be read and cleared
+fn answer_host<F: Flash>(query: &Packet, faults: &mut FaultLog<F>) -> Option<Packet> {
+    let message = match query.message {
+        Message::GetFault(index) => Message::LoggedFault { index, record: faults.get(index as usize) },
+        Message::ClearFaults => {
+            faults.clear().ok();
+            Message::ClearFaults
         }
-    }
+        _ => return None,
+    };
+    Some(query.reply(MASTER, message))
 }
 
 fn linearize(x: u16) -> u16 {
@@ -86,6 +138,18 @@ fn main() -> ! {
 
     // Take ownership over the raw flash and rcc devices and convert them into the corresponding
     // HAL structs
+    let reset = watchdog::reset_reason(&dp.RCC);
+    let last_panic = panic::take();
+
+    // Read and cleared by a host on one of the ports, see `answer_host`
+    let mut faults = FaultLog::open(unsafe { FlashRegion::new(flash::Regs, flash::FAULT_LOG, flash::FAULT_LOG_PAGES) });
+    if reset == ResetReason::Watchdog {
+        faults.record(FaultRecord { kind: FaultKind::WatchdogReset, time: 0, context: 0 }).ok();
+    }
+    if last_panic.is_some() {
+        faults.record(FaultRecord { kind: FaultKind::Panic, time: 0, context: 0 }).ok();
+    }
+
     let mut flash = dp.FLASH.constrain();
     let mut rcc = dp.RCC.constrain();
     let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
@@ -99,16 +163,16 @@ fn main() -> ! {
     let mut serial_l = {
         let pin_tx = gpioa.pa9.into_alternate_push_pull(&mut gpioa.crh);
         let pin_rx = gpioa.pa10;
-        Serial::usart1(dp.USART1, (pin_tx, pin_rx), &mut afio.mapr, 9_600.bps(), clocks, &mut rcc.apb2)
+        Serial::usart1(dp.USART1, (pin_tx, pin_rx), &mut afio.mapr, common::DEFAULT_BAUD.bps(), clocks, &mut rcc.apb2)
     };
 
     let mut serial_r = {
         let pin_tx = gpiob.pb10.into_alternate_push_pull(&mut gpiob.crh);
         let pin_rx = gpiob.pb11;
-        Serial::usart3(dp.USART3, (pin_tx, pin_rx), &mut afio.mapr, 9_600.bps(), clocks, &mut rcc.apb1)
+        Serial::usart3(dp.USART3, (pin_tx, pin_rx), &mut afio.mapr, common::DEFAULT_BAUD.bps(), clocks, &mut rcc.apb1)
     };
 
-    let mut clock = Timer::syst(cp.SYST, 10.hz(), clocks);
+    let mut clock = Timer::syst(cp.SYST, 1000.hz(), clocks);
 
     let mut adc = Adc::adc1(dp.ADC1, &mut rcc.apb2);
 
@@ -132,14 +196,102 @@ fn main() -> ! {
     let mut led_7 = gpiob.pb6.into_push_pull_output(&mut gpiob.crl);
     let mut led_8 = gpiob.pb7.into_push_pull_output(&mut gpiob.crl);
 
-    let (mut tx_l, mut rx_l) = serial_l.split();
-    let (mut tx_r, mut rx_r) = serial_r.split();
+    // Flash the ring for a second after a watchdog reset or a panic so it is
+    // noticed
+    if reset == ResetReason::Watchdog || last_panic.is_some() {
+        let mut leds: [&mut dyn OutputPin; 8] = [
+            &mut led_1, &mut led_2, &mut led_3, &mut led_4, &mut led_5, &mut led_6, &mut led_7, &mut led_8,
+        ];
+        for i in 0..10 {
+            for led in leds.iter_mut() {
+                if i % 2 == 0 {
+                    led.set_high();
+                } else {
+                    led.set_low();
+                }
+            }
+            for _ in 0..TICK_MS {
+                nb::block!(clock.wait()).ok();
+            }
+        }
+    }
+    clock.listen(timer::Event::Update);
+
+    // Replies are taken out of the uarts in their interrupts, and parsed
+    // in the loop
+    serial_l.listen(stm32f1xx_hal::serial::Event::Rxne);
+    serial_r.listen(stm32f1xx_hal::serial::Event::Rxne);
+    let (tx_l, rx_l) = serial_l.split();
+    let (tx_r, rx_r) = serial_r.split();
+
+    TX_L.init(tx_l, gpioa.pa8.into_push_pull_output(&mut gpioa.crh));
+    TX_R.init(tx_r, NoPin);
+    RX_L.init(rx_l);
+    RX_R.init(rx_r);
+
+    let mut nvic = cp.NVIC;
+    nvic.enable(pac::Interrupt::USART1);
+    nvic.enable(pac::Interrupt::USART3);
 
-    let mut mode = Mode::MotorControl;
+    let mut controls = Controls::new();
+
+    let mut link_l = Link::<link::FramingL>::new();
+    let mut link_r = Link::<link::FramingR>::new();
+    let mut seq: u8 = 0;
+    // Uptime the control loop last ran at
+    let mut last_tick: u32 = 0;
+    let mut since_sync: u8 = 0;
+    // Left driver, right driver and steering node, to log each loss once
+    let mut health = [Health::Unknown; 3];
+
+    watchdog::start(&dp.IWDG, WATCHDOG_MS);
 
     loop {
-        if btn_1.is_falling() {
-            mode.switch();
+        if CHECK_INS.all_in() {
+            watchdog::feed();
+        }
+
+        // A port nothing answers on cannot show it works, it checks in while
+        // it is down so a missing driver does not reset the controller
+        if link_l.poll(RX_L.bytes()) || !link_l.is_healthy() {
+            CHECK_INS.check_in(SERIAL_L);
+        }
+        if link_r.poll(RX_R.bytes()) || !link_r.is_healthy() {
+            CHECK_INS.check_in(SERIAL_R);
+        }
+
+        if let Some(query) = link_l.take_query() {
+            if let Some(answer) = answer_host(&query, &mut faults) {
+                link_l.send(answer);
+            }
+        }
+        if let Some(query) = link_r.take_query() {
+            if let Some(answer) = answer_host(&query, &mut faults) {
+                link_r.send(answer);
+            }
+        }
+
+        // Packets are only queued here, they are sent from the uart
+        // interrupts so the loop keeps ticking the buttons
+        while let Some(packet) = link_l.next() {
+            TX_L.send(&packet);
+        }
+        while let Some(packet) = link_r.next() {
+            TX_R.send(&packet);
+        }
+
+        if let Some(baud) = link_l.take_switch() {
+            TX_L.set_baud(baud, &clocks);
+        }
+        if let Some(baud) = link_r.take_switch() {
+            TX_R.set_baud(baud, &clocks);
+        }
+
+        let events = [btn_1.event(), btn_2.event(), btn_3.event(), btn_4.event(), btn_5.event()];
+        for (i, event) in events.iter().enumerate() {
+            if let Some(event) = event {
+                controls.handle(i as u8 + 1, *event);
+            }
         }
 
         btn_1.tick();
@@ -159,47 +311,203 @@ fn main() -> ! {
         }*/
 
         // Send state
-        match clock.wait() {
-            Ok(()) => {
+        let uptime = cortex_m::interrupt::free(|cs| TIME.borrow(cs).borrow().uptime());
+        match uptime.wrapping_sub(last_tick) {
+            elapsed if elapsed >= TICK_MS => {
+                last_tick = uptime;
+                CHECK_INS.check_in(CONTROL);
+
+                let held = [btn_1.held(), btn_2.held(), btn_3.held(), btn_4.held(), btn_5.held()];
+                for (i, held) in held.iter().enumerate() {
+                    if *held {
+                        controls.handle(i as u8 + 1, Event::Hold);
+                    }
+                }
+
+                if let Some(baud) = link_l.tick() {
+                    TX_L.set_baud(baud, &clocks);
+                }
+                if let Some(baud) = link_r.tick() {
+                    TX_R.set_baud(baud, &clocks);
+                }
+
+                // A steering node that lost its zero does not move until told
+                // to find it again
+                if let Some((StepperState::Unzeroed, _)) = link_l.steering() {
+                    link_l.request(Packet { dst: link::STEERING_ID, src: MASTER, seq, message: Message::SteerZero });
+                }
+
+                // The replies to the frames are the heartbeats of the drivers
+                let link_2 = match link::TOPOLOGY {
+                    Topology::Bus => &link_l,
+                    _ => &link_r,
+                };
+                let left = link_l.health(link::LEFT_DRIVER.id);
+                let right = link_2.health(link::RIGHT_DRIVER.id);
+                show_health(&mut led_4, left, seq % 4 < 2);
+                show_health(&mut led_5, right, seq % 4 < 2);
+
+                let now = [left, right, link_l.health(link::STEERING_ID)];
+                let ids = [link::LEFT_DRIVER.id, link::RIGHT_DRIVER.id, link::STEERING_ID];
+                for i in 0..3 {
+                    if now[i] == Health::Lost && health[i] == Health::Alive {
+                        let record = FaultRecord { kind: FaultKind::LinkLost, time: uptime, context: ids[i] as i32 };
+                        faults.record(record).ok();
+                    }
+                    health[i] = now[i];
+                }
+
+                // Safe mode, both engines idle until both drivers answer.
+                // They have to be started again after.
+                if left != Health::Alive || right != Health::Alive {
+                    controls.apply(Action::EStop);
+                }
+
                 let l_pot: u16 = adc.read(&mut left_pot).unwrap();
                 let m_pot: u16 = adc.read(&mut mid_pot).unwrap();
                 let r_pot: u16 = adc.read(&mut right_pot).unwrap();
 
-                let l_motor_state = MotorState::from_pot(linearize(l_pot));
-                let r_motor_state = MotorState::from_pot(4096 - linearize(4096 - r_pot));
-                let motor_direction = (m_pot >> 4) as u8;
+                let mut l_motor_state = controls.motor_state(Side::Left, MotorState::from_pot(linearize(l_pot)));
+                let mut r_motor_state = controls.motor_state(Side::Right, MotorState::from_pot(4096 - linearize(4096 - r_pot)));
+
+                // No power until both drivers answered with firmware we
+                // can talk to
+                if !link_l.has(&link::LEFT_DRIVER) || !link_2.has(&link::RIGHT_DRIVER) {
+                    l_motor_state = MotorState::Idle(0);
+                    r_motor_state = MotorState::Idle(0);
+                }
+                let motor_direction = controls.motor_direction((m_pot >> 4) as u8);
 
-                match l_motor_state {
+                if controls.is_running(Side::Left) {
+                    led_1.set_high();
+                } else {
+                    led_1.set_low();
+                }
+
+                if controls.is_running(Side::Right) {
+                    led_8.set_high();
+                } else {
+                    led_8.set_low();
+                }
+
+                if controls.is_held(Side::Left) {
+                    led_3.set_high();
+                } else {
+                    led_3.set_low();
+                }
+
+                if controls.is_held(Side::Right) {
+                    led_6.set_high();
+                } else {
+                    led_6.set_low();
+                }
+
+                // What the drivers report they do, what they are told until
+                // they answer
+                let l_reported = link_l.telemetry(link::LEFT_DRIVER.id).map_or(l_motor_state, |t| t.motor_state);
+                let r_reported = link_2.telemetry(link::RIGHT_DRIVER.id).map_or(r_motor_state, |t| t.motor_state);
+
+                match l_reported {
                     MotorState::Idle(_) => led_2.set_high(),
                     _ => led_2.set_low(),
                 };
 
-                match r_motor_state {
+                match r_reported {
                     MotorState::Idle(_) => led_7.set_high(),
                     _ => led_7.set_low(),
                 };
 
+                // Starts and stops go out ahead of the frames, with their
+                // own sequence number so they are not taken for a copy
+                let commands = [
+                    (link::LEFT_DRIVER.id, controls.take_command(Side::Left)),
+                    (link::RIGHT_DRIVER.id, controls.take_command(Side::Right)),
+                ];
+                for &(dst, command) in commands.iter() {
+                    if let Some(message) = command {
+                        seq = seq.wrapping_add(1);
+                        let packet = Packet { dst, src: MASTER, seq, message };
+                        let left = dst == link::LEFT_DRIVER.id;
+                        match link::TOPOLOGY {
+                            Topology::PerEngine if left => {
+                                link_l.request(packet);
+                            }
+                            Topology::PerEngine => {
+                                link_r.request(packet);
+                            }
+                            Topology::Broadcast if left => {
+                                link_l.request(packet);
+                                link_r.send(packet);
+                            }
+                            Topology::Broadcast => {
+                                link_l.send(packet);
+                                link_r.request(packet);
+                            }
+                            Topology::Bus => {
+                                link_l.request(packet);
+                            }
+                        }
+                    }
+                }
+
+                seq = seq.wrapping_add(1);
+
                 let left_frame = Frame {
-                    id: 1,
+                    id: link::LEFT_DRIVER.id,
+                    seq,
                     motor_state: l_motor_state,
                     motor_direction,
                 };
 
                 let right_frame = Frame {
-                    id: 2,
+                    id: link::RIGHT_DRIVER.id,
+                    seq,
                     motor_state: r_motor_state,
                     motor_direction,
                 };
 
-                let mut buf = [0; 12];
-                left_frame.write(&mut buf);
-                //assert_eq!(Frame::read(&buf), Some(left_frame));
+                // Only the direction matters to the steering node
+                let steering_frame = Frame {
+                    id: link::STEERING_ID,
+                    seq,
+                    motor_state: MotorState::Idle(0),
+                    motor_direction,
+                };
 
                 //hprintln!("{:?}", left_frame);
 
-                left_frame.send(&mut tx_l);
-                right_frame.send(&mut tx_l);
-                //frame.send(&mut tx_r);
+                // A driver only answers on its own port, the copy on the
+                // other port is not answered
+                match link::TOPOLOGY {
+                    Topology::PerEngine => {
+                        link_l.request(left_frame.packet());
+                        link_r.request(right_frame.packet());
+                    }
+                    Topology::Broadcast => {
+                        link_l.request(left_frame.packet());
+                        link_l.send(right_frame.packet());
+                        link_r.send(left_frame.packet());
+                        link_r.request(right_frame.packet());
+                    }
+                    Topology::Bus => {
+                        link_l.request(left_frame.packet());
+                        link_l.request(right_frame.packet());
+                    }
+                }
+                link_l.request(steering_frame.packet());
+
+                since_sync += 1;
+                if since_sync == SYNC_TICKS {
+                    since_sync = 0;
+                    let sync = Packet { dst: BROADCAST, src: MASTER, seq, message: Message::TimeSync(uptime) };
+                    link_l.send(sync);
+                    match link::TOPOLOGY {
+                        Topology::Bus => (),
+                        _ => {
+                            link_r.send(sync);
+                        }
+                    }
+                }
 
                 //hprintln!("l: {}, m: {}, r: {}", l_pot, m_pot, r_pot);
                 //let motor_direction = common;
@@ -236,7 +544,24 @@ fn main() -> ! {
                 }
                 */
             }
-            Err(_) => (),
+            _ => (),
         }
     }
 }
+
+#[exception]
+fn SysTick() {
+    cortex_m::interrupt::free(|cs| TIME.borrow(cs).borrow_mut().tick());
+}
+
+#[interrupt]
+fn USART1() {
+    RX_L.on_interrupt();
+    TX_L.on_interrupt();
+}
+
+#[interrupt]
+fn USART3() {
+    RX_R.on_interrupt();
+    TX_R.on_interrupt();
+}
@@ -0,0 +1,180 @@
+use core::cell::RefCell;
+use core::marker::PhantomData;
+
+use cortex_m::interrupt::{self, Mutex};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::serial::{Read, Write};
+use stm32f1xx_hal::pac::{self, USART1, USART3};
+use stm32f1xx_hal::rcc::Clocks;
+use stm32f1xx_hal::serial::{Rx, Tx};
+use stm32f1xx_hal::time::Hertz;
+
+use common::{Encode, Framing, Queue, TxQueue, MAX_PACKET_LEN};
+
+/// Bytes that can wait for the main loop, over 20 ms of traffic at
+/// 115 200 baud
+const RX_QUEUE_LEN: usize = 256;
+
+pub trait Usart {
+    fn ptr() -> *const pac::usart1::RegisterBlock;
+    /// Clock of the bus the uart sits on
+    fn pclk(clocks: &Clocks) -> Hertz;
+}
+
+impl Usart for USART1 {
+    fn ptr() -> *const pac::usart1::RegisterBlock {
+        USART1::ptr()
+    }
+
+    fn pclk(clocks: &Clocks) -> Hertz {
+        clocks.pclk2()
+    }
+}
+
+impl Usart for USART3 {
+    fn ptr() -> *const pac::usart1::RegisterBlock {
+        USART3::ptr()
+    }
+
+    fn pclk(clocks: &Clocks) -> Hertz {
+        clocks.pclk1()
+    }
+}
+
+/// Serial transmitter that queues packets and sends them from the TXE
+/// interrupt so the main loop never waits for the uart. `F` is the framing
+/// used on the wire. `DE` drives the transceiver of a half duplex bus, it is
+/// high from the first byte queued until the last one is out.
+pub struct SerialTx<USART, F, DE> {
+    tx: Mutex<RefCell<Option<(Tx<USART>, DE)>>>,
+    queue: Mutex<RefCell<TxQueue>>,
+    framing: PhantomData<F>,
+}
+
+impl<USART, F, DE> SerialTx<USART, F, DE> {
+    pub const fn new() -> Self {
+        SerialTx {
+            tx: Mutex::new(RefCell::new(None)),
+            queue: Mutex::new(RefCell::new(TxQueue::new())),
+            framing: PhantomData,
+        }
+    }
+}
+
+impl<USART: Usart, F: Framing, DE: OutputPin> SerialTx<USART, F, DE>
+where
+    Tx<USART>: Write<u8>,
+{
+    pub fn init(&self, tx: Tx<USART>, mut de: DE) {
+        de.set_low();
+        interrupt::free(|cs| {
+            self.tx.borrow(cs).replace(Some((tx, de)));
+        });
+    }
+
+    /// Returns false if there was no room for the packet
+    pub fn send<E: Encode>(&self, msg: &E) -> bool {
+        let mut buf = [0; MAX_PACKET_LEN];
+        let len = F::write(msg, &mut buf);
+        self.send_bytes(&buf[..len])
+    }
+
+    fn send_bytes(&self, data: &[u8]) -> bool {
+        interrupt::free(|cs| {
+            let queued = self.queue.borrow(cs).borrow_mut().push_slice(data).is_ok();
+            if queued {
+                if let Some((_, de)) = self.tx.borrow(cs).borrow_mut().as_mut() {
+                    de.set_high();
+                }
+                // The TXE interrupt fires straight away and starts sending
+                unsafe { (*USART::ptr()).cr1.modify(|_, w| w.txeie().set_bit()) };
+            }
+            queued
+        })
+    }
+
+    /// Waits for everything queued to go out before changing speed
+    pub fn set_baud(&self, baud: u32, clocks: &Clocks) {
+        while !interrupt::free(|cs| self.queue.borrow(cs).borrow().is_empty()) {}
+
+        let usart = unsafe { &*USART::ptr() };
+        while usart.sr.read().tc().bit_is_clear() {}
+        usart.brr.write(|w| unsafe { w.bits(USART::pclk(clocks).0 / baud) });
+    }
+
+    /// Call from the uart interrupt
+    pub fn on_interrupt(&self) {
+        interrupt::free(|cs| {
+            let usart = unsafe { &*USART::ptr() };
+            let mut queue = self.queue.borrow(cs).borrow_mut();
+            if let Some((tx, de)) = self.tx.borrow(cs).borrow_mut().as_mut() {
+                queue.drain(tx);
+                if queue.is_empty() {
+                    // Wait for the last byte to leave the shift register
+                    // before letting go of the bus
+                    if usart.sr.read().tc().bit_is_set() {
+                        de.set_low();
+                        usart.cr1.modify(|_, w| w.txeie().clear_bit().tcie().clear_bit());
+                    }
+                    else {
+                        usart.cr1.modify(|_, w| w.txeie().clear_bit().tcie().set_bit());
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Serial receiver that takes every byte out of the uart in the RXNE
+/// interrupt, so none are lost to an overrun while the main loop is busy
+pub struct SerialRx<USART> {
+    rx: Mutex<RefCell<Option<Rx<USART>>>>,
+    queue: Mutex<RefCell<Queue<u8, RX_QUEUE_LEN>>>,
+}
+
+impl<USART> SerialRx<USART> {
+    pub const fn new() -> Self {
+        SerialRx {
+            rx: Mutex::new(RefCell::new(None)),
+            queue: Mutex::new(RefCell::new(Queue::new())),
+        }
+    }
+
+    /// Every byte received so far, never blocks
+    pub fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        core::iter::from_fn(move || interrupt::free(|cs| self.queue.borrow(cs).borrow_mut().pop()))
+    }
+}
+
+impl<USART> SerialRx<USART>
+where
+    Rx<USART>: Read<u8>,
+{
+    /// The RXNE interrupt has to be enabled on the uart before it is split
+    pub fn init(&self, rx: Rx<USART>) {
+        interrupt::free(|cs| {
+            self.rx.borrow(cs).replace(Some(rx));
+        });
+    }
+
+    /// Call from the uart interrupt
+    pub fn on_interrupt(&self) {
+        interrupt::free(|cs| {
+            let mut queue = self.queue.borrow(cs).borrow_mut();
+            if let Some(rx) = self.rx.borrow(cs).borrow_mut().as_mut() {
+                loop {
+                    match rx.read() {
+                        // A byte that does not fit is lost like an overrun,
+                        // the parser resyncs on the next packet
+                        Ok(byte) => {
+                            queue.push(byte).ok();
+                        }
+                        // Reading cleared the error, the byte is gone
+                        Err(nb::Error::Other(_)) => (),
+                        Err(nb::Error::WouldBlock) => break,
+                    }
+                }
+            }
+        });
+    }
+}
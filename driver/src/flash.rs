@@ -0,0 +1,12 @@
+pub use common::stm32f103::FlashRegs as Regs;
+
+pub type FlashRegion = common::FlashRegion<Regs>;
+
+/// Start of the fault log, the last two pages. memory.x keeps the program out
+/// of them.
+pub const FAULT_LOG: usize = 0x0800_f800;
+pub const FAULT_LOG_PAGES: usize = 2;
+
+/// Start of the usage counters, the two pages below the fault log
+pub const USAGE: usize = 0x0800_f000;
+pub const USAGE_PAGES: usize = 2;
@@ -2,13 +2,11 @@
 #![no_main]
 #![allow(deprecated)]
 
-//extern crate panic_halt;
-extern crate panic_semihosting;
-
 #[macro_use]
 extern crate cortex_m_semihosting;
 
 use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::serial::Write;
 use embedded_hal::timer::{CountDown, Periodic};
 
 use cortex_m_rt::entry;
@@ -18,51 +16,147 @@ use stm32f1xx_hal::adc::Adc;
 use stm32f1xx_hal::gpio::{gpioa, gpiob, gpioc, Analog, Input, Output, PullUp, PushPull};
 use stm32f1xx_hal::pac;
 use stm32f1xx_hal::prelude::*;
+use stm32f1xx_hal::rcc::Clocks;
 use stm32f1xx_hal::serial::{Event, Rx, Serial, Tx};
 use stm32f1xx_hal::time::Hertz;
 use stm32f1xx_hal::timer::{self, Timer};
 
 use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, Ordering};
 use cortex_m::interrupt::Mutex;
 
 use common::*;
 
 mod stepper;
-use stepper::*;
+use stepper::{PendingFaults, StepperController};
 
-mod mpsc;
+use common::stm32f103::watchdog;
 
-#[cfg(feature = "left")]
-mod consts {
-    pub const ID: u8 = 1;
+mod panic;
 
-    pub const GEAR_REV: u16 = 1540;
-    pub const GEAR_FWD: u16 = 2630;
-    pub const GEAR_IDLE: u16 = (GEAR_REV + GEAR_FWD) / 2;
+mod flash;
+use flash::FlashRegion;
 
-    pub const THROTTLE_MIN: u16 = 2720; //1500;
-    pub const THROTTLE_MAX: u16 = 1500; // 2720;
+/// Framing on serial1, `FrameParser` or `CobsParser`. Has to match
+/// `FramingL` on the controller
+type Framing1 = FrameParser;
+/// Framing on serial3, has to match `FramingR` on the controller
+type Framing3 = FrameParser;
+
+/// Serial1 shares its lines with the other nodes, pa8 enables the transceiver
+type Tx1 = HalfDuplex<Tx<pac::USART1>, gpioa::PA8<Output<PushPull>>>;
+
+/// Starter relay on pb0, stop relay on pb1
+type Relays = EngineRelays<gpiob::PB0<Output<PushPull>>, gpiob::PB1<Output<PushPull>>>;
+
+#[cfg(feature = "left")]
+mod consts {
+    use common::{ActuatorConfig, NodeKind, Polarity, StepperConfig};
 
-    pub const STEPPER_LIM_R: i32 = 800*18;
+    pub const ID: u8 = 1;
+    pub const KIND: NodeKind = NodeKind::LeftDriver;
+
+    /// Reported to the controller, bump it with every change to the configs
+    /// below
+    pub const CALIBRATION: u16 = 1;
+
+    /// Reverse gear at `min`, forward at `max`
+    pub const GEAR_CONFIG: ActuatorConfig = ActuatorConfig {
+        min: 1540,
+        max: 2630,
+        invert_sensor: false,
+        invert_motor: true,
+    };
+
+    /// Closed at `max`
+    pub const THROTTLE_CONFIG: ActuatorConfig = ActuatorConfig {
+        min: 1500,
+        max: 2720,
+        invert_sensor: true,
+        invert_motor: false,
+    };
+
+    pub const STEPPER_CONFIG: StepperConfig = StepperConfig {
+        travel: 800*18,
+        enable: Polarity::ActiveLow,
+        invert_dir: false,
+    };
 }
 #[cfg(feature = "right")]
 mod consts {
-    pub const ID: u8 = 2;
-    pub const GEAR_REV: u16 = 1883;
-    pub const GEAR_FWD: u16 = 2712;
-    pub const GEAR_IDLE: u16 = (GEAR_REV + GEAR_FWD) / 2;
-
-    pub const THROTTLE_MIN: u16 = 1900;
-    pub const THROTTLE_MAX: u16 = 2790;
+    use common::{ActuatorConfig, NodeKind, Polarity, StepperConfig};
 
-    pub const STEPPER_LIM_R: i32 = 800*18;
+    pub const ID: u8 = 2;
+    pub const KIND: NodeKind = NodeKind::RightDriver;
+
+    /// Reported to the controller, bump it with every change to the configs
+    /// below
+    pub const CALIBRATION: u16 = 1;
+
+    /// Reverse gear at `min`, forward at `max`
+    pub const GEAR_CONFIG: ActuatorConfig = ActuatorConfig {
+        min: 1883,
+        max: 2712,
+        invert_sensor: false,
+        invert_motor: true,
+    };
+
+    /// Closed at `min`
+    pub const THROTTLE_CONFIG: ActuatorConfig = ActuatorConfig {
+        min: 1900,
+        max: 2790,
+        invert_sensor: false,
+        invert_motor: true,
+    };
+
+    pub const STEPPER_CONFIG: StepperConfig = StepperConfig {
+        travel: 800*18,
+        enable: Polarity::ActiveLow,
+        invert_dir: false,
+    };
 }
 
 use consts::*;
 
+// Gear and throttle targets in per-mille of their travel
+const GEAR_REV: u16 = 0;
+const GEAR_FWD: u16 = FULL_TRAVEL;
+const GEAR_IDLE: u16 = FULL_TRAVEL / 2;
+
+const THROTTLE_MIN: u16 = 0;
+const THROTTLE_MAX: u16 = FULL_TRAVEL;
+
+/// Pulses per revolution the stepper driver is set to
+const STEPPER_PPR: u32 = 8000;
+const STEPPER_RPM: u32 = 1;
+
 
 static STEPPER_CONTROLLER: StepperController = StepperController::new();
 
+// Faults from the interrupts, written to FAULTS from idle
+static PENDING_FAULTS: PendingFaults = PendingFaults::new();
+
+// Parts that have to check in before the watchdog is fed
+const STEPPER_LOOP: u8 = 1 << 0;
+const ACTUATORS: u8 = 1 << 1;
+const SERIAL_LINK: u8 = 1 << 2;
+
+static CHECK_INS: CheckIns = CheckIns::new(STEPPER_LOOP | ACTUATORS | SERIAL_LINK);
+
+/// How long the starter turns for one `EngineStart`
+const CRANK_MS: u16 = 1500;
+/// How long the stop relay is held for one `EngineStop`
+const STOP_MS: u16 = 3000;
+
+/// Usage is saved this often, when it changed
+const USAGE_SAVE_MS: u32 = 10 * 60 * 1000;
+
+/// Set by SysTick when usage is due to be saved, idle saves it
+static SAVE_USAGE: AtomicBool = AtomicBool::new(false);
+
+/// The board resets if a part has not checked in for this long
+const WATCHDOG_MS: u32 = 500;
+
 #[rtfm::app(device = stm32f1xx_hal::pac)]
 const APP: () = {
     static mut GEAR: common::Actuator<
@@ -70,6 +164,7 @@ const APP: () = {
         gpioa::PA2<Output<PushPull>>,
         gpioa::PA0<Analog>,
         gpioa::PA3<Input<PullUp>>,
+        gpiob::PB12<Input<PullUp>>,
     > = ();
 
     static mut THROTTLE: common::Actuator<
@@ -77,29 +172,60 @@ const APP: () = {
         gpioa::PA6<Output<PushPull>>,
         gpioa::PA4<Analog>,
         gpioa::PA7<Input<PullUp>>,
+        gpiob::PB13<Input<PullUp>>,
     > = ();
 
     static mut ADC: Adc<pac::ADC1> = ();
 
     static mut TIMER_HANDLE: Timer<pac::SYST> = ();
 
-    static mut STEPPER: Stepper<'static,
+    static mut STEPPER: Stepper<
         gpiob::PB3<Output<PushPull>>,
         gpiob::PB7<Output<PushPull>>,
         gpiob::PB5<Output<PushPull>>,
-        gpiob::PB6<Input<PullUp>>,
         gpiob::PB4<Input<PullUp>>,
         gpiob::PB9<Input<PullUp>>,
         gpiob::PB8<Input<PullUp>>,
-        Timer<pac::TIM2>,
     > = ();
 
+    static mut STEPPER_TIMER: Timer<pac::TIM2> = ();
+
     static mut MOTOR_STATE: common::MotorState = common::MotorState::Idle(0);
 
+    static mut RELAYS: Relays = ();
+
     static mut CLOCK: Timer<pac::TIM1> = ();
 
     static mut RX: Rx<pac::USART1> = ();
-    static mut TX: Tx<pac::USART1> = ();
+    static mut TX: Tx1 = ();
+    // Replies waiting for the TXE interrupt, and a speed to switch to once
+    // they are out
+    static mut TX_QUEUE: TxQueue = TxQueue::new();
+    static mut NEXT_BAUD: Option<u32> = None;
+
+    // Second link, only listens as the tx line is shared with the other driver
+    static mut RX3: Rx<pac::USART3> = ();
+
+    static mut DEDUP: Dedup = Dedup::new();
+
+    // Falls back to the default speed after a second without frames
+    static mut AUTOBAUD: Autobaud = Autobaud::new(1000);
+    static mut AUTOBAUD3: Autobaud = Autobaud::new(1000);
+
+    static mut CLOCKS: Clocks = ();
+
+    // Uptime and system time, counted by SysTick
+    static mut TIME: Clock = Clock::new();
+
+    static mut RESET: ResetReason = ();
+
+    // Message of a panic before the reset, kept to answer GetPanic
+    static mut LAST_PANIC: Option<PanicText> = ();
+
+    static mut FAULTS: FaultLog<FlashRegion> = ();
+
+    static mut USAGE: UsageStore<FlashRegion> = ();
+    static mut METER: UsageMeter = ();
 
     static mut PC13: gpioc::PC13<Output<PushPull>> = ();
 
@@ -112,6 +238,20 @@ const APP: () = {
 
         // Take ownership over the raw flash and rcc devices and convert them into the corresponding
         // HAL structs
+        let reset = watchdog::reset_reason(&dp.RCC);
+        let last_panic = panic::take();
+
+        // Nothing else runs yet, so these go straight to flash
+        let mut faults = FaultLog::open(unsafe { FlashRegion::new(flash::Regs, flash::FAULT_LOG, flash::FAULT_LOG_PAGES) });
+        if reset == ResetReason::Watchdog {
+            faults.record(FaultRecord { kind: FaultKind::WatchdogReset, time: 0, context: 0 }).ok();
+        }
+        if last_panic.is_some() {
+            faults.record(FaultRecord { kind: FaultKind::Panic, time: 0, context: 0 }).ok();
+        }
+        let usage = UsageStore::open(unsafe { FlashRegion::new(flash::Regs, flash::USAGE, flash::USAGE_PAGES) });
+        let meter = UsageMeter::new(usage.saved());
+
         let mut flash = dp.FLASH.constrain();
         let mut rcc = dp.RCC.constrain();
         let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
@@ -128,10 +268,19 @@ const APP: () = {
         let mut serial = {
             let pin_tx = gpioa.pa9.into_alternate_push_pull(&mut gpioa.crh);
             let pin_rx = gpioa.pa10;
-            Serial::usart1(dp.USART1, (pin_tx, pin_rx), &mut afio.mapr, 9_600.bps(), clocks, &mut rcc.apb2)
+            Serial::usart1(dp.USART1, (pin_tx, pin_rx), &mut afio.mapr, DEFAULT_BAUD.bps(), clocks, &mut rcc.apb2)
         };
         serial.listen(Event::Rxne);
         let (tx, rx) = serial.split();
+        let tx = HalfDuplex::new(tx, gpioa.pa8.into_push_pull_output(&mut gpioa.crh));
+
+        let mut serial3 = {
+            let pin_tx = gpiob.pb10.into_alternate_push_pull(&mut gpiob.crh);
+            let pin_rx = gpiob.pb11;
+            Serial::usart3(dp.USART3, (pin_tx, pin_rx), &mut afio.mapr, DEFAULT_BAUD.bps(), clocks, &mut rcc.apb1)
+        };
+        serial3.listen(Event::Rxne);
+        let (_, rx3) = serial3.split();
 
         /*
         cortex_m::interrupt::free(|cs| {
@@ -139,8 +288,11 @@ const APP: () = {
             SERIAL.borrow(&cs).replace(Some(serial.split()));
         });
         */
-        let mut start_relay = gpiob.pb0.into_push_pull_output(&mut gpiob.crl);
-        let mut stop_relay = gpiob.pb1.into_push_pull_output(&mut gpiob.crl);
+        let relays = {
+            let start_relay = gpiob.pb0.into_push_pull_output(&mut gpiob.crl);
+            let stop_relay = gpiob.pb1.into_push_pull_output(&mut gpiob.crl);
+            EngineRelays::new(start_relay, stop_relay, CRANK_MS, STOP_MS)
+        };
 
         let mut adc = Adc::adc1(dp.ADC1, &mut rcc.apb2);
 
@@ -148,8 +300,10 @@ const APP: () = {
             let pos_pin = gpioa.pa0.into_analog(&mut gpioa.crl);
             let mut in1 = gpioa.pa1.into_push_pull_output(&mut gpioa.crl);
             let mut in2 = gpioa.pa2.into_push_pull_output(&mut gpioa.crl);
-            let lim = gpioa.pa3.into_pull_up_input(&mut gpioa.crl);
-            common::Actuator::new(in1, in2, pos_pin, lim)
+            // The switches close to ground
+            let lim_fwd = Limit::new(gpioa.pa3.into_pull_up_input(&mut gpioa.crl), Polarity::ActiveLow);
+            let lim_rev = Limit::new(gpiob.pb12.into_pull_up_input(&mut gpiob.crh), Polarity::ActiveLow);
+            common::Actuator::new(in1, in2, pos_pin, lim_fwd, lim_rev, GEAR_CONFIG)
         };
         //gear.goto(10000);
         //gear.go_fwd();
@@ -158,47 +312,28 @@ const APP: () = {
             let pos_pin = gpioa.pa4.into_analog(&mut gpioa.crl);
             let in1 = gpioa.pa5.into_push_pull_output(&mut gpioa.crl);
             let in2 = gpioa.pa6.into_push_pull_output(&mut gpioa.crl);
-            let lim = gpioa.pa7.into_pull_up_input(&mut gpioa.crl);
-            common::Actuator::new(in1, in2, pos_pin, lim)
+            let lim_fwd = Limit::new(gpioa.pa7.into_pull_up_input(&mut gpioa.crl), Polarity::ActiveLow);
+            let lim_rev = Limit::new(gpiob.pb13.into_pull_up_input(&mut gpiob.crh), Polarity::ActiveLow);
+            common::Actuator::new(in1, in2, pos_pin, lim_fwd, lim_rev, THROTTLE_CONFIG)
         };
 
         //throttle.goto(10000);
         //throttle.go_fwd();
 
 
-        let mut stepper = {
+        let stepper = {
             let ena = gpiob.pb3.into_push_pull_output(&mut gpiob.crl);
             let dir = gpiob.pb7.into_push_pull_output(&mut gpiob.crl);
             let pul = gpiob.pb5.into_push_pull_output(&mut gpiob.crl);
 
-            let pend = gpiob.pb6.into_pull_up_input(&mut gpiob.crl);
-            let alm = gpiob.pb4.into_pull_up_input(&mut gpiob.crl);
-
-            let lim_r = gpiob.pb8.into_pull_up_input(&mut gpiob.crh);
-            let lim_l = gpiob.pb9.into_pull_up_input(&mut gpiob.crh);
-
-            let timer = Timer::tim2(dp.TIM2, 1000.hz(), clocks, &mut rcc.apb1);
-
-            stepper::Stepper::new(
-                ena,
-                dir,
-                pul,
-                pend,
-                alm,
-                lim_l,
-                lim_r,
-                STEPPER_LIM_R,
-                timer,
-                8000,
-                &STEPPER_CONTROLLER,
-            )
-        };
-        stepper.set_speed(1);
+            // The alarm output and the switches pull to ground
+            let alm = Limit::new(gpiob.pb4.into_pull_up_input(&mut gpiob.crl), Polarity::ActiveLow);
+            let lim_r = Limit::new(gpiob.pb8.into_pull_up_input(&mut gpiob.crh), Polarity::ActiveLow);
+            let lim_l = Limit::new(gpiob.pb9.into_pull_up_input(&mut gpiob.crh), Polarity::ActiveLow);
 
-        loop {
-            stepper.step_left();
-        }
-        
+            Stepper::new(ena, dir, pul, alm, lim_l, lim_r, STEPPER_CONFIG)
+        };
+        let stepper_timer = Timer::tim2(dp.TIM2, tick_rate(STEPPER_PPR, STEPPER_RPM).hz(), clocks, &mut rcc.apb1);
 
         let mut syst = Timer::syst(cp.SYST, 1000.hz(), clocks);
         //let mut syst = Timer::syst(cp.SYST, 1.hz(), clocks);
@@ -206,6 +341,10 @@ const APP: () = {
 
         let mut clock = Timer::tim1(dp.TIM1, 1.hz(), clocks, &mut rcc.apb2);
 
+        // Off while calibrating, the calibration blocks for seconds at a time
+        #[cfg(not(feature = "calibration"))]
+        watchdog::start(&dp.IWDG, WATCHDOG_MS);
+
         pc13.set_high();
 
         init::LateResources {
@@ -214,14 +353,23 @@ const APP: () = {
             ADC: adc,
             TIMER_HANDLE: syst,
             STEPPER: stepper,
+            STEPPER_TIMER: stepper_timer,
+            RELAYS: relays,
             CLOCK: clock,
             RX: rx,
             TX: tx,
+            RX3: rx3,
+            CLOCKS: clocks,
             PC13: pc13,
+            RESET: reset,
+            LAST_PANIC: last_panic,
+            FAULTS: faults,
+            USAGE: usage,
+            METER: meter,
         }
     }
 
-    #[idle(resources = [STEPPER, CLOCK, GEAR, THROTTLE, PC13])]
+    #[idle(resources = [STEPPER, STEPPER_TIMER, CLOCK, GEAR, THROTTLE, PC13, FAULTS, USAGE, METER])]
     fn idle() -> ! {
         #[cfg(feature = "calibration")]
         {
@@ -235,7 +383,7 @@ const APP: () = {
             }
 
             let pos = resources.GEAR.lock(|gear| {
-                gear.position()
+                gear.raw_position()
             });
 
             hprintln!("Gear rev: {}", pos);
@@ -250,7 +398,7 @@ const APP: () = {
             }
 
             let pos = resources.GEAR.lock(|gear| {
-                gear.position()
+                gear.raw_position()
             });
 
             hprintln!("Gear fwd: {}", pos);
@@ -265,7 +413,7 @@ const APP: () = {
             }
 
             let pos = resources.THROTTLE.lock(|throttle| {
-                throttle.position()
+                throttle.raw_position()
             });
 
             hprintln!("Throttle rev: {}", pos);
@@ -280,7 +428,7 @@ const APP: () = {
             }
 
             let pos = resources.THROTTLE.lock(|throttle| {
-                throttle.position()
+                throttle.raw_position()
             });
 
             hprintln!("Throttle fwd: {}", pos);
@@ -300,20 +448,71 @@ const APP: () = {
                 throttle.goto(THROTTLE_MIN);
             });
 
-            resources.STEPPER.zero();
+            STEPPER_CONTROLLER.zero();
 
-            // Run stepper
-            resources.STEPPER.run();
-            loop {}
+            // Run stepper, and write faults and usage to flash between steps
+            let mut faults = resources.FAULTS;
+            let usage = resources.USAGE;
+            let mut meter = resources.METER;
+            STEPPER_CONTROLLER.run(resources.STEPPER, resources.STEPPER_TIMER, &CHECK_INS, STEPPER_LOOP, || {
+                if PENDING_FAULTS.is_pending() {
+                    faults.lock(|faults| PENDING_FAULTS.commit(faults)).ok();
+                }
+                if SAVE_USAGE.swap(false, Ordering::Relaxed) {
+                    usage.save(meter.lock(|meter| meter.usage())).ok();
+                }
+            })
         }
     }
 
-    #[exception(priority = 1, resources = [GEAR, THROTTLE, ADC, MOTOR_STATE])]
+    #[exception(priority = 1, resources = [GEAR, THROTTLE, ADC, MOTOR_STATE, RELAYS, AUTOBAUD, AUTOBAUD3, CLOCKS, TIME, METER])]
     fn SysTick() {
+        // Faults already logged, so each is logged once when it starts
+        static mut stalled: [bool; 2] = [false; 2];
+        static mut alarm: bool = false;
+        static mut quiet: [bool; 2] = [true; 2];
+        static mut since_save: u32 = 0;
+
+        resources.TIME.tick();
+        let now = resources.TIME.now();
+
+        if let Some(baud) = resources.AUTOBAUD.tick() {
+            set_baud(unsafe { &*pac::USART1::ptr() }, resources.CLOCKS.pclk2(), baud);
+        }
+        if let Some(baud) = resources.AUTOBAUD3.tick() {
+            set_baud(unsafe { &*pac::USART3::ptr() }, resources.CLOCKS.pclk1(), baud);
+        }
+        // Serial1 and serial3, the port is the context
+        let links = [(resources.AUTOBAUD.is_quiet(), 1), (resources.AUTOBAUD3.is_quiet(), 3)];
+        for (i, &(is_quiet, port)) in links.iter().enumerate() {
+            if is_quiet && !quiet[i] {
+                log_fault(FaultKind::LinkLost, now, port);
+            }
+            quiet[i] = is_quiet;
+        }
 
         resources.GEAR.tick(resources.ADC);
         resources.THROTTLE.tick(resources.ADC);
+        resources.RELAYS.tick();
+
+        for (i, stall) in [resources.GEAR.stalled(), resources.THROTTLE.stalled()].iter().enumerate() {
+            if *stall && !stalled[i] {
+                log_fault(FaultKind::Stall, now, i as i32);
+            }
+            stalled[i] = *stall;
+        }
+        let stepper = STEPPER_CONTROLLER.status();
+        if stepper.state == StepperState::Alarm && !*alarm {
+            log_fault(FaultKind::StepperAlarm, now, stepper.position);
+        }
+        *alarm = stepper.state == StepperState::Alarm;
 
+        resources.METER.tick(resources.MOTOR_STATE.lock(|x| *x), stepper);
+        *since_save += 1;
+        if *since_save >= USAGE_SAVE_MS {
+            *since_save = 0;
+            SAVE_USAGE.store(true, Ordering::Relaxed);
+        }
 
         #[cfg(not(feature = "calibration"))]
         {
@@ -375,29 +574,263 @@ const APP: () = {
                 }
             }
         }
+
+        // An actuator that is driven without getting anywhere resets the
+        // board, which lets go of it
+        if !resources.GEAR.stalled() && !resources.THROTTLE.stalled() {
+            CHECK_INS.check_in(ACTUATORS);
+        }
+        // A quiet link is fine as long as the engine idles
+        if let common::MotorState::Idle(_) = resources.MOTOR_STATE.lock(|x| *x) {
+            CHECK_INS.check_in(SERIAL_LINK);
+        }
+        if CHECK_INS.all_in() {
+            watchdog::feed();
+        }
     }
     
-    #[interrupt(priority = 1, resources = [TX, RX, MOTOR_STATE])]
+    #[interrupt(priority = 1, resources = [TX, TX_QUEUE, NEXT_BAUD, RX, MOTOR_STATE, RELAYS, DEDUP, AUTOBAUD, CLOCKS, TIME, RESET, LAST_PANIC, FAULTS, METER])]
     fn USART1() {
-        static mut parser: FrameParser = FrameParser::new();
+        static mut parser: Framing1 = Framing1::new();
+        let replies = resources.TX_QUEUE;
+
+        while let Some(packet) = parser.recv_packet(resources.RX) {
+            resources.AUTOBAUD.received();
+            match packet.message {
+                // Every node on the line switches, only the one asked answers
+                Message::BaudPropose(baud) => {
+                    let switch = BAUD_RATES.contains(&baud);
+                    if packet.is_for(ID) && packet.wants_reply() {
+                        // Acknowledge at the old speed, with the speed we
+                        // stay at if it is not supported
+                        let ack = if switch { baud } else { resources.AUTOBAUD.baud() };
+                        reply(replies, &packet.reply(ID, Message::BaudAck(ack)));
+                    }
+                    if switch {
+                        *resources.NEXT_BAUD = Some(baud);
+                    }
+                }
+                _ if !packet.is_for(ID) => (),
+                Message::TimeSync(time) => resources.TIME.sync(time),
+                Message::Ping => {
+                    if packet.wants_reply() {
+                        reply(replies, &packet.reply(ID, Message::Pong));
+                    }
+                }
+                Message::Identify => {
+                    if packet.wants_reply() {
+                        let identity = identity(resources.TIME.uptime(), *resources.RESET);
+                        reply(replies, &packet.reply(ID, Message::Identity(identity)));
+                    }
+                }
+                Message::GetPanic => {
+                    if packet.wants_reply() {
+                        let text = resources.LAST_PANIC.unwrap_or(PanicText::empty());
+                        reply(replies, &packet.reply(ID, Message::Panic(text)));
+                    }
+                }
+                Message::GetFault(index) => {
+                    if packet.wants_reply() {
+                        let record = resources.FAULTS.get(index as usize);
+                        reply(replies, &packet.reply(ID, Message::LoggedFault { index, record }));
+                    }
+                }
+                Message::ClearFaults => {
+                    PENDING_FAULTS.clear();
+                    if packet.wants_reply() {
+                        reply(replies, &packet.reply(ID, Message::ClearFaults));
+                    }
+                }
+                Message::GetUsage => {
+                    if packet.wants_reply() {
+                        let usage = resources.METER.usage();
+                        reply(replies, &packet.reply(ID, Message::Usage(usage)));
+                    }
+                }
+                _ => {
+                    if resources.DEDUP.accept(&packet) {
+                        if packet.message == Message::EStop {
+                            log_fault(FaultKind::EStop, resources.TIME.now(), packet.src as i32);
+                        }
+                        let motor_state = resources.MOTOR_STATE.lock(|x| *x);
+                        if let Some(state) = handle(&packet.message, &mut *resources.RELAYS, &mut *resources.METER, motor_state) {
+                            resources.MOTOR_STATE.lock(|motor_state| {
+                                *motor_state = state;
+                            });
+                        }
+                    }
 
-        while let Some(frame) = parser.recv(resources.RX) {
-            if frame.id != ID {
-                return
+                    if packet.wants_reply() {
+                        // Frames are answered with what the driver does now,
+                        // anything else is echoed so the controller knows
+                        // this link is up
+                        let answer = match packet.message {
+                            Message::Drive { .. } => Message::Telemetry {
+                                motor_state: resources.MOTOR_STATE.lock(|x| *x),
+                                steering: STEPPER_CONTROLLER.status().position,
+                                time: resources.TIME.now(),
+                                reset: *resources.RESET,
+                                dropped: dropped(),
+                            },
+                            message => message,
+                        };
+                        reply(replies, &packet.reply(ID, answer));
+                    }
+                }
             }
+            // Traffic for other nodes does not show this one is listening
+            if packet.is_for(ID) {
+                CHECK_INS.check_in(SERIAL_LINK);
+            }
+        }
 
-            //hprintln!("Frame: {:?}", frame);
-
-            let steering_pos = common::remap(frame.motor_direction as i32, 0, 255, 0, STEPPER_LIM_R);
-            STEPPER_CONTROLLER.goto(steering_pos);
-            resources.MOTOR_STATE.lock(|motor_state| {
-                *motor_state = frame.motor_state;
-            });
-                                       
-
+        let usart = unsafe { &*pac::USART1::ptr() };
+        replies.drain(resources.TX);
+        if replies.is_empty() {
+            // Lets go of the bus once the last byte is out, the TC interrupt
+            // comes back here if it is not yet
+            match resources.TX.flush() {
+                Ok(()) => {
+                    usart.cr1.modify(|_, w| w.txeie().clear_bit().tcie().clear_bit());
+                    if let Some(baud) = resources.NEXT_BAUD.take() {
+                        set_baud(usart, resources.CLOCKS.pclk2(), baud);
+                        resources.AUTOBAUD.switch(baud);
+                    }
+                }
+                Err(_) => usart.cr1.modify(|_, w| w.txeie().clear_bit().tcie().set_bit()),
+            }
         }
+    }
 
-        //static mut parser: impl communication::Parser<Result = communication::Msg> = communication::msg_parser();
+    #[interrupt(priority = 1, resources = [RX3, MOTOR_STATE, RELAYS, DEDUP, AUTOBAUD3, CLOCKS, TIME, METER])]
+    fn USART3() {
+        static mut parser: Framing3 = Framing3::new();
+
+        while let Some(packet) = parser.recv_packet(resources.RX3) {
+            resources.AUTOBAUD3.received();
+            match packet.message {
+                Message::BaudPropose(baud) => {
+                    if BAUD_RATES.contains(&baud) {
+                        set_baud(unsafe { &*pac::USART3::ptr() }, resources.CLOCKS.pclk1(), baud);
+                        resources.AUTOBAUD3.switch(baud);
+                    }
+                }
+                // Nothing is answered here, the tx line belongs to the
+                // other driver
+                _ if !packet.is_for(ID) => (),
+                Message::TimeSync(time) => resources.TIME.sync(time),
+                Message::Ping
+                | Message::Identify
+                | Message::GetPanic
+                | Message::GetFault(_)
+                | Message::ClearFaults
+                | Message::GetUsage => (),
+                _ => {
+                    if resources.DEDUP.accept(&packet) {
+                        if packet.message == Message::EStop {
+                            log_fault(FaultKind::EStop, resources.TIME.now(), packet.src as i32);
+                        }
+                        let motor_state = resources.MOTOR_STATE.lock(|x| *x);
+                        if let Some(state) = handle(&packet.message, &mut *resources.RELAYS, &mut *resources.METER, motor_state) {
+                            resources.MOTOR_STATE.lock(|motor_state| {
+                                *motor_state = state;
+                            });
+                        }
+                    }
+                }
+            }
+            if packet.is_for(ID) {
+                CHECK_INS.check_in(SERIAL_LINK);
+            }
+        }
     }
 };
 
+/// Queues a reply on serial1 and lets the TXE interrupt send it. A reply
+/// that does not fit is dropped, the controller asks again.
+fn reply(queue: &mut TxQueue, packet: &Packet) {
+    let mut buf = [0; MAX_PACKET_LEN];
+    let len = Framing1::write(packet, &mut buf);
+    if queue.push_slice(&buf[..len]).is_ok() {
+        unsafe { (*pac::USART1::ptr()).cr1.modify(|_, w| w.txeie().set_bit()) };
+    }
+}
+
+/// Changes the speed of a uart that is already running
+fn set_baud(usart: &pac::usart1::RegisterBlock, pclk: Hertz, baud: u32) {
+    while usart.sr.read().tc().bit_is_clear() {}
+    usart.brr.write(|w| unsafe { w.bits(pclk.0 / baud) });
+}
+
+/// Who this driver is, `uptime` in milliseconds
+fn identity(uptime: u32, reset: ResetReason) -> Identity {
+    Identity {
+        kind: KIND,
+        id: ID,
+        version: Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+        git_hash: git_hash(env!("GIT_HASH")),
+        calibration: CALIBRATION,
+        uptime: uptime / 1000,
+        reset,
+    }
+}
+
+/// Stepper commands and faults that did not fit their queue, for telemetry
+fn dropped() -> u16 {
+    let dropped = STEPPER_CONTROLLER.dropped().saturating_add(PENDING_FAULTS.dropped());
+    dropped.min(u16::MAX as u32) as u16
+}
+
+/// Queues a fault for idle to write, flash is too slow for an interrupt
+fn log_fault(kind: FaultKind, time: u32, context: i32) {
+    PENDING_FAULTS.push(FaultRecord { kind, time, context });
+}
+
+/// Acts on a message for this driver, returns the new motor state if it
+/// changes
+fn handle(message: &Message, relays: &mut Relays, meter: &mut UsageMeter, motor_state: MotorState) -> Option<MotorState> {
+    match *message {
+        Message::Drive { motor_state, direction } => {
+            steer(direction);
+            Some(motor_state)
+        }
+        Message::EStop => {
+            STEPPER_CONTROLLER.stop();
+            if relays.state() == RelayState::Cranking {
+                relays.open();
+            }
+            Some(MotorState::Idle(0))
+        }
+        Message::EngineStart => {
+            // The starter only turns in neutral, and only what turns it is a
+            // start
+            if let MotorState::Idle(_) = motor_state {
+                if relays.start() {
+                    meter.started();
+                }
+            }
+            None
+        }
+        Message::EngineStop => {
+            relays.stop();
+            Some(MotorState::Idle(0))
+        }
+        Message::SteerGoto(pos) => {
+            STEPPER_CONTROLLER.goto(pos);
+            None
+        }
+        Message::SteerZero => {
+            STEPPER_CONTROLLER.zero();
+            None
+        }
+        // Calibration blocks for seconds, it only runs at boot of a
+        // calibration build
+        Message::Calibrate => None,
+        _ => None,
+    }
+}
+
+fn steer(direction: u8) {
+    let steering_pos = common::remap(direction as i32, 0, 255, 0, STEPPER_CONFIG.travel);
+    STEPPER_CONTROLLER.goto(steering_pos);
+}
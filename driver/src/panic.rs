@@ -0,0 +1,44 @@
+use core::panic::PanicInfo;
+
+use cortex_m::peripheral::SCB;
+use stm32f1xx_hal::pac;
+
+use common::{PanicLog, PanicText, Polarity};
+
+use crate::consts::STEPPER_CONFIG;
+
+/// Lives in the `.panic_log` section from memory.x, which the runtime does
+/// not touch, so the value here is never loaded
+#[link_section = ".panic_log"]
+static mut PANIC_LOG: PanicLog = PanicLog::new();
+
+/// The message of a panic before the last reset, only once after it
+pub fn take() -> Option<PanicText> {
+    cortex_m::interrupt::free(|_| unsafe { PANIC_LOG.take() })
+}
+
+/// Stops every output, keeps the message and resets. Nothing here may
+/// panic again or wait on a peripheral.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    outputs_off();
+    unsafe { PANIC_LOG.record_panic(info) };
+    SCB::sys_reset()
+}
+
+/// Drives the pins straight through the registers, the pin types are owned
+/// by whatever panicked
+fn outputs_off() {
+    let gpioa = unsafe { &*pac::GPIOA::ptr() };
+    let gpiob = unsafe { &*pac::GPIOB::ptr() };
+    // Gear in1/in2 on pa1/pa2 and throttle in1/in2 on pa5/pa6
+    gpioa.brr.write(|w| unsafe { w.bits(1 << 1 | 1 << 2 | 1 << 5 | 1 << 6) });
+    // Start and stop relays on pb0/pb1 open, no step pulse on pb5
+    gpiob.brr.write(|w| unsafe { w.bits(1 << 0 | 1 << 1 | 1 << 5) });
+    // Stepper ena on pb3 to its disabled level
+    match STEPPER_CONFIG.enable {
+        Polarity::ActiveHigh => gpiob.brr.write(|w| unsafe { w.bits(1 << 3) }),
+        Polarity::ActiveLow => gpiob.bsrr.write(|w| unsafe { w.bits(1 << 3) }),
+    }
+}
@@ -21,10 +21,17 @@ use stm32f1xx_hal::{adc::Adc, pac, prelude::*, time::Hertz, timer::Timer};
 
 use core::cell::RefCell;
 
+use common::{tick_rate, Limit, Polarity, Stepper, StepperCommand, StepperConfig};
+
 mod actuator;
 mod adc;
 mod blocking_executor;
-mod steering;
+
+const STEERING: StepperConfig = StepperConfig {
+    travel: 800*18,
+    enable: Polarity::ActiveHigh,
+    invert_dir: false,
+};
 
 #[entry]
 fn main() -> ! {
@@ -56,25 +63,21 @@ fn main() -> ! {
         dp.TIM4
             .pwm(pwm_pins, &mut afio.mapr, 5.khz(), clocks, &mut rcc.apb1);
 
-    let steering_controller = steering::SteeringController::new();
-
     let mut steering = {
         let ena = gpioa.pa0.into_push_pull_output(&mut gpioa.crl);
         let dir = gpioa.pa1.into_push_pull_output(&mut gpioa.crl);
         let pul = gpioa.pa2.into_push_pull_output(&mut gpioa.crl);
 
-        let pend = gpioa.pa3.into_pull_down_input(&mut gpioa.crl);
-        let alm = gpioa.pa4.into_pull_down_input(&mut gpioa.crl);
+        let alm = Limit::new(gpioa.pa4.into_pull_down_input(&mut gpioa.crl), Polarity::ActiveHigh);
 
-        let lim_l = gpioa.pa5.into_pull_down_input(&mut gpioa.crl);
-        let lim_r = gpioa.pa6.into_pull_down_input(&mut gpioa.crl);
+        let lim_l = Limit::new(gpioa.pa5.into_pull_down_input(&mut gpioa.crl), Polarity::ActiveHigh);
+        let lim_r = Limit::new(gpioa.pa6.into_pull_down_input(&mut gpioa.crl), Polarity::ActiveHigh);
 
-        let timer = Timer::tim1(dp.TIM1, 1000.hz(), clocks, &mut rcc.apb2);
-
-        steering_controller.new_steering(ena, dir, pul, pend, alm, lim_l, lim_r, timer, 8000)
+        Stepper::new(ena, dir, pul, alm, lim_l, lim_r, STEERING)
     };
-    steering.set_speed(30);
-    let mut steering = steering.run();
+    let mut steering_timer = Timer::tim1(dp.TIM1, tick_rate(8000, 30).hz(), clocks, &mut rcc.apb2);
+    steering.command(StepperCommand::Zero);
+    steering.command(StepperCommand::Goto(STEERING.travel / 2));
 
 
     //block!(steering.step(8000));
@@ -122,7 +125,9 @@ fn main() -> ! {
         //actuator.go_fwd();
         actuator.tick();
         control.tick();
-        steering.tick();
+        if steering_timer.wait().is_ok() {
+            steering.tick();
+        }
         //block!(actuator.goto(5000));
         /*
         actuator.go_rev();
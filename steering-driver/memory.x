@@ -1,6 +1,18 @@
 /* Linker script for the STM32F103C8T6 */
 MEMORY
 {
-  FLASH : ORIGIN = 0x08000000, LENGTH = 64K
-  RAM : ORIGIN = 0x20000000, LENGTH = 20K
+  /* The top 2K keep the fault log, see src/flash.rs */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 64K - 2K
+  /* The top 64 bytes keep a panic message over a reset, see src/panic.rs */
+  RAM : ORIGIN = 0x20000000, LENGTH = 20K - 64
+  PANIC_LOG : ORIGIN = 0x20000000 + 20K - 64, LENGTH = 64
 }
+
+SECTIONS
+{
+  /* Neither loaded nor zeroed at boot */
+  .panic_log (NOLOAD) : ALIGN(4)
+  {
+    KEEP(*(.panic_log));
+  } > PANIC_LOG
+} INSERT AFTER .bss;
@@ -0,0 +1,8 @@
+pub use common::stm32f103::FlashRegs as Regs;
+
+pub type FlashRegion = common::FlashRegion<Regs>;
+
+/// Start of the fault log, the last two pages. memory.x keeps the program out
+/// of them.
+pub const FAULT_LOG: usize = 0x0800_f800;
+pub const FAULT_LOG_PAGES: usize = 2;
@@ -2,19 +2,15 @@
 #![no_main]
 #![allow(deprecated)]
 
-extern crate panic_halt;
-
-use embedded_hal::digital::{InputPin, OutputPin};
-use embedded_hal::timer::{CountDown, Periodic};
-
-use cortex_m_rt::entry;
+use cortex_m_rt::{entry, exception};
 
 use stm32f1::stm32f103::interrupt;
 use stm32f1xx_hal::prelude::*;
 use stm32f1xx_hal::pac;
 use stm32f1xx_hal::time::Hertz;
-use stm32f1xx_hal::timer::Timer;
+use stm32f1xx_hal::timer::{self, Timer};
 use stm32f1xx_hal::serial::{Event, Serial, Tx, Rx};
+use stm32f1xx_hal::gpio::{gpioa, Output, PushPull};
 use stm32f1xx_hal::pac::USART1;
 
 use cortex_m::interrupt::{Mutex};
@@ -23,13 +19,64 @@ use core::cell::{Cell, RefCell};
 use common::*;
 
 mod stepper;
-use stepper::*;
+use stepper::{PendingFaults, StepperController};
+
+use common::stm32f103::watchdog;
+
+mod panic;
+
+mod flash;
+use flash::FlashRegion;
+
+/// Address of this node, the engine drivers are 1 and 2
+const ID: u8 = 3;
+
+/// Reported to the controller, bump it with every change to `STEPPER_CONFIG`
+const CALIBRATION: u16 = 1;
 
-mod mpsc;
-mod communication;
+/// Framing on serial1, has to match `FramingL` on the controller
+type Framing1 = FrameParser;
+
+/// Serial1 shares its lines with the left driver, pa8 enables the transceiver
+type Tx1 = HalfDuplex<Tx<USART1>, gpioa::PA8<Output<PushPull>>>;
+
+const STEPPER_CONFIG: StepperConfig = StepperConfig {
+    travel: 800*18,
+    enable: Polarity::ActiveHigh,
+    invert_dir: false,
+};
+
+/// Pulses per revolution the stepper driver is set to
+const STEPPER_PPR: u32 = 8000;
+const STEPPER_RPM: u32 = 30;
 
 static STEPPER_CONTROLLER: StepperController = StepperController::new();
-static SERIAL: Mutex<Cell<Option<(Tx<USART1>, Rx<USART1>)>>> = Mutex::new(Cell::new(None));
+
+// Faults from the interrupts, written to FAULTS from the stepper loop
+static PENDING_FAULTS: PendingFaults = PendingFaults::new();
+
+// Parts that have to check in before the watchdog is fed
+const STEPPER_LOOP: u8 = 1 << 0;
+const TICK: u8 = 1 << 1;
+const SERIAL_LINK: u8 = 1 << 2;
+
+static CHECK_INS: CheckIns = CheckIns::new(STEPPER_LOOP | TICK | SERIAL_LINK);
+
+/// The board resets if a part has not checked in for this long
+const WATCHDOG_MS: u32 = 500;
+
+static RESET: Mutex<Cell<ResetReason>> = Mutex::new(Cell::new(ResetReason::Unknown));
+// Message of a panic before the reset, kept to answer GetPanic
+static LAST_PANIC: Mutex<Cell<Option<PanicText>>> = Mutex::new(Cell::new(None));
+static FAULTS: Mutex<RefCell<Option<FaultLog<FlashRegion>>>> = Mutex::new(RefCell::new(None));
+static SERIAL: Mutex<Cell<Option<(Tx1, Rx<USART1>)>>> = Mutex::new(Cell::new(None));
+static PCLK2: Mutex<Cell<Hertz>> = Mutex::new(Cell::new(Hertz(0)));
+
+// Uptime and system time, counted by SysTick
+static TIME: Mutex<RefCell<Clock>> = Mutex::new(RefCell::new(Clock::new()));
+
+// Falls back to the default speed after a second without packets
+static AUTOBAUD: Mutex<RefCell<Autobaud>> = Mutex::new(RefCell::new(Autobaud::new(1000)));
 
 #[entry]
 fn main() -> ! {
@@ -40,6 +87,22 @@ fn main() -> ! {
 
     // Take ownership over the raw flash and rcc devices and convert them into the corresponding
     // HAL structs
+    let reset = watchdog::reset_reason(&dp.RCC);
+    let last_panic = panic::take();
+    // Nothing else runs yet, so these go straight to flash
+    let mut faults = FaultLog::open(unsafe { FlashRegion::new(flash::Regs, flash::FAULT_LOG, flash::FAULT_LOG_PAGES) });
+    if reset == ResetReason::Watchdog {
+        faults.record(FaultRecord { kind: FaultKind::WatchdogReset, time: 0, context: 0 }).ok();
+    }
+    if last_panic.is_some() {
+        faults.record(FaultRecord { kind: FaultKind::Panic, time: 0, context: 0 }).ok();
+    }
+    cortex_m::interrupt::free(|cs| {
+        RESET.borrow(cs).set(reset);
+        LAST_PANIC.borrow(cs).set(last_panic);
+        FAULTS.borrow(cs).replace(Some(faults));
+    });
+
     let mut flash = dp.FLASH.constrain();
     let mut rcc = dp.RCC.constrain();
     let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
@@ -47,45 +110,105 @@ fn main() -> ! {
     let clocks = rcc.cfgr.freeze(&mut flash.acr);
 
     let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
-    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
-    let mut gpioc = dp.GPIOC.split(&mut rcc.apb2);
 
     let mut serial = {
         let pin_tx = gpioa.pa9.into_alternate_push_pull(&mut gpioa.crh);
         let pin_rx = gpioa.pa10;
-        Serial::usart1(dp.USART1, (pin_tx, pin_rx), &mut afio.mapr, 9_600.bps(), clocks, &mut rcc.apb2)
+        Serial::usart1(dp.USART1, (pin_tx, pin_rx), &mut afio.mapr, DEFAULT_BAUD.bps(), clocks, &mut rcc.apb2)
     };
 
-    
+    let de = gpioa.pa8.into_push_pull_output(&mut gpioa.crh);
     cortex_m::interrupt::free(|cs| {
         serial.listen(Event::Rxne);
-        SERIAL.borrow(&cs).replace(Some(serial.split()));
+        let (tx, rx) = serial.split();
+        SERIAL.borrow(&cs).replace(Some((HalfDuplex::new(tx, de), rx)));
+        PCLK2.borrow(&cs).set(clocks.pclk2());
     });
 
+    let mut nvic = cp.NVIC;
+    nvic.enable(pac::Interrupt::USART1);
+
+    // Counts the time, ticks the autobaud fall back and feeds the watchdog
+    let mut syst = Timer::syst(cp.SYST, 1000.hz(), clocks);
+    syst.listen(timer::Event::Update);
+
     let mut stepper = {
         let ena = gpioa.pa0.into_push_pull_output(&mut gpioa.crl);
         let dir = gpioa.pa1.into_push_pull_output(&mut gpioa.crl);
         let pul = gpioa.pa2.into_push_pull_output(&mut gpioa.crl);
 
-        let pend = gpioa.pa3.into_pull_down_input(&mut gpioa.crl);
-        let alm = gpioa.pa4.into_pull_down_input(&mut gpioa.crl);
+        // The alarm output and the switches pull high
+        let alm = Limit::new(gpioa.pa4.into_pull_down_input(&mut gpioa.crl), Polarity::ActiveHigh);
+        let lim_l = Limit::new(gpioa.pa5.into_pull_down_input(&mut gpioa.crl), Polarity::ActiveHigh);
+        let lim_r = Limit::new(gpioa.pa6.into_pull_down_input(&mut gpioa.crl), Polarity::ActiveHigh);
 
-        let lim_l = gpioa.pa5.into_pull_down_input(&mut gpioa.crl);
-        let lim_r = gpioa.pa6.into_pull_down_input(&mut gpioa.crl);
+        Stepper::new(ena, dir, pul, alm, lim_l, lim_r, STEPPER_CONFIG)
+    };
+    let mut timer = Timer::tim1(dp.TIM1, tick_rate(STEPPER_PPR, STEPPER_RPM).hz(), clocks, &mut rcc.apb2);
 
-        let timer = Timer::tim1(dp.TIM1, 1000.hz(), clocks, &mut rcc.apb2);
+    watchdog::start(&dp.IWDG, WATCHDOG_MS);
 
-        stepper::Stepper::new(ena, dir, pul, pend, alm, lim_l, lim_r, timer, 8000, &STEPPER_CONTROLLER)
-    };
-    stepper.set_speed(30);
+    // Home before taking any target
+    STEPPER_CONTROLLER.zero();
+    STEPPER_CONTROLLER.run(&mut stepper, &mut timer, &CHECK_INS, STEPPER_LOOP, commit_faults)
+}
 
-    stepper.run()
+/// Writes the faults the interrupts queued. The log is taken out while it
+/// is written so the interrupts are not held off for a page erase,
+/// `GetFault` finds no records until it is back.
+fn commit_faults() {
+    if !PENDING_FAULTS.is_pending() {
+        return;
+    }
+    let faults = cortex_m::interrupt::free(|cs| FAULTS.borrow(cs).replace(None));
+    if let Some(mut faults) = faults {
+        PENDING_FAULTS.commit(&mut faults).ok();
+        cortex_m::interrupt::free(|cs| FAULTS.borrow(cs).replace(Some(faults)));
+    }
+}
+
+#[exception]
+fn SysTick() {
+    // Faults already logged, so each is logged once when it starts
+    static mut quiet: bool = true;
+    static mut alarm: bool = false;
+
+    let is_quiet = cortex_m::interrupt::free(|cs| {
+        TIME.borrow(cs).borrow_mut().tick();
+
+        let mut autobaud = AUTOBAUD.borrow(cs).borrow_mut();
+        if let Some(baud) = autobaud.tick() {
+            set_baud(baud, PCLK2.borrow(cs).get());
+        }
+        autobaud.is_quiet()
+    });
+    if is_quiet && !*quiet {
+        // Serial1 is the only link
+        log_fault(FaultKind::LinkLost, 1);
+    }
+    *quiet = is_quiet;
+
+    let status = STEPPER_CONTROLLER.status();
+    if status.state == StepperState::Alarm && !*alarm {
+        log_fault(FaultKind::StepperAlarm, status.position);
+    }
+    *alarm = status.state == StepperState::Alarm;
+
+    CHECK_INS.check_in(TICK);
+    // A quiet link is fine as long as the steering is not moving
+    if status.state != StepperState::Ready || status.position == status.target {
+        CHECK_INS.check_in(SERIAL_LINK);
+    }
+    if CHECK_INS.all_in() {
+        watchdog::feed();
+    }
 }
 
 #[interrupt]
 fn USART1() {
-    static mut tx: Option<Tx<USART1>> = None;
+    static mut tx: Option<Tx1> = None;
     static mut rx: Option<Rx<USART1>> = None;
+    static mut parser: Framing1 = Framing1::new();
 
     if tx.is_none() && rx.is_none() {
         cortex_m::interrupt::free(|cs| {
@@ -95,10 +218,135 @@ fn USART1() {
         });
     }
 
-    static mut parser = FrameParser::new();
+    while let Some(packet) = parser.recv_packet(rx.as_mut().unwrap()) {
+        cortex_m::interrupt::free(|cs| AUTOBAUD.borrow(cs).borrow_mut().received());
+        match packet.message {
+            // Every node on the line switches, only the one asked answers
+            Message::BaudPropose(baud) => {
+                let switch = BAUD_RATES.contains(&baud);
+                if packet.is_for(ID) && packet.wants_reply() {
+                    let ack = if switch {
+                        baud
+                    }
+                    else {
+                        cortex_m::interrupt::free(|cs| AUTOBAUD.borrow(cs).borrow().baud())
+                    };
+                    Framing1::send(&packet.reply(ID, Message::BaudAck(ack)), tx.as_mut().unwrap());
+                }
+                if switch {
+                    cortex_m::interrupt::free(|cs| {
+                        set_baud(baud, PCLK2.borrow(cs).get());
+                        AUTOBAUD.borrow(cs).borrow_mut().switch(baud);
+                    });
+                }
+            }
+            _ if !packet.is_for(ID) => (),
+            Message::TimeSync(time) => cortex_m::interrupt::free(|cs| TIME.borrow(cs).borrow_mut().sync(time)),
+            Message::Ping => {
+                if packet.wants_reply() {
+                    Framing1::send(&packet.reply(ID, Message::Pong), tx.as_mut().unwrap());
+                }
+            }
+            Message::Identify => {
+                if packet.wants_reply() {
+                    let reply = packet.reply(ID, Message::Identity(identity()));
+                    Framing1::send(&reply, tx.as_mut().unwrap());
+                }
+            }
+            Message::GetPanic => {
+                if packet.wants_reply() {
+                    let text = cortex_m::interrupt::free(|cs| LAST_PANIC.borrow(cs).get());
+                    let reply = packet.reply(ID, Message::Panic(text.unwrap_or(PanicText::empty())));
+                    Framing1::send(&reply, tx.as_mut().unwrap());
+                }
+            }
+            Message::GetFault(index) => {
+                if packet.wants_reply() {
+                    let record = cortex_m::interrupt::free(|cs| {
+                        FAULTS.borrow(cs).borrow().as_ref().and_then(|faults| faults.get(index as usize))
+                    });
+                    let reply = packet.reply(ID, Message::LoggedFault { index, record });
+                    Framing1::send(&reply, tx.as_mut().unwrap());
+                }
+            }
+            Message::ClearFaults => {
+                PENDING_FAULTS.clear();
+                if packet.wants_reply() {
+                    Framing1::send(&packet.reply(ID, Message::ClearFaults), tx.as_mut().unwrap());
+                }
+            }
+            message => {
+                if message == Message::EStop {
+                    log_fault(FaultKind::EStop, packet.src as i32);
+                }
+                handle(&message);
+
+                // Reply so the controller knows where the steering is
+                if packet.wants_reply() {
+                    let status = STEPPER_CONTROLLER.status();
+                    let (time, reset) =
+                        cortex_m::interrupt::free(|cs| (TIME.borrow(cs).borrow().now(), RESET.borrow(cs).get()));
+                    let reply = packet.reply(ID, Message::SteerStatus {
+                        state: status.state,
+                        position: status.position,
+                        time,
+                        reset,
+                        dropped: dropped(),
+                    });
+                    Framing1::send(&reply, tx.as_mut().unwrap());
+                }
+            }
+        }
+        // Traffic for other nodes does not show this one is listening
+        if packet.is_for(ID) {
+            CHECK_INS.check_in(SERIAL_LINK);
+        }
+    }
+}
+
+fn set_baud(baud: u32, pclk: Hertz) {
+    let usart = unsafe { &*pac::USART1::ptr() };
+    while usart.sr.read().tc().bit_is_clear() {}
+    usart.brr.write(|w| unsafe { w.bits(pclk.0 / baud) });
+}
 
-    if let Some(frame) = parser.recv(rx.as_mut().unwrap()) {
+/// Who this node is
+fn identity() -> Identity {
+    let (uptime, reset) = cortex_m::interrupt::free(|cs| (TIME.borrow(cs).borrow().uptime(), RESET.borrow(cs).get()));
+    Identity {
+        kind: NodeKind::Steering,
+        id: ID,
+        version: Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+        git_hash: git_hash(env!("GIT_HASH")),
+        calibration: CALIBRATION,
+        uptime: uptime / 1000,
+        reset,
     }
+}
+
+/// Stepper commands and faults that did not fit their queue, for telemetry
+fn dropped() -> u16 {
+    let dropped = STEPPER_CONTROLLER.dropped().saturating_add(PENDING_FAULTS.dropped());
+    dropped.min(u16::MAX as u32) as u16
+}
 
-    //static mut parser: impl communication::Parser<Result = communication::Msg> = communication::msg_parser();
+/// Logs a fault at the current system time, the stepper loop writes it to
+/// flash. A fault that cannot be written is dropped, there is nowhere else
+/// to put it.
+fn log_fault(kind: FaultKind, context: i32) {
+    let time = cortex_m::interrupt::free(|cs| TIME.borrow(cs).borrow().now());
+    PENDING_FAULTS.push(FaultRecord { kind, time, context });
+}
+
+/// Acts on a message for this node
+fn handle(message: &Message) {
+    match *message {
+        Message::Drive { direction, .. } => {
+            STEPPER_CONTROLLER.goto(common::remap(direction as i32, 0, 255, 0, STEPPER_CONFIG.travel));
+        }
+        Message::SteerZero => STEPPER_CONTROLLER.zero(),
+        Message::SteerGoto(pos) => STEPPER_CONTROLLER.goto(pos),
+        Message::EStop => STEPPER_CONTROLLER.stop(),
+        _ => (),
+    }
 }
@@ -0,0 +1,41 @@
+use core::panic::PanicInfo;
+
+use cortex_m::peripheral::SCB;
+use stm32f1xx_hal::pac;
+
+use common::{PanicLog, PanicText, Polarity};
+
+use crate::STEPPER_CONFIG;
+
+/// Lives in the `.panic_log` section from memory.x, which the runtime does
+/// not touch, so the value here is never loaded
+#[link_section = ".panic_log"]
+static mut PANIC_LOG: PanicLog = PanicLog::new();
+
+/// The message of a panic before the last reset, only once after it
+pub fn take() -> Option<PanicText> {
+    cortex_m::interrupt::free(|_| unsafe { PANIC_LOG.take() })
+}
+
+/// Stops every output, keeps the message and resets. Nothing here may
+/// panic again or wait on a peripheral.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    outputs_off();
+    unsafe { PANIC_LOG.record_panic(info) };
+    SCB::sys_reset()
+}
+
+/// Drives the pins straight through the registers, the pin types are owned
+/// by whatever panicked
+fn outputs_off() {
+    let gpioa = unsafe { &*pac::GPIOA::ptr() };
+    // No step pulse on pa2
+    gpioa.brr.write(|w| unsafe { w.bits(1 << 2) });
+    // Stepper ena on pa0 to its disabled level
+    match STEPPER_CONFIG.enable {
+        Polarity::ActiveHigh => gpioa.brr.write(|w| unsafe { w.bits(1 << 0) }),
+        Polarity::ActiveLow => gpioa.bsrr.write(|w| unsafe { w.bits(1 << 0) }),
+    }
+}